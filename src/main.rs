@@ -4,8 +4,8 @@ use axum::{
     routing::{get, post},
     Router,
     body::Body,
-    http::header,
-    response::Response,
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Response},
 };
 use chrono::{DateTime, Utc};
 use fuzzy_matcher::skim::SkimMatcherV2;
@@ -17,80 +17,856 @@ use std::path::PathBuf;
 use tokio::sync::RwLock;
 use walkdir::WalkDir;
 use clap::Parser;
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use tokio_util::sync::CancellationToken;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Starting directory path (defaults to current directory)
+    /// Starting directory path (defaults to the last one used, then the current directory)
     #[arg(short, long)]
     path: Option<PathBuf>,
+
+    /// Days an index for a directory no longer in the recent paths is kept before pruning
+    #[arg(long, default_value_t = 30)]
+    index_retention_days: i64,
+
+    /// Serve the frontend from this directory instead of the embedded page
+    #[arg(long)]
+    static_dir: Option<PathBuf>,
+
+    /// Maximum threads for blocking filesystem work such as indexing (defaults to tokio's limit)
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    blocking_threads: Option<usize>,
+
+    /// Index the files inside zip and tar archives
+    #[arg(long)]
+    index_archives: bool,
+
+    /// Add directories to the index alongside files
+    #[arg(long)]
+    index_directories: bool,
+
+    /// Record each file's owner and group (Unix only; resolving names slows indexing)
+    #[arg(long)]
+    index_owners: bool,
+
+    /// Record symlinks, with where they point, instead of skipping them; they are never followed
+    #[arg(long)]
+    index_symlinks: bool,
+
+    /// Count the lines of text files while indexing (reads every file)
+    #[arg(long)]
+    index_line_counts: bool,
+
+    /// Largest file, in bytes, whose lines are counted with --index-line-counts
+    #[arg(long, default_value_t = 16 * 1024 * 1024)]
+    max_line_count_size: u64,
+
+    /// Store a hash of each file while indexing, searchable with `hash=` and
+    /// /by-hash, and have /refresh-metadata compare hashes rather than trust
+    /// modification times (reads every file)
+    #[arg(long)]
+    index_content_hashes: bool,
+
+    /// Hash used by --index-content-hashes
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Md5)]
+    content_hash_algorithm: HashAlgorithm,
+
+    /// Largest file, in bytes, hashed with --index-content-hashes
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    max_content_hash_size: u64,
+
+    /// Seconds between checkpoints of an index run's progress, which the next
+    /// run resumes from if this one is interrupted; 0 disables checkpoints
+    #[arg(long, default_value_t = 30)]
+    index_checkpoint_secs: u64,
+
+    /// Largest archive, in bytes, that will be opened when indexing archives
+    #[arg(long, default_value_t = 100 * 1024 * 1024)]
+    max_archive_size: u64,
+
+    /// How many levels of archives-within-archives to open
+    #[arg(long, default_value_t = 2)]
+    max_archive_depth: usize,
+
+    /// Origin allowed to call the API cross-origin (repeatable)
+    #[arg(long = "cors-origin")]
+    cors_origins: Vec<String>,
+
+    /// Allow any origin to call the API (for local development)
+    #[arg(long)]
+    cors_any: bool,
+
+    /// Write index files as indented JSON (larger, but easier to read when debugging)
+    #[arg(long)]
+    pretty_index: bool,
+
+    /// Entries above which an index is saved as several files, loaded and saved in parallel
+    #[arg(long, default_value_t = 250_000)]
+    index_shard_threshold: usize,
+
+    /// Number of files a sharded index is split into
+    #[arg(long, default_value_t = 16, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..=256))]
+    index_shards: usize,
+
+    /// Times to try writing an index file before giving up
+    #[arg(long, default_value_t = 4, value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    save_attempts: u32,
+
+    /// Milliseconds to wait before the first retry of a failed index write; doubles each retry
+    #[arg(long, default_value_t = 100)]
+    save_retry_delay_ms: u64,
+
+    /// Refuse every request that changes indices, config or the selected directory
+    #[arg(long)]
+    read_only: bool,
+
+    /// Seconds to wait on a single file read before giving up with 504
+    #[arg(long, default_value_t = 30)]
+    read_timeout_secs: u64,
+
+    /// Downloads served at once; further ones queue for a slot
+    #[arg(long, default_value_t = 32, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_concurrent_downloads: usize,
+
+    /// Milliseconds a queued download waits for a slot before getting 503
+    #[arg(long, default_value_t = 5000)]
+    download_queue_ms: u64,
+
+    /// Append every /search query, with its result count and timing, to query_log.jsonl in the data directory
+    #[arg(long)]
+    query_log: bool,
+
+    /// Size in bytes at which the query log is rotated to query_log.jsonl.1
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    query_log_max_bytes: u64,
+
+    /// Largest request body, in bytes, accepted by routes that take one; larger get 413
+    #[arg(long, default_value_t = 4 * 1024 * 1024)]
+    max_body_bytes: usize,
+
+    /// Most subdirectories /list-directories returns per request
+    #[arg(long, default_value_t = 1000)]
+    max_listed_directories: usize,
+
+    /// Load the saved indices of all recent paths in the background at startup, so switching to them is instant
+    #[arg(long)]
+    preload_recent: bool,
+
+    /// Most index entries kept in memory across all loaded roots; beyond it the least recently used indices are unloaded
+    #[arg(long)]
+    max_loaded_entries: Option<usize>,
+
+    /// Shut down after this many minutes without a request; off unless set
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+
+    /// Let `/download` with `inline=true` show HTML, SVG and XML files in the browser instead of downloading them
+    #[arg(long)]
+    allow_inline_active: bool,
+
+    /// Content-Security-Policy for responses that don't set their own; empty to send none
+    #[arg(long, default_value = DEFAULT_CONTENT_SECURITY_POLICY)]
+    content_security_policy: String,
+
+    /// Path prefix to serve everything under, e.g. `/fzf` behind a reverse proxy
+    #[arg(long, default_value = "/", value_parser = parse_base_path)]
+    base_path: String,
 }
 use std::fs;
 use std::io;
 
+/// Normalizes `--base-path` to `/prefix` without a trailing slash, or the
+/// empty string for the root. Limited to characters that need no escaping
+/// when pasted into the page's HTML and JavaScript.
+fn parse_base_path(value: &str) -> Result<String, String> {
+    if !value.starts_with('/') {
+        return Err("must start with /".to_string());
+    }
+    if let Some(c) = value.chars().find(|c| !(c.is_ascii_alphanumeric() || "/-._~".contains(*c))) {
+        return Err(format!("unsupported character {:?}", c));
+    }
+    Ok(value.trim_end_matches('/').to_string())
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct IndexEntry {
     path: String,
     name: String,
     last_modified: DateTime<Utc>,
     size: u64,
+    /// Indices written before directories could be indexed only hold files
+    #[serde(default)]
+    kind: EntryKind,
+    /// Root directory the entry belongs to; only set on results of an
+    /// `all_roots` search, where it must be passed back to `/download`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root: Option<String>,
+    /// Stable identifier for a search result, see `result_id`; not stored in
+    /// the index files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// Full path on disk; only set on search results when `abs=true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    absolute_path: Option<String>,
+    /// Broad kind of file for picking an icon, see `FileType::of`; only set
+    /// on search results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_type: Option<FileType>,
+    /// Only recorded on Unix when indexing with `--index-owners`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<Owner>,
+    /// The real path, percent-encoded byte for byte, when it isn't valid
+    /// UTF-8 and `path` is only a lossy rendering of it; pass it back to
+    /// `/download` as `raw`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_path: Option<String>,
+    /// Number of lines, only recorded for text files when indexing with
+    /// `--index-line-counts`; `null` for binary files
+    #[serde(default)]
+    lines: Option<u64>,
+    /// Hash of the file's contents, only recorded when indexing with
+    /// `--index-content-hashes`: hex MD5, or hex prefixed with `sha256:` or
+    /// `blake3:` for the other algorithms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_hash: Option<String>,
+    /// ASCII romanization of `path`, e.g. `Dong Jing .txt` for `東京.txt`,
+    /// matched alongside it; only recorded for non-ASCII paths in directories
+    /// indexed with `transliterate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    romanized: Option<String>,
+    /// Set with `POST /tags`; copied from `FileTags` whenever the index is
+    /// built or loaded, so it survives reindexing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Whether the entry is a symlink; only recorded when indexing with
+    /// `--index-symlinks`. Its size and kind are those of the target.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    is_symlink: bool,
+    /// Where a symlink points, as written in the link, so often relative
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link_target: Option<String>,
+    /// Filled in when the index is built or loaded, never saved, so changes
+    /// to the folding rules apply without a reindex
+    #[serde(skip)]
+    folded: std::sync::OnceLock<FoldedText>,
+}
+
+/// Diacritic-folded copies of an entry's `path` and `name`, matched against
+/// by `fold_diacritics` searches so they don't re-fold every entry each time.
+#[derive(Debug, Clone)]
+struct FoldedText {
+    path: String,
+    name: String,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Owner {
+    uid: u32,
+    gid: u32,
+    /// User name, when the uid resolves to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    /// Group name, when the gid resolves to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+}
+
+/// Reads file owners during a walk, caching uid/gid name lookups.
+#[derive(Default)]
+struct OwnerLookup {
+    #[cfg(unix)]
+    names: users::UsersCache,
+}
+
+impl OwnerLookup {
+    #[cfg(unix)]
+    fn owner(&self, metadata: &fs::Metadata) -> Option<Owner> {
+        use std::os::unix::fs::MetadataExt;
+        use users::{Groups, Users};
+
+        let (uid, gid) = (metadata.uid(), metadata.gid());
+        Some(Owner {
+            uid,
+            gid,
+            user: self.names.get_user_by_uid(uid).map(|user| user.name().to_string_lossy().to_string()),
+            group: self.names.get_group_by_gid(gid).map(|group| group.name().to_string_lossy().to_string()),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn owner(&self, _metadata: &fs::Metadata) -> Option<Owner> {
+        None
+    }
+}
+
+/// Algorithm behind `IndexEntry::content_hash`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+enum HashAlgorithm {
+    #[default]
+    Md5,
+    Sha256,
+    Blake3,
+}
+
+/// Whether a stored `IndexEntry::content_hash` is `wanted`, given either
+/// with its algorithm prefix or as bare hex.
+fn hash_matches(stored: &str, wanted: &str) -> bool {
+    stored.eq_ignore_ascii_case(wanted)
+        || stored.split_once(':').is_some_and(|(_, hex)| hex.eq_ignore_ascii_case(wanted))
+}
+
+/// The algorithm prefix of a stored `IndexEntry::content_hash`; empty for MD5.
+fn hash_prefix(stored: &str) -> &str {
+    stored.split_once(':').map_or("", |(prefix, _)| prefix)
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum EntryKind {
+    #[default]
+    File,
+    Dir,
+}
+
+impl EntryKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+        }
+    }
+}
+
+/// Category of an entry, derived from its extension, so clients can show an
+/// icon without keeping their own mapping.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum FileType {
+    Directory,
+    Code,
+    Image,
+    Document,
+    Archive,
+    Audio,
+    Video,
+    Other,
+}
+
+/// Lowercase extensions of every `FileType` but `Directory` and `Other`.
+const FILE_TYPE_EXTENSIONS: &[(FileType, &[&str])] = &[
+    (FileType::Code, &[
+        "rs", "py", "js", "mjs", "ts", "tsx", "jsx", "go", "c", "h", "cc", "cpp", "hpp", "cs", "java",
+        "kt", "swift", "rb", "php", "pl", "lua", "sh", "bash", "zsh", "ps1", "sql", "html", "htm",
+        "css", "scss", "json", "toml", "yaml", "yml", "xml", "vue", "svelte", "dart", "scala", "hs",
+        "ex", "exs", "erl", "clj", "r", "m", "zig", "nim",
+    ]),
+    (FileType::Image, &[
+        "png", "jpg", "jpeg", "gif", "bmp", "svg", "webp", "ico", "tif", "tiff", "heic", "avif", "psd", "raw",
+    ]),
+    (FileType::Document, &[
+        "pdf", "doc", "docx", "odt", "rtf", "txt", "md", "markdown", "rst", "tex", "xls", "xlsx", "ods",
+        "csv", "ppt", "pptx", "odp", "epub",
+    ]),
+    (FileType::Archive, &["zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "iso", "dmg"]),
+    (FileType::Audio, &["mp3", "wav", "flac", "ogg", "oga", "m4a", "aac", "opus", "wma", "aiff"]),
+    (FileType::Video, &["mp4", "mkv", "mov", "avi", "webm", "wmv", "flv", "m4v", "mpg", "mpeg"]),
+];
+
+impl FileType {
+    const ALL: [FileType; 8] = [
+        FileType::Directory,
+        FileType::Code,
+        FileType::Image,
+        FileType::Document,
+        FileType::Archive,
+        FileType::Audio,
+        FileType::Video,
+        FileType::Other,
+    ];
+
+    /// Shown before a result's name, by the embedded UI and `/search/html`.
+    fn icon(self) -> &'static str {
+        match self {
+            FileType::Directory => "📁",
+            FileType::Code => "📜",
+            FileType::Image => "🖼️",
+            FileType::Document => "📄",
+            FileType::Archive => "📦",
+            FileType::Audio => "🎵",
+            FileType::Video => "🎬",
+            FileType::Other => "📎",
+        }
+    }
+
+    fn of(entry: &IndexEntry) -> FileType {
+        if entry.kind == EntryKind::Dir {
+            return FileType::Directory;
+        }
+        let Some(extension) = entry.extension() else {
+            return FileType::Other;
+        };
+        FILE_TYPE_EXTENSIONS.iter()
+            .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+            .map_or(FileType::Other, |&(file_type, _)| file_type)
+    }
+}
+
+/// How index files are written.
+struct SaveOptions {
+    pretty: bool,
+    compress: bool,
+    /// Entries above which the index is split across `shards` files
+    shard_threshold: usize,
+    shards: usize,
+    attempts: u32,
+    retry_delay: std::time::Duration,
+}
+
+impl SaveOptions {
+    fn new(args: &Args, config: &Config) -> Self {
+        SaveOptions {
+            pretty: args.pretty_index,
+            compress: !config.plain_index_files,
+            shard_threshold: args.index_shard_threshold,
+            shards: args.index_shards,
+            attempts: args.save_attempts,
+            retry_delay: std::time::Duration::from_millis(args.save_retry_delay_ms),
+        }
+    }
+}
+
+/// Leading bytes of a gzip stream, used to recognize compressed index files
+/// regardless of their name.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Serializes `entries` and writes them to `path`, retrying with exponential
+/// backoff since the write can fail transiently (a virus scanner holding the
+/// file on Windows, EBUSY). Returns the last error once attempts run out.
+fn write_index_file(path: &std::path::Path, entries: &[IndexEntry], options: &SaveOptions) -> io::Result<()> {
+    // Either form loads the same way, so this only affects size on disk
+    let mut contents = if options.pretty {
+        serde_json::to_vec_pretty(entries)?
+    } else {
+        serde_json::to_vec(entries)?
+    };
+    if options.compress {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&contents)?;
+        contents = encoder.finish()?;
+    }
+
+    let mut delay = options.retry_delay;
+    let mut attempt = 1;
+    loop {
+        match fs::write(path, &contents) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < options.attempts => {
+                println!("Saving index failed (attempt {} of {}): {}; retrying in {:?}", attempt, options.attempts, e, delay);
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads one index file, compressed or not.
+fn read_index_file(path: &std::path::Path) -> io::Result<Vec<IndexEntry>> {
+    let contents = fs::read(path)?;
+    if contents.starts_with(&GZIP_MAGIC) {
+        Ok(serde_json::from_reader(flate2::read::GzDecoder::new(contents.as_slice()))?)
+    } else {
+        Ok(serde_json::from_slice(&contents)?)
+    }
+}
+
+/// Runs `task` on every item on its own thread, collecting the results in
+/// order. Index shards are independent files, so they load and save in
+/// parallel.
+fn for_each_shard<T: Sync, R: Send>(items: &[T], task: impl Fn(&T) -> io::Result<R> + Sync) -> io::Result<Vec<R>> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items.iter().map(|item| scope.spawn(|| task(item))).collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(io::Error::other("index shard thread panicked"))))
+            .collect()
+    })
 }
 
 impl IndexEntry {
-    fn save_index(entries: &[IndexEntry], root_path: &PathBuf) -> io::Result<()> {
+    /// The name's extension, lowercased so `Photo.JPG` and `photo.jpg`
+    /// classify and filter alike; `name` itself keeps its original case.
+    fn extension(&self) -> Option<String> {
+        std::path::Path::new(&self.name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+    }
+
+    /// Rough bytes this entry takes in memory, counting its strings' heap
+    /// allocations but not allocator overhead.
+    fn approx_size(&self) -> usize {
+        let owned = |text: &Option<String>| text.as_ref().map_or(0, String::capacity);
+        let owner = self.owner.as_ref().map_or(0, |owner| owned(&owner.user) + owned(&owner.group));
+        let folded = self.folded.get().map_or(0, |folded| folded.path.capacity() + folded.name.capacity());
+        std::mem::size_of::<Self>()
+            + self.path.capacity()
+            + self.name.capacity()
+            + owned(&self.root) + owned(&self.id) + owned(&self.absolute_path) + owned(&self.raw_path)
+            + owned(&self.content_hash) + owned(&self.romanized) + owned(&self.link_target)
+            + self.tags.iter().map(|tag| std::mem::size_of::<String>() + tag.capacity()).sum::<usize>()
+            + owner
+            + folded
+    }
+
+    /// Writes the index for `root_path`: a single file, or once it has more
+    /// than `options.shard_threshold` entries, `options.shards` files written
+    /// in parallel. Files left from the other layout or format are removed so
+    /// a later load can't pick up stale entries, and so is the checkpoint of
+    /// an interrupted run, see `IndexCheckpoint`.
+    fn save_index(entries: &[IndexEntry], root_path: &std::path::Path, options: &SaveOptions) -> io::Result<()> {
         let index_dir = get_index_dir()?;
         fs::create_dir_all(&index_dir)?;
-        
-        // Create a unique filename based on the root path
-        let path_hash = format!("{:x}", md5::compute(root_path.to_string_lossy().as_bytes()));
-        let index_path = index_dir.join(format!("index_{}.json", path_hash));
-        
-        let contents = serde_json::to_string_pretty(entries)?;
-        fs::write(index_path, contents)
+
+        let stem = index_file_stem(&root_path.to_string_lossy());
+        let extension = if options.compress { "json.gz" } else { "json" };
+        let files: Vec<(PathBuf, &[IndexEntry])> = if entries.len() > options.shard_threshold {
+            // Contiguous chunks, so loading the shards in order restores the original order
+            let per_shard = entries.len().div_ceil(options.shards);
+            println!("Saving index as {} shards", entries.len().div_ceil(per_shard));
+            entries.chunks(per_shard)
+                .enumerate()
+                .map(|(shard, chunk)| (index_dir.join(format!("{}.shard{:03}.{}", stem, shard, extension)), chunk))
+                .collect()
+        } else {
+            vec![(index_dir.join(format!("{}.{}", stem, extension)), entries)]
+        };
+        for_each_shard(&files, |(path, chunk)| write_index_file(path, chunk, options))?;
+
+        let prefix = format!("{}.", stem);
+        for file in fs::read_dir(&index_dir)?.filter_map(|e| e.ok()) {
+            let path = file.path();
+            let ours = file.file_name().to_string_lossy().starts_with(&prefix);
+            if ours && !files.iter().any(|(written, _)| *written == path) {
+                if let Err(e) = fs::remove_file(&path) {
+                    println!("Error removing old index file {}: {}", path.display(), e);
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn load_index(root_path: &PathBuf) -> io::Result<Vec<IndexEntry>> {
+    /// The files the index for `root_path` is saved in, in load order:
+    /// the single index file, or its shards. Empty if it was never saved.
+    fn saved_files(root_path: &std::path::Path) -> io::Result<Vec<PathBuf>> {
         let index_dir = get_index_dir()?;
-        let path_hash = format!("{:x}", md5::compute(root_path.to_string_lossy().as_bytes()));
-        let index_path = index_dir.join(format!("index_{}.json", path_hash));
+        let stem = index_file_stem(&root_path.to_string_lossy());
 
-        if index_path.exists() {
-            let contents = fs::read_to_string(index_path)?;
-            Ok(serde_json::from_str(&contents)?)
-        } else {
-            Ok(Vec::new())
+        // Prefer the compressed file; plain ones are written by older versions
+        // or with `plain_index_files` set
+        let single = [format!("{}.json.gz", stem), format!("{}.json", stem)]
+            .into_iter()
+            .map(|name| index_dir.join(name))
+            .find(|p| p.exists());
+        if let Some(path) = single {
+            return Ok(vec![path]);
+        }
+
+        let prefix = format!("{}.shard", stem);
+        let mut shards: Vec<PathBuf> = match fs::read_dir(&index_dir) {
+            Ok(dir) => dir
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+                .map(|e| e.path())
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        shards.sort();
+        Ok(shards)
+    }
+
+    fn load_index(root_path: &std::path::Path) -> io::Result<Vec<IndexEntry>> {
+        let files = IndexEntry::saved_files(root_path)?;
+        let entries: Vec<IndexEntry> = match files.as_slice() {
+            [] => Vec::new(),
+            [path] => read_index_file(path)?,
+            shards => {
+                println!("Loading index from {} shards", shards.len());
+                for_each_shard(shards, |path| read_index_file(path))?.into_iter().flatten().collect()
+            }
+        };
+        entries.iter().for_each(|entry| { entry.folded(); });
+        Ok(entries)
+    }
+
+    /// The folded match text, computed on first use if the index didn't
+    /// already prepare it.
+    fn folded(&self) -> &FoldedText {
+        self.folded.get_or_init(|| FoldedText {
+            path: fold_diacritics(&self.path),
+            name: fold_diacritics(&self.name),
+        })
+    }
+}
+
+/// Progress of an interrupted index run, saved every `--index-checkpoint-secs`
+/// as `<stem>.partial.json` next to the index. The next run keeps the entries
+/// of the directories listed here and doesn't walk them again.
+#[derive(Serialize, Deserialize, Default)]
+struct IndexCheckpoint {
+    /// Directories, relative to the root, that were walked to the end; only
+    /// the outermost, as those nested in them are covered too
+    completed_dirs: std::collections::BTreeSet<String>,
+    entries: Vec<IndexEntry>,
+}
+
+impl IndexCheckpoint {
+    fn path(root_path: &std::path::Path) -> io::Result<PathBuf> {
+        let stem = index_file_stem(&root_path.to_string_lossy());
+        Ok(get_index_dir()?.join(format!("{}.partial.json", stem)))
+    }
+
+    /// The checkpoint left for `root_path`, if any, keeping only the entries
+    /// of completed directories; the rest are walked again.
+    fn load(root_path: &std::path::Path) -> Option<Self> {
+        let contents = fs::read(IndexCheckpoint::path(root_path).ok()?).ok()?;
+        let mut checkpoint: IndexCheckpoint = match serde_json::from_slice(&contents) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                println!("Ignoring unreadable index checkpoint: {}", e);
+                return None;
+            }
+        };
+        let entries = std::mem::take(&mut checkpoint.entries);
+        checkpoint.entries = entries.into_iter()
+            .filter(|entry| checkpoint.covers(&entry.path))
+            .collect();
+        Some(checkpoint)
+    }
+
+    /// Replaces the saved checkpoint in one step, so a crash mid-write
+    /// leaves the previous one intact.
+    fn save(&self, root_path: &std::path::Path) -> io::Result<()> {
+        let path = IndexCheckpoint::path(root_path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        fs::rename(temp_path, path)
+    }
+
+    /// Whether `path` is a completed directory or lies inside one.
+    fn covers(&self, mut path: &str) -> bool {
+        loop {
+            if self.completed_dirs.contains(path) {
+                return true;
+            }
+            match path.rsplit_once('/') {
+                Some((parent, _)) => path = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn complete(&mut self, dir: String) {
+        let prefix = format!("{}/", dir);
+        let nested: Vec<String> = self.completed_dirs.range(prefix.clone()..)
+            .take_while(|nested| nested.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for nested in nested {
+            self.completed_dirs.remove(&nested);
         }
+        self.completed_dirs.insert(dir);
+    }
+}
+
+/// Renders a relative path with `/` separators on every platform, so results
+/// look the same to the frontend and round-trip through download URLs.
+fn slash_path(path: &std::path::Path) -> String {
+    #[cfg(windows)]
+    {
+        path.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    // Backslash is a legal filename character elsewhere, so leave it alone
+    #[cfg(not(windows))]
+    {
+        path.to_string_lossy().to_string()
+    }
+}
+
+/// Bytes escaped in `IndexEntry::raw_path`; separators and common filename
+/// punctuation stay readable.
+const RAW_PATH_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_');
+
+/// Lossless form of a relative path `slash_path` can't render faithfully,
+/// see `IndexEntry::raw_path`. Only Unix paths are arbitrary bytes, so
+/// elsewhere there's nothing to preserve.
+fn raw_path(path: &std::path::Path) -> Option<String> {
+    if path.to_str().is_some() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Some(percent_encoding::percent_encode(path.as_os_str().as_bytes(), RAW_PATH_ENCODE_SET).to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+/// Reverses `raw_path`.
+fn decode_raw_path(raw: &str) -> Option<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        let bytes: Vec<u8> = percent_encoding::percent_decode_str(raw).collect();
+        Some(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = raw;
+        None
     }
 }
 
+/// Create a unique file name stem based on the root path. The index is saved
+/// as `<stem>.json`, or `.json.gz` when compressed, or split into
+/// `<stem>.shardNNN.json` files; see `IndexEntry::save_index`.
+fn index_file_stem(root_path: &str) -> String {
+    let path_hash = format!("{:x}", md5::compute(root_path.as_bytes()));
+    format!("index_{}", path_hash)
+}
+
+/// Identifier for the file at `path` under `root`, the same on every request
+/// so clients can track a result while the list around it reorders.
+fn result_id(root: &str, path: &str) -> String {
+    let absolute = std::path::Path::new(root).join(path);
+    format!("{:x}", md5::compute(absolute.to_string_lossy().as_bytes()))
+}
+
 fn get_index_dir() -> io::Result<PathBuf> {
     let proj_dirs = directories::ProjectDirs::from("", "", "rsconfig")
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not determine index directory"))?;
-    let index_dir = proj_dirs.cache_dir().join("indices");
-    println!("Index directory: {}", index_dir.display());
-    Ok(index_dir)
+        .ok_or_else(|| io::Error::other("Could not determine index directory"))?;
+    Ok(proj_dirs.cache_dir().join("indices"))
 }
 
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct PathConfig {
     path: String,
     last_indexed: Option<DateTime<Utc>>,
     total_files: usize,
+    /// Files larger than this many bytes are left out of the index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_file_size: Option<u64>,
+    /// Files smaller than this many bytes are left out of the index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_file_size: Option<u64>,
+    /// Rebuild the index this many minutes after the last run while the path is active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reindex_interval_minutes: Option<u64>,
+    /// Index a romanized form of non-ASCII paths, see `IndexEntry::romanized`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    transliterate: bool,
+    /// Don't descend into directories on another filesystem than the root,
+    /// like `find -xdev`; Unix only
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    one_file_system: bool,
+}
+
+impl PathConfig {
+    /// When the background scheduler will next rebuild this path's index, if
+    /// a reindex interval is set. Never-indexed paths are due immediately.
+    fn next_reindex(&self) -> Option<DateTime<Utc>> {
+        let minutes = i64::try_from(self.reindex_interval_minutes?).ok()?;
+        let Some(last_indexed) = self.last_indexed else {
+            return Some(Utc::now());
+        };
+        last_indexed.checked_add_signed(chrono::Duration::try_minutes(minutes)?)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 struct Config {
     recent_paths: Vec<PathConfig>,
+    /// Pinned directories, never evicted like recent paths
+    #[serde(default)]
+    bookmarks: Vec<PathConfig>,
+    /// Deep link for opening a result in an editor, e.g. `vscode://file/{path}`
+    /// or `idea://open?file={path_encoded}`; unset disables editor links
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    editor_link_template: Option<String>,
+    /// Search settings applied when a request leaves them out
+    #[serde(default)]
+    default_search: SearchDefaults,
+    /// Weights of the ranking formula, see `SearchQuery::score`
+    #[serde(default)]
+    scoring: ScoringWeights,
+    /// Directory selected when the server last ran, restored on startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_root: Option<String>,
+    /// Page title and heading of the embedded UI; "Fuzzy File Search" if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    app_title: Option<String>,
+    /// Plain-text note shown under the heading of the embedded UI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    banner: Option<String>,
+    /// Save indices as plain `.json` instead of gzip-compressed `.json.gz`,
+    /// for inspecting them by hand
+    #[serde(default)]
+    plain_index_files: bool,
 }
 
 use std::collections::HashMap;
 
 #[derive(Clone)]
 struct AppState {
+    #[allow(dead_code)]
     working_dir: Arc<PathBuf>,
     user_selected_dir: Arc<RwLock<PathBuf>>,
     indices: Arc<RwLock<HashMap<String, Vec<IndexEntry>>>>,
+    /// When each loaded index was last selected or searched, see `--max-loaded-entries`
+    index_used: Arc<RwLock<HashMap<String, DateTime<Utc>>>>,
     config: Arc<RwLock<Config>>,
+    access_log: Arc<RwLock<AccessLog>>,
+    tags: Arc<RwLock<FileTags>>,
+    metrics: PrometheusHandle,
+    search_cache: Arc<RwLock<SearchCache>>,
+    /// Cancels the indexing run currently in progress, if any
+    index_cancel: Arc<RwLock<Option<Arc<CancellationToken>>>>,
+    highlighter: Arc<Highlighter>,
+    /// One permit per download in flight, see `--max-concurrent-downloads`
+    download_permits: Arc<tokio::sync::Semaphore>,
+    /// Set with `--query-log`
+    query_log: Option<Arc<QueryLog>>,
+    /// Files that showed up in index updates, oldest first, see `/new-files`
+    new_files: Arc<RwLock<std::collections::VecDeque<NewFile>>>,
+    /// Bumped whenever `new_files` grows, waking `/new-files` long-polls
+    new_files_signal: Arc<tokio::sync::watch::Sender<u64>>,
+    /// Unix milliseconds when the last request started or finished, see `--idle-timeout`
+    last_request: Arc<std::sync::atomic::AtomicI64>,
+    args: Arc<Args>,
 }
 
 impl Config {
@@ -98,9 +874,9 @@ impl Config {
         let config_path = get_config_path()?;
         if config_path.exists() {
             let contents = fs::read_to_string(config_path)?;
-            Ok(serde_json::from_str(&contents).unwrap_or(Config { recent_paths: vec![] }))
+            Ok(serde_json::from_str(&contents).unwrap_or_default())
         } else {
-            Ok(Config { recent_paths: vec![] })
+            Ok(Config::default())
         }
     }
 
@@ -114,8 +890,11 @@ impl Config {
     }
 
     fn add_path(&mut self, path: String, total_files: usize) {
+        let path = normalize_root(std::path::Path::new(&path)).to_string_lossy().to_string();
+        // Entries saved before paths were normalized may be spelled differently
         if let Some(existing) = self.recent_paths.iter_mut()
-            .find(|p| p.path == path) {
+            .find(|p| p.path == path || normalize_root(std::path::Path::new(&p.path)).to_string_lossy() == path) {
+            existing.path = path;
             existing.last_indexed = Some(Utc::now());
             existing.total_files = total_files;
         } else {
@@ -123,6 +902,11 @@ impl Config {
                 path,
                 last_indexed: Some(Utc::now()),
                 total_files,
+                max_file_size: None,
+                min_file_size: None,
+                reindex_interval_minutes: None,
+                transliterate: false,
+                one_file_system: false,
             });
             if self.recent_paths.len() > 5 {
                 self.recent_paths.pop();
@@ -130,725 +914,7060 @@ impl Config {
         }
     }
 
-    fn get_paths(&self) -> Vec<String> {
-        self.recent_paths.iter().map(|p| p.path.clone()).collect()
+    fn path_config(&self, path: &str) -> Option<&PathConfig> {
+        self.recent_paths.iter()
+            .chain(self.bookmarks.iter())
+            .find(|p| p.path == path)
+    }
+
+    /// Every saved entry for `path`, recent and bookmarked.
+    fn path_configs_mut<'a>(&'a mut self, path: &'a str) -> impl Iterator<Item = &'a mut PathConfig> {
+        self.recent_paths.iter_mut()
+            .chain(self.bookmarks.iter_mut())
+            .filter(move |p| p.path == path)
+    }
+
+    /// Records a finished index run on every saved entry for `path`, without
+    /// promoting it in the recent list.
+    fn mark_indexed(&mut self, path: &str, total_files: usize) {
+        for path_config in self.path_configs_mut(path) {
+            path_config.last_indexed = Some(Utc::now());
+            path_config.total_files = total_files;
+        }
+    }
+
+    /// Pins `path`; returns false if it was already bookmarked.
+    fn add_bookmark(&mut self, path: String) -> bool {
+        let path = normalize_root(std::path::Path::new(&path)).to_string_lossy().to_string();
+        if self.bookmarks.iter().any(|p| p.path == path) {
+            return false;
+        }
+        let (last_indexed, total_files) = self.recent_paths.iter()
+            .find(|p| p.path == path)
+            .map(|p| (p.last_indexed, p.total_files))
+            .unwrap_or((None, 0));
+        self.bookmarks.push(PathConfig {
+            path,
+            last_indexed,
+            total_files,
+            max_file_size: None,
+            min_file_size: None,
+            reindex_interval_minutes: None,
+            transliterate: false,
+            one_file_system: false,
+        });
+        true
+    }
+
+    /// Unpins `path`; returns false if it wasn't bookmarked.
+    fn remove_bookmark(&mut self, path: &str) -> bool {
+        let normalized = normalize_root(std::path::Path::new(path)).to_string_lossy().to_string();
+        let before = self.bookmarks.len();
+        self.bookmarks.retain(|p| p.path != path && p.path != normalized);
+        self.bookmarks.len() != before
+    }
+}
+
+/// The spelling a directory is selected and saved under, so that a trailing
+/// slash, `..` or a symlink don't make it a different directory: the
+/// canonical path, without Windows' `\\?\` prefix on drive paths. Paths
+/// that can't be resolved only lose trailing separators.
+fn normalize_root(path: &std::path::Path) -> PathBuf {
+    let Ok(canonical) = path.canonicalize() else {
+        let text = path.to_string_lossy();
+        let trimmed = text.trim_end_matches(['/', '\\']);
+        return match trimmed.is_empty() || trimmed.len() == text.len() {
+            true => path.to_path_buf(),
+            false => PathBuf::from(trimmed),
+        };
+    };
+    let text = canonical.to_string_lossy();
+    match text.strip_prefix(r"\\?\").filter(|rest| rest.as_bytes().get(1) == Some(&b':')) {
+        Some(rest) => PathBuf::from(rest),
+        None => canonical,
     }
 }
 
 fn get_config_path() -> io::Result<PathBuf> {
     let proj_dirs = directories::ProjectDirs::from("", "", "rsconfig")
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not determine config directory"))?;
+        .ok_or_else(|| io::Error::other("Could not determine config directory"))?;
     Ok(proj_dirs.config_dir().join("config.json"))
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct AccessRecord {
+    count: u64,
+    last_accessed: DateTime<Utc>,
+}
+
+/// Per-file download/open counts, keyed by absolute path.
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct AccessLog {
+    files: HashMap<String, AccessRecord>,
+}
+
+/// Days after which an access counts for half as much in the frecency score.
+const FRECENCY_HALF_LIFE_DAYS: f64 = 7.0;
+
+impl AccessRecord {
+    /// Access count decayed by how long ago the file was last touched.
+    fn frecency(&self, now: DateTime<Utc>) -> f64 {
+        let age_days = (now - self.last_accessed).num_seconds().max(0) as f64 / 86_400.0;
+        self.count as f64 * 0.5_f64.powf(age_days / FRECENCY_HALF_LIFE_DAYS)
+    }
+}
+
+impl AccessLog {
+    fn load() -> io::Result<Self> {
+        let log_path = get_index_dir()?.join("access_counts.json");
+        if log_path.exists() {
+            let contents = fs::read_to_string(log_path)?;
+            Ok(serde_json::from_str(&contents).unwrap_or_default())
+        } else {
+            Ok(AccessLog::default())
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let index_dir = get_index_dir()?;
+        fs::create_dir_all(&index_dir)?;
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(index_dir.join("access_counts.json"), contents)
+    }
+
+    fn record(&mut self, full_path: &std::path::Path) {
+        let record = self.files
+            .entry(full_path.to_string_lossy().to_string())
+            .or_insert(AccessRecord { count: 0, last_accessed: Utc::now() });
+        record.count += 1;
+        record.last_accessed = Utc::now();
+    }
+}
+
+/// Tags attached to files with `POST /tags`, keyed by absolute path like the
+/// access log. Kept apart from the index files, which every reindex rewrites.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+struct FileTags {
+    files: HashMap<String, Vec<String>>,
+}
+
+impl FileTags {
+    fn load() -> io::Result<Self> {
+        let tags_path = get_index_dir()?.join("tags.json");
+        if tags_path.exists() {
+            let contents = fs::read_to_string(tags_path)?;
+            Ok(serde_json::from_str(&contents).unwrap_or_default())
+        } else {
+            Ok(FileTags::default())
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let index_dir = get_index_dir()?;
+        fs::create_dir_all(&index_dir)?;
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(index_dir.join("tags.json"), contents)
+    }
+
+    /// Tags of the entry at `path` under `root`.
+    fn get(&self, root: &str, path: &str) -> Vec<String> {
+        if self.files.is_empty() {
+            return Vec::new();
+        }
+        self.files.get(std::path::Path::new(root).join(path).to_string_lossy().as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Copies the saved tags onto `index`, the index of `root`.
+    fn apply(&self, root: &str, index: &mut [IndexEntry]) {
+        for entry in index {
+            entry.tags = self.get(root, &entry.path);
+        }
+    }
+
+    /// Copies the tags of files under `old_root` to the same paths under
+    /// `new_root`, dropping the originals unless `keep_old`. Returns whether
+    /// any tags were affected.
+    fn rekey_root(&mut self, old_root: &str, new_root: &str, keep_old: bool) -> bool {
+        let old_root = std::path::Path::new(old_root);
+        let rekeyed: Vec<(String, Vec<String>)> = self.files.iter()
+            .filter_map(|(full_path, tags)| {
+                let relative = std::path::Path::new(full_path).strip_prefix(old_root).ok()?;
+                Some((std::path::Path::new(new_root).join(relative).to_string_lossy().to_string(), tags.clone()))
+            })
+            .collect();
+        if !keep_old {
+            self.files.retain(|full_path, _| !std::path::Path::new(full_path).starts_with(old_root));
+        }
+        let changed = !rekeyed.is_empty();
+        self.files.extend(rekeyed);
+        changed
+    }
+
+    /// Replaces the tags of the file at `full_path`; no tags forgets it.
+    fn set(&mut self, full_path: &std::path::Path, tags: Vec<String>) {
+        let key = full_path.to_string_lossy().to_string();
+        match tags.is_empty() {
+            true => self.files.remove(&key),
+            false => self.files.insert(key, tags),
+        };
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Debug, Deserialize)]
 struct SearchQuery {
+    #[serde(alias = "query")]
     q: String,
+    /// Ignore accents so `cafe` matches `café`
+    #[serde(default)]
+    fold_diacritics: bool,
+    /// Match the query as a plain substring instead of a fuzzy subsequence;
+    /// overrides `mode`
+    #[serde(default)]
+    strict_substring: bool,
+    /// Match against the whole path or just the file name
+    #[serde(default)]
+    field: Option<SearchField>,
+    /// How letter case is compared
+    #[serde(default)]
+    case: Option<CaseMode>,
+    /// Fuzzy or substring matching
+    #[serde(default)]
+    mode: Option<MatchMode>,
+    /// Algorithm for fuzzy matching
+    #[serde(default)]
+    matcher: Option<MatcherKind>,
+    /// Only return files or only directories; both when unset
+    #[serde(default)]
+    kind: Option<EntryKind>,
+    /// Include each result's absolute path
+    #[serde(default)]
+    abs: bool,
+    /// Multiplier on the matcher's score; defaults to `scoring.fuzzy`
+    #[serde(default)]
+    fuzzy_weight: Option<f64>,
+    /// Added when the query also matches the file name on its own; defaults
+    /// to `scoring.name_match`
+    #[serde(default)]
+    name_match_bonus: Option<i64>,
+    /// Maximum score bonus for recently modified files; zero disables the
+    /// boost. Defaults to `scoring.recency`
+    #[serde(default)]
+    recency_weight: Option<f64>,
+    /// Maximum score bonus for often and recently downloaded files, see
+    /// `frecency_bonus`; zero disables the boost. Defaults to `scoring.frecency`
+    #[serde(default)]
+    frecency_weight: Option<f64>,
+    /// Shape of the recency bonus as files age
+    #[serde(default)]
+    recency_decay: RecencyDecay,
+    /// Half-life (exponential) or cut-off (linear) of the recency bonus, in hours
+    #[serde(default = "default_recency_window_hours")]
+    recency_window_hours: f64,
+    /// Score subtracted per directory a result is nested in, so shallow
+    /// files win between similar matches; zero leaves ranking unchanged.
+    /// Defaults to `scoring.depth_penalty`
+    #[serde(default)]
+    depth_penalty: Option<i64>,
+    /// Only entries modified within this long before now, e.g. `24h` or
+    /// `7d`; the same as a `modified:` filter in `q`
+    #[serde(default, deserialize_with = "deserialize_age")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>), param(value_type = Option<String>))]
+    age: Option<chrono::Duration>,
+    /// Only entries with this tag, like a `tag:` filter in `q`
+    #[serde(default)]
+    tag: Option<String>,
+    /// Also match the query against each entry's tags, scoring an entry by
+    /// its best match
+    #[serde(default)]
+    match_tags: bool,
+    /// Only files with this content hash, with or without its algorithm
+    /// prefix; needs an index built with `--index-content-hashes`
+    #[serde(default)]
+    hash: Option<String>,
+    /// `true` for only symlinks, `false` to leave them out; needs an index
+    /// built with `--index-symlinks`
+    #[serde(default)]
+    symlink: Option<bool>,
+    /// Only files with at least this many lines; needs an index built with
+    /// `--index-line-counts`, and excludes files without a count
+    #[serde(default)]
+    min_lines: Option<u64>,
+    /// Only files with at most this many lines; excludes files without a count
+    #[serde(default)]
+    max_lines: Option<u64>,
+    /// Collapse results that share a file name into the best-scoring one
+    #[serde(default)]
+    group_by_name: bool,
+    /// Nest results in `groups` instead of listing them in `files`
+    #[serde(default)]
+    group: Option<ResultGrouping>,
+    /// `tree` returns results as a directory tree in `tree` instead of
+    /// `files`, ignoring `group`, `group_by_name` and `collapse_case`
+    #[serde(default)]
+    format: ResultFormat,
+    /// When nothing matches, suggest paths whose names are a few typos away
+    #[serde(default = "default_suggest")]
+    suggest: bool,
+    /// Leave out files that have been downloaded before
+    #[serde(default)]
+    exclude_downloaded: bool,
+    /// How to order results that share a score
+    #[serde(default, alias = "sort")]
+    tie_breaker: Option<TieBreaker>,
+    /// Search every loaded index rather than just the selected directory's
+    #[serde(default)]
+    all_roots: bool,
+    /// Merge results whose absolute paths differ only in letter case
+    #[serde(default)]
+    collapse_case: bool,
+    /// Treat everything up to the last `/` of the query as a literal path
+    /// prefix and only match the rest, see `extract_operators`
+    #[serde(default)]
+    anchor_prefix: Option<bool>,
+    /// Comma-separated `IndexEntry` fields to keep in each result, e.g.
+    /// `path,size`; all of them when unset. See `project_result`
+    #[serde(default)]
+    fields: Option<String>,
+    /// Return at most this many of `files`, with a `next_cursor` for the rest
+    #[serde(default)]
+    limit: Option<usize>,
+    /// A previous page's `next_cursor`, to continue where it left off
+    #[serde(default)]
+    cursor: Option<String>,
+    /// Filters pulled out of `q` by `extract_operators`
+    #[serde(skip)]
+    filters: QueryFilters,
+    /// Directory prefix pulled out of `q` by `extract_operators` when
+    /// `anchor_prefix` is on
+    #[serde(skip)]
+    path_prefix: Option<String>,
 }
 
-#[derive(Serialize)]
-struct SearchResult {
-    files: Vec<IndexEntry>,
+/// Filters written inline in the query box.
+///
+/// Grammar, with tokens separated by whitespace:
+/// - `ext:rs` or `ext:rs,md` — file extension, case-insensitive
+/// - `size>1k`, `size<10m` — size bounds; suffixes `k`, `m`, `g` are powers of 1024
+/// - `modified:7d`, `modified:12h` — modified within the last days/hours
+/// - `owner:alice` or `owner:1000` — owned by this user name or uid; only
+///   matches indices built with `--index-owners`
+/// - `tag:todo` — tagged `todo` through `POST /tags`, ignoring case; repeat
+///   for entries with several tags
+/// - `is:symlink` — only symlinks; only matches indices built with
+///   `--index-symlinks`
+///
+/// Anything else, including operators with unparseable values, stays part of
+/// the fuzzy query.
+#[derive(Debug, Default)]
+struct QueryFilters {
+    extensions: Vec<String>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_within: Option<chrono::Duration>,
+    owner: Option<String>,
+    /// From the `hash` parameter rather than `q`, see `hash_matches`
+    hash: Option<String>,
+    /// Every one of these must be among the entry's tags
+    tags: Vec<String>,
+    /// Only symlinks, or no symlinks
+    symlink: Option<bool>,
 }
 
-#[derive(Serialize)]
-struct IndexStatus {
-    total_files: usize,
-    last_updated: DateTime<Utc>,
-    root_path: String,
+/// Parses `10`, `1k`, `5M`, `2g` into bytes.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let (number, multiplier) = match value.chars().last()? {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value.as_str(), 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
 }
 
-async fn index() -> Html<&'static str> {
-    Html(r#"
-        <!DOCTYPE html>
-        <html>
-        <head>
-            <title>Fuzzy File Search</title>
-            <style>
-                body { 
-                    font-family: Arial, sans-serif; 
-                    max-width: 800px; 
-                    margin: 2rem auto;
-                    padding: 0 1rem;
-                }
-                .controls {
-                    display: grid;
-                    grid-template-columns: 1fr 1fr;
-                    gap: 0.5rem;
-                    margin-bottom: 1rem;
-                }
-                .search-container {
-                    display: grid;
-                    grid-template-columns: 1fr auto;
-                    gap: 0.5rem;
-                    margin-bottom: 1rem;
-                }
-                #results { 
-                    height: 400px;
-                    overflow-y: auto;
-                    border: 1px solid #ddd;
-                    padding: 0.5rem;
-                    margin-top: 0.5rem;
-                }
-                button {
-                    padding: 0.5rem 1rem;
-                    width: 100%;
-                }
-                input, select {
-                    padding: 0.5rem;
-                    width: 100%;
-                }
-                .file-link {
-                    display: block;
-                    padding: 0.5rem;
-                    text-decoration: none;
-                    color: #0066cc;
-                }
-                .file-link:hover {
-                    background-color: #f0f0f0;
-                }
-            </style>
-        </head>
-        <body>
-            <h1>Fuzzy File Search</h1>
-            <div id="selectedPath" style="background: #f0f0f0; padding: 10px; margin: 10px 0; border-radius: 4px;">
-                Selected Directory: <span id="pathDisplay"></span>
-                <div id="indexStatus" style="font-size: 0.9em; color: #666;"></div>
-            </div>
-            <div class="controls">
-                <select id="pathSelect" onchange="changePath(this.value)">
-                    <option value="">Select recent path...</option>
-                </select>
-                <button onclick="createIndex()">Create/Update Index</button>
-                <button onclick="purgeIndices()" style="background-color: #ff4444; color: white;">Purge All Indices</button>
-                <button onclick="clearRecentPaths()" style="background-color: #ff4444; color: white;">Clear Recent Paths</button>
-                <button onclick="openDirectoryBrowser()">Browse Directories</button>
-            </div>
-            <div class="search-container">
-                <input type="text" id="search" placeholder="Search query...">
-                <button onclick="search()">Search</button>
-            </div>
-            <div id="results">
-                <div class="results-header">Search results: (only 25 rows visible)</div>
-            </div>
-            <button onclick="cancelSearch()" id="cancelBtn" style="display: none;">Cancel</button>
+/// Parses `7d` or `12h` into a duration. Zero, negative and ages reaching
+/// back past the earliest representable date are rejected.
+fn parse_age(value: &str) -> Option<chrono::Duration> {
+    let value = value.to_lowercase();
+    let number: i64 = value.get(..value.len().checked_sub(1)?)?.parse().ok().filter(|number| *number > 0)?;
+    let age = match value.chars().last()? {
+        'd' => chrono::Duration::try_days(number),
+        'h' => chrono::Duration::try_hours(number),
+        _ => None,
+    }?;
+    Utc::now().checked_sub_signed(age).map(|_| age)
+}
 
-            <script>
-                let currentController = null;
+/// Reads an optional `parse_age` value, rejecting anything it can't parse.
+fn deserialize_age<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<chrono::Duration>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_age(&value)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid age `{}`, expected a positive number of hours or days like `24h` or `7d`", value)))
+}
 
-                // Load recent paths on page load
-                window.addEventListener('load', async () => {
-                    const response = await fetch('/recent-paths');
-                    const paths = await response.json();
-                    const select = document.getElementById('pathSelect');
-                    
-                    paths.forEach(pathConfig => {
-                        const option = document.createElement('option');
-                        option.value = pathConfig.path;
-                        const lastIndexed = pathConfig.last_indexed ? 
-                            new Date(pathConfig.last_indexed).toLocaleString() : 'Never';
-                        option.textContent = `${pathConfig.path} (${pathConfig.total_files} files, indexed: ${lastIndexed})`;
-                        select.appendChild(option);
-                    });
-                });
+impl QueryFilters {
+    /// Records `token` if it's a recognized operator; returns whether it was.
+    fn parse_token(&mut self, token: &str) -> bool {
+        if let Some(exts) = token.strip_prefix("ext:") {
+            let exts: Vec<String> = exts.split(',')
+                .filter(|ext| !ext.is_empty())
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect();
+            if exts.is_empty() {
+                return false;
+            }
+            self.extensions.extend(exts);
+        } else if let Some(size) = token.strip_prefix("size>").and_then(parse_size) {
+            self.min_size = Some(size);
+        } else if let Some(size) = token.strip_prefix("size<").and_then(parse_size) {
+            self.max_size = Some(size);
+        } else if let Some(age) = token.strip_prefix("modified:").and_then(parse_age) {
+            self.modified_within = Some(age);
+        } else if let Some(owner) = token.strip_prefix("owner:").filter(|owner| !owner.is_empty()) {
+            self.owner = Some(owner.to_string());
+        } else if let Some(tag) = token.strip_prefix("tag:").filter(|tag| !tag.is_empty()) {
+            self.tags.push(tag.to_string());
+        } else if token == "is:symlink" {
+            self.symlink = Some(true);
+        } else {
+            return false;
+        }
+        true
+    }
 
-                async function changePath(path) {
-                    if (!path) return;
-                    
-                    const statusSpan = document.getElementById('indexStatus');
-                    statusSpan.textContent = 'Loading index for ' + path + '...';
-                    
-                    try {
-                        const response = await fetch('/change-path', {
-                            method: 'POST',
-                            headers: {
-                                'Content-Type': 'application/json',
-                            },
-                            body: JSON.stringify({ path }),
-                        });
-                        const result = await response.json();
-                        statusSpan.textContent = `Loaded index with ${result.total_files} files`;
-                    } catch (err) {
-                        statusSpan.textContent = 'Error changing path: ' + err.message;
-                    }
-                }
+    fn matches(&self, entry: &IndexEntry) -> bool {
+        if !self.extensions.is_empty() && !entry.extension().is_some_and(|ext| self.extensions.contains(&ext)) {
+            return false;
+        }
+        if self.min_size.is_some_and(|min| entry.size <= min) {
+            return false;
+        }
+        if self.max_size.is_some_and(|max| entry.size >= max) {
+            return false;
+        }
+        let cutoff = self.modified_within.and_then(|age| Utc::now().checked_sub_signed(age));
+        if cutoff.is_some_and(|cutoff| entry.last_modified < cutoff) {
+            return false;
+        }
+        if !self.tags.iter().all(|wanted| entry.tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted))) {
+            return false;
+        }
+        if self.symlink.is_some_and(|wanted| entry.is_symlink != wanted) {
+            return false;
+        }
+        if let Some(wanted) = &self.hash {
+            if !entry.content_hash.as_deref().is_some_and(|stored| hash_matches(stored, wanted)) {
+                return false;
+            }
+        }
+        if let Some(wanted) = &self.owner {
+            let owned = entry.owner.as_ref().is_some_and(|owner| {
+                owner.user.as_deref() == Some(wanted.as_str()) || owner.uid.to_string() == *wanted
+            });
+            if !owned {
+                return false;
+            }
+        }
+        true
+    }
+}
 
-                async function clearRecentPaths() {
-                    if (!confirm('Are you sure you want to clear all recent paths?')) {
-                        return;
-                    }
-                    
-                    const statusSpan = document.getElementById('indexStatus');
-                    statusSpan.textContent = 'Clearing recent paths...';
-                    
-                    try {
-                        const response = await fetch('/clear-recent-paths', {
-                            method: 'POST'
-                        });
-                        const result = await response.json();
-                        statusSpan.textContent = result;
-                        
-                        // Clear the path select dropdown
-                        const select = document.getElementById('pathSelect');
-                        while (select.options.length > 1) { // Keep the first "Select recent path..." option
-                            select.remove(1);
-                        }
-                    } catch (err) {
-                        statusSpan.textContent = 'Error clearing recent paths: ' + err.message;
-                    }
-                }
+fn default_recency_window_hours() -> f64 {
+    24.0
+}
 
-                async function purgeIndices() {
-                    if (!confirm('Are you sure you want to delete all saved indices?')) {
-                        return;
-                    }
-                    
-                    const statusSpan = document.getElementById('indexStatus');
-                    statusSpan.textContent = 'Purging all indices...';
-                    
-                    try {
-                        const response = await fetch('/purge-indices', {
-                            method: 'POST'
-                        });
-                        const result = await response.json();
-                        statusSpan.textContent = result;
-                    } catch (err) {
-                        statusSpan.textContent = 'Error purging indices: ' + err.message;
-                    }
-                }
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum RecencyDecay {
+    /// Bonus halves every window
+    #[default]
+    Exponential,
+    /// Bonus falls to zero over one window
+    Linear,
+}
 
-                async function createIndex() {
-                    const statusSpan = document.getElementById('indexStatus');
-                    statusSpan.textContent = 'Creating index...';
-                    
-                    try {
-                        const response = await fetch('/create-index', {
-                            method: 'POST'
-                        });
-                        const status = await response.json();
-                        statusSpan.textContent = `Indexed ${status.total_files} files`;
-                    } catch (err) {
-                        statusSpan.textContent = 'Error creating index: ' + err.message;
-                    }
-                }
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum TieBreaker {
+    /// Shorter paths first, then alphabetical
+    #[default]
+    ShortestPath,
+    /// Alphabetical by path
+    Path,
+}
 
-                async function search() {
-                    const searchInput = document.getElementById('search');
-                    const resultsDiv = document.getElementById('results');
-                    const cancelBtn = document.getElementById('cancelBtn');
-                    
-                    if (currentController) {
-                        currentController.abort();
-                    }
+fn default_suggest() -> bool {
+    true
+}
 
-                    currentController = new AbortController();
-                    cancelBtn.style.display = 'inline';
-                    resultsDiv.textContent = 'Searching...';
+/// Most "did you mean" paths returned for a search without results.
+const SUGGESTION_LIMIT: usize = 5;
 
-                    try {
-                        const response = await fetch(`/search?q=${encodeURIComponent(searchInput.value)}`, {
-                            signal: currentController.signal
-                        });
-                        const data = await response.json();
-                        
-                        // Clear previous results
-                        resultsDiv.innerHTML = '';
-                        
-                        // Create links for each file
-                        // Only show first 25 results
-                        data.files.slice(0, 25).forEach(file => {
-                            const link = document.createElement('a');
-                            link.href = `/download/${encodeURIComponent(file.path)}`;
-                            link.className = 'file-link';
-                            link.textContent = file.name;
-                            link.title = file.path; // Show full path on hover
-                            resultsDiv.appendChild(link);
-                        });
-                        
-                        if (data.files.length === 0) {
-                            resultsDiv.textContent = 'No files found';
-                        }
-                    } catch (err) {
-                        if (err.name === 'AbortError') {
-                            resultsDiv.textContent = 'Search cancelled';
-                        } else {
-                            resultsDiv.textContent = 'Error: ' + err.message;
-                        }
-                    } finally {
-                        currentController = null;
-                        cancelBtn.style.display = 'none';
-                    }
-                }
+/// Edit distance between `a` and `b`: the fewest single-character
+/// insertions, deletions and substitutions turning one into the other.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
 
-                function cancelSearch() {
-                    if (currentController) {
-                        currentController.abort();
-                    }
-                }
+/// Paths of entries whose name, with or without its extension, is within a
+/// few edits of `query`, closest first. A looser net for when fuzzy
+/// matching found nothing, typically because of a typo.
+fn suggest_paths<'a>(query: &SearchQuery, entries: impl Iterator<Item = &'a IndexEntry>) -> Vec<String> {
+    let wanted = query.q.trim().to_lowercase();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let max_distance = (wanted.chars().count() / 3).max(1);
 
-                // Enable search on Enter key
-                document.getElementById('search').addEventListener('keypress', function(e) {
-                    if (e.key === 'Enter') {
-                        search();
-                    }
-                });
-                async function openDirectoryBrowser() {
-                    const currentPath = document.getElementById('pathDisplay').textContent || '/';
-                    
-                    // Create modal overlay
-                    const overlay = document.createElement('div');
-                    overlay.style.cssText = `
-                        position: fixed;
-                        top: 0;
-                        left: 0;
-                        width: 100%;
-                        height: 100%;
-                        background: rgba(0,0,0,0.5);
-                        z-index: 999;
-                    `;
-                    
-                    async function loadDirectory(path) {
-                        try {
-                            const response = await fetch(`/list-directories/${encodeURIComponent(path)}`);
-                            const dirs = await response.json();
-                            
-                            // Update current path display
-                            pathDisplay.textContent = path;
-                            
-                            // Clear and rebuild directory list
-                            dirList.innerHTML = '';
-                            
-                            dirs.forEach(dir => {
-                                const link = document.createElement('a');
-                                link.href = '#';
-                                const isParent = dir === dirs[0] && path !== '/';
-                                link.textContent = isParent ? '📁 ..' : `📁 ${dir.split('/').pop()}`;
-                                link.style.cssText = `
-                                    display: block;
-                                    padding: 8px;
-                                    text-decoration: none;
-                                    color: #333;
-                                    border-bottom: 1px solid #eee;
-                                `;
-                                link.onmouseover = () => { link.style.backgroundColor = '#f0f0f0'; };
-                                link.onmouseout = () => { link.style.backgroundColor = 'transparent'; };
-                                link.onclick = async (e) => {
-                                    e.preventDefault();
-                                    await loadDirectory(dir);
-                                };
-                                dirList.appendChild(link);
-                            });
-                        } catch (err) {
-                            console.error('Error listing directories:', err);
-                        }
-                    }
-                    
-                    // Create modal content
-                    const modal = document.createElement('div');
-                    modal.style.cssText = `
-                        position: fixed;
-                        top: 50%;
-                        left: 50%;
-                        transform: translate(-50%, -50%);
-                        background: white;
-                        padding: 20px;
-                        border-radius: 8px;
-                        box-shadow: 0 2px 10px rgba(0,0,0,0.1);
-                        width: 80%;
-                        max-width: 600px;
-                        max-height: 80vh;
-                        display: flex;
-                        flex-direction: column;
-                        z-index: 1000;
-                    `;
-                    
-                    // Create header with current path display
-                    const header = document.createElement('div');
-                    header.style.marginBottom = '10px';
-                    const pathDisplay = document.createElement('div');
-                    pathDisplay.style.cssText = `
-                        font-weight: bold;
-                        padding: 8px;
-                        background: #f5f5f5;
-                        border-radius: 4px;
-                        margin-bottom: 10px;
-                    `;
-                    header.appendChild(pathDisplay);
-                    
-                    // Create scrollable directory list
-                    const dirList = document.createElement('div');
-                    dirList.style.cssText = `
-                        overflow-y: auto;
-                        flex-grow: 1;
-                        border: 1px solid #eee;
-                        border-radius: 4px;
-                    `;
-                    
-                    // Create footer with buttons
-                    const footer = document.createElement('div');
-                    footer.style.cssText = `
-                        margin-top: 15px;
-                        display: flex;
-                        justify-content: flex-end;
-                        gap: 10px;
-                    `;
-                    
-                    const selectButton = document.createElement('button');
-                    selectButton.textContent = 'Select Directory';
-                    selectButton.onclick = () => {
-                        const selectedPath = pathDisplay.textContent;
-                        changePath(selectedPath);
-                        document.body.removeChild(overlay);
-                    };
-                    
-                    const cancelButton = document.createElement('button');
-                    cancelButton.textContent = 'Cancel';
-                    cancelButton.onclick = () => {
-                        document.body.removeChild(overlay);
-                    };
-                    
-                    footer.appendChild(cancelButton);
-                    footer.appendChild(selectButton);
-                    
-                    // Assemble modal
-                    modal.appendChild(header);
-                    modal.appendChild(dirList);
-                    modal.appendChild(footer);
-                    
-                    // Add modal to overlay
-                    overlay.appendChild(modal);
-                    document.body.appendChild(overlay);
-                    
-                    // Load initial directory
-                    await loadDirectory(currentPath);
+    let mut candidates: Vec<(usize, &str)> = entries
+        .filter(|entry| query.kind.is_none_or(|kind| kind == entry.kind) && query.filters.matches(entry))
+        .filter_map(|entry| {
+            let name = entry.name.to_lowercase();
+            let stem = name.rsplit_once('.').map_or(name.as_str(), |(stem, _)| stem);
+            let distance = levenshtein(&wanted, &name).min(levenshtein(&wanted, stem));
+            (distance <= max_distance).then_some((distance, entry.path.as_str()))
+        })
+        .collect();
+    candidates.sort();
+    candidates.dedup_by(|a, b| a.1 == b.1);
+    candidates.into_iter().take(SUGGESTION_LIMIT).map(|(_, path)| path.to_string()).collect()
+}
+
+/// How `/search` can nest its results.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Deserialize, Clone, Copy)]
+enum ResultGrouping {
+    /// By the first component of the path
+    #[serde(rename = "toplevel")]
+    TopLevel,
+}
+
+/// Shape of `/search` results.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ResultFormat {
+    /// A list in `files`
+    #[default]
+    Flat,
+    /// Nested directories in `tree`, see `build_result_tree`
+    Tree,
+}
+
+/// File format of `/search/export`.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    /// One path per line
+    #[default]
+    Txt,
+    /// `{"paths": [...], "total": n, "truncated": bool}`
+    Json,
+    /// The columns of `/export/csv`
+    Csv,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Txt => "txt",
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Txt => "text/plain; charset=utf-8",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+/// Which part of an entry the query is matched against.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum SearchField {
+    /// The path relative to the root
+    #[default]
+    Path,
+    /// Just the file name
+    Name,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum CaseMode {
+    /// Case-insensitive unless the query has an uppercase letter
+    #[default]
+    Smart,
+    Insensitive,
+    Sensitive,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum MatchMode {
+    /// Query characters in order, not necessarily adjacent
+    #[default]
+    Fuzzy,
+    /// The query as a contiguous substring
+    Substring,
+}
+
+/// Fuzzy matching algorithm used to score entries.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum MatcherKind {
+    /// skim's V2 algorithm
+    #[default]
+    Skim,
+    /// nucleo, faster on large indices and ranking closer to fzf
+    Nucleo,
+}
+
+/// Adapts `nucleo_matcher` to the `FuzzyMatcher` trait so it can stand in
+/// for skim. nucleo needs mutable scratch space, hence the mutex.
+struct NucleoMatcher {
+    scratch: std::sync::Mutex<NucleoScratch>,
+    case: CaseMode,
+}
+
+/// nucleo's matcher and buffers, reused for every entry of a search. The
+/// needle is prepared once and kept for as long as the pattern stays the same.
+struct NucleoScratch {
+    matcher: nucleo_matcher::Matcher,
+    pattern: String,
+    needle: nucleo_matcher::Utf32String,
+    haystack: Vec<char>,
+    indices: Vec<u32>,
+}
+
+impl NucleoMatcher {
+    fn new(case: CaseMode) -> Self {
+        let mut config = nucleo_matcher::Config::DEFAULT.match_paths();
+        // Diacritics are handled by `fold_diacritics`, as they are for skim
+        config.normalize = false;
+        NucleoMatcher {
+            scratch: std::sync::Mutex::new(NucleoScratch {
+                matcher: nucleo_matcher::Matcher::new(config),
+                pattern: String::new(),
+                needle: Default::default(),
+                haystack: Vec::new(),
+                indices: Vec::new(),
+            }),
+            case,
+        }
+    }
+
+    /// The scratch space, with its needle and case handling set up for `pattern`
+    fn scratch(&self, pattern: &str) -> std::sync::MutexGuard<'_, NucleoScratch> {
+        let mut scratch = self.scratch.lock().unwrap_or_else(|e| e.into_inner());
+        if scratch.pattern != pattern {
+            let ignore_case = match self.case {
+                CaseMode::Smart => !pattern.chars().any(char::is_uppercase),
+                CaseMode::Insensitive => true,
+                CaseMode::Sensitive => false,
+            };
+            // nucleo folds the haystack itself but expects a lowercase needle
+            scratch.needle = match ignore_case {
+                true => pattern.to_lowercase().into(),
+                false => pattern.into(),
+            };
+            scratch.matcher.config.ignore_case = ignore_case;
+            scratch.pattern = pattern.to_string();
+        }
+        scratch
+    }
+}
+
+impl FuzzyMatcher for NucleoMatcher {
+    fn fuzzy_indices(&self, choice: &str, pattern: &str) -> Option<(i64, Vec<usize>)> {
+        let mut scratch = self.scratch(pattern);
+        let NucleoScratch { matcher, needle, haystack, indices, .. } = &mut *scratch;
+        indices.clear();
+        let score = matcher.fuzzy_indices(nucleo_matcher::Utf32Str::new(choice, haystack), needle.slice(..), indices)?;
+
+        indices.sort_unstable();
+        indices.dedup();
+        Some((score as i64, indices.iter().map(|&i| i as usize).collect()))
+    }
+
+    /// Scores without tracking matched positions, which searches don't need
+    fn fuzzy_match(&self, choice: &str, pattern: &str) -> Option<i64> {
+        let mut scratch = self.scratch(pattern);
+        let NucleoScratch { matcher, needle, haystack, .. } = &mut *scratch;
+        matcher.fuzzy_match(nucleo_matcher::Utf32Str::new(choice, haystack), needle.slice(..)).map(i64::from)
+    }
+}
+
+/// Fallbacks for the search parameters a request may omit, kept in `Config`.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+struct SearchDefaults {
+    field: SearchField,
+    case: CaseMode,
+    mode: MatchMode,
+    sort: TieBreaker,
+    matcher: MatcherKind,
+    anchor_prefix: bool,
+}
+
+/// Weights combined by `SearchQuery::score`, kept in `Config` and overridable
+/// per request. The defaults rank by the matcher's score alone.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+struct ScoringWeights {
+    /// Multiplier on the matcher's score
+    fuzzy: f64,
+    /// Bonus when the query matches the file name as well as the path
+    name_match: i64,
+    /// Largest bonus for a just-modified file, fading with age
+    recency: f64,
+    /// Largest bonus for a file downloaded often and lately
+    frecency: f64,
+    /// Subtracted once per directory a result is nested in
+    depth_penalty: i64,
+}
+
+impl Default for ScoringWeights {
+    fn default() -> Self {
+        ScoringWeights {
+            fuzzy: 1.0,
+            name_match: 0,
+            recency: 0.0,
+            frecency: 0.0,
+            depth_penalty: 0,
+        }
+    }
+}
+
+impl TieBreaker {
+    fn compare(self, a: &IndexEntry, b: &IndexEntry) -> std::cmp::Ordering {
+        self.compare_paths(&a.path, &b.path)
+    }
+
+    fn compare_paths(self, a: &str, b: &str) -> std::cmp::Ordering {
+        match self {
+            TieBreaker::ShortestPath => a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+            TieBreaker::Path => a.cmp(b),
+        }
+    }
+}
+
+impl RecencyDecay {
+    /// Fraction of the full bonus left after `age_hours`.
+    fn factor(self, age_hours: f64, window_hours: f64) -> f64 {
+        if window_hours <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            RecencyDecay::Exponential => 0.5_f64.powf(age_hours / window_hours),
+            RecencyDecay::Linear => (1.0 - age_hours / window_hours).max(0.0),
+        }
+    }
+}
+
+/// Strips combining marks after canonical decomposition, e.g. `é` -> `e`.
+fn fold_diacritics(text: &str) -> String {
+    use unicode_normalization::char::is_combining_mark;
+    use unicode_normalization::UnicodeNormalization;
+
+    text.nfd().filter(|c| !is_combining_mark(*c)).collect()
+}
+
+/// Substring match scored by position, so earlier matches rank higher. Case
+/// handling follows `case`, as it does for the fuzzy matcher.
+fn substring_score(text: &str, pattern: &str, case: CaseMode) -> Option<i64> {
+    let sensitive = match case {
+        CaseMode::Smart => pattern.chars().any(char::is_uppercase),
+        CaseMode::Insensitive => false,
+        CaseMode::Sensitive => true,
+    };
+    let position = if sensitive {
+        text.find(pattern)
+    } else {
+        text.to_lowercase().find(&pattern.to_lowercase())
+    };
+    position.map(|pos| -(pos as i64))
+}
+
+impl SearchQuery {
+    /// Label used for metrics
+    fn mode(&self) -> &'static str {
+        match self.match_mode() {
+            MatchMode::Fuzzy => "fuzzy",
+            MatchMode::Substring => "substring",
+        }
+    }
+
+    fn match_mode(&self) -> MatchMode {
+        if self.strict_substring {
+            MatchMode::Substring
+        } else {
+            self.mode.unwrap_or_default()
+        }
+    }
+
+    fn tie_breaker(&self) -> TieBreaker {
+        self.tie_breaker.unwrap_or_default()
+    }
+
+    /// Fills in whatever the request left out from the configured defaults.
+    fn with_defaults(mut self, config: &Config) -> Self {
+        let defaults = &config.default_search;
+        self.field.get_or_insert(defaults.field);
+        self.case.get_or_insert(defaults.case);
+        self.mode.get_or_insert(defaults.mode);
+        self.tie_breaker.get_or_insert(defaults.sort);
+        self.matcher.get_or_insert(defaults.matcher);
+        self.anchor_prefix.get_or_insert(defaults.anchor_prefix);
+        let scoring = &config.scoring;
+        self.fuzzy_weight.get_or_insert(scoring.fuzzy);
+        self.name_match_bonus.get_or_insert(scoring.name_match);
+        self.recency_weight.get_or_insert(scoring.recency);
+        self.frecency_weight.get_or_insert(scoring.frecency);
+        self.depth_penalty.get_or_insert(scoring.depth_penalty);
+        self
+    }
+
+    /// The requested fuzzy matcher, honouring the requested case handling.
+    fn matcher(&self) -> Box<dyn FuzzyMatcher> {
+        let case = self.case.unwrap_or_default();
+        match self.matcher.unwrap_or_default() {
+            MatcherKind::Skim => Box::new(match case {
+                CaseMode::Smart => SkimMatcherV2::default().smart_case(),
+                CaseMode::Insensitive => SkimMatcherV2::default().ignore_case(),
+                CaseMode::Sensitive => SkimMatcherV2::default().respect_case(),
+            }),
+            MatcherKind::Nucleo => Box::new(NucleoMatcher::new(case)),
+        }
+    }
+
+    /// Moves inline operators such as `ext:rs` out of `q` and into `filters`,
+    /// leaving the free text to be fuzzy-matched, folded once up front when
+    /// `fold_diacritics` is set.
+    ///
+    /// With `anchor_prefix`, a query like `src/ma` is split at its last `/`:
+    /// `src/` goes to `path_prefix`, which results must start with, and only
+    /// `ma` is matched, against the rest of the path. Queries without a `/`,
+    /// or with whitespace before it, are left alone.
+    fn extract_operators(mut self) -> Self {
+        let mut filters = QueryFilters::default();
+        let text: Vec<&str> = self.q.split_whitespace()
+            .filter(|token| !filters.parse_token(token))
+            .collect();
+        // The stricter of `age` and an inline `modified:` applies
+        filters.modified_within = filters.modified_within.into_iter().chain(self.age).min();
+        filters.tags.extend(self.tag.as_deref().map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_string));
+        filters.hash = self.hash.as_deref().map(str::trim).filter(|hash| !hash.is_empty()).map(str::to_string);
+        filters.symlink = filters.symlink.or(self.symlink);
+        self.q = text.join(" ");
+        if self.fold_diacritics {
+            self.q = fold_diacritics(&self.q);
+        }
+        if self.anchor_prefix.unwrap_or_default() {
+            if let Some(slash) = self.q.rfind('/') {
+                let prefix = self.q[..=slash].trim_start_matches('/');
+                if !prefix.is_empty() && !prefix.contains(char::is_whitespace) {
+                    self.path_prefix = Some(prefix.to_string());
+                    self.q = self.q[slash + 1..].to_string();
                 }
+            }
+        }
+        self.filters = filters;
+        self
+    }
 
-                // Update path display when path changes
-                function updatePathDisplay(path) {
-                    document.getElementById('pathDisplay').textContent = path;
+    /// The part of `path` after `path_prefix`, or `None` if it doesn't
+    /// start with it; all of `path` when there is no prefix. Letter case is
+    /// compared as `case` says, for ASCII letters.
+    fn strip_path_prefix<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let Some(prefix) = &self.path_prefix else {
+            return Some(path);
+        };
+        let head = path.get(..prefix.len())?;
+        let sensitive = match self.case.unwrap_or_default() {
+            CaseMode::Sensitive => true,
+            CaseMode::Insensitive => false,
+            CaseMode::Smart => prefix.chars().any(char::is_uppercase),
+        };
+        let matches = if sensitive { head == prefix } else { head.eq_ignore_ascii_case(prefix) };
+        matches.then(|| &path[prefix.len()..])
+    }
+
+    /// Scores `entry` against the query, or `None` if it doesn't match:
+    /// the matcher's score times `fuzzy_weight`, plus `name_match_bonus` when
+    /// the file name matches too, plus the recency bonus, minus the depth
+    /// penalty.
+    fn score(&self, matcher: &dyn FuzzyMatcher, entry: &IndexEntry) -> Option<i64> {
+        if self.kind.is_some_and(|kind| kind != entry.kind) || !self.filters.matches(entry) {
+            return None;
+        }
+        if self.min_lines.is_some() || self.max_lines.is_some() {
+            let lines = entry.lines?;
+            if self.min_lines.is_some_and(|min| lines < min) || self.max_lines.is_some_and(|max| lines > max) {
+                return None;
+            }
+        }
+
+        let (path, name) = match self.fold_diacritics {
+            false => (entry.path.as_str(), entry.name.as_str()),
+            true => (entry.folded().path.as_str(), entry.folded().name.as_str()),
+        };
+        let romanized = entry.romanized.as_deref()
+            .and_then(|romanized| self.match_text(romanized, romanized.rsplit('/').next().unwrap_or(romanized)));
+        let tags = entry.tags.iter()
+            .filter(|_| self.match_tags && self.strip_path_prefix(path).is_some())
+            .filter_map(|tag| self.match_score(matcher, tag))
+            .max();
+        let score = self.match_text(path, name).and_then(|text| self.match_score(matcher, text))
+            .max(romanized.and_then(|romanized| self.match_score(matcher, romanized)))
+            .max(tags)?;
+        let score = (score as f64 * self.fuzzy_weight.unwrap_or(1.0)).round() as i64;
+
+        // Weights come straight from the request, so extreme ones clamp
+        // rather than overflow
+        Some(score
+            .saturating_add(self.name_match_bonus(matcher, entry))
+            .saturating_add(self.recency_bonus(entry))
+            .saturating_sub(self.depth_penalty(entry)))
+    }
+
+    /// What `field` selects from an entry's `path` and `name`, past
+    /// `path_prefix`; `None` if the path is outside the prefix.
+    fn match_text<'a>(&self, path: &'a str, name: &'a str) -> Option<&'a str> {
+        let rest = self.strip_path_prefix(path)?;
+        Some(match self.field.unwrap_or_default() {
+            SearchField::Path => rest,
+            SearchField::Name => name,
+        })
+    }
+
+    fn match_score(&self, matcher: &dyn FuzzyMatcher, text: &str) -> Option<i64> {
+        match self.match_mode() {
+            MatchMode::Substring => substring_score(text, &self.q, self.case.unwrap_or_default()),
+            MatchMode::Fuzzy => matcher.fuzzy_match(text, &self.q),
+        }
+    }
+
+    /// Bonus for a path match whose file name matches on its own, so `main`
+    /// prefers `src/main.rs` over `maintenance/index.js`.
+    fn name_match_bonus(&self, matcher: &dyn FuzzyMatcher, entry: &IndexEntry) -> i64 {
+        let bonus = self.name_match_bonus.unwrap_or_default();
+        if bonus == 0 {
+            return 0;
+        }
+        let name = match self.fold_diacritics {
+            false => &entry.name,
+            true => &entry.folded().name,
+        };
+        if self.match_score(matcher, name).is_some() { bonus } else { 0 }
+    }
+
+    fn depth_penalty(&self, entry: &IndexEntry) -> i64 {
+        let penalty = self.depth_penalty.unwrap_or_default();
+        if penalty == 0 {
+            return 0;
+        }
+        // Paths are stored with `/` separators on every platform, see `slash_path`
+        penalty.saturating_mul(entry.path.matches('/').count() as i64)
+    }
+
+    /// Score nudge for recently modified files, so fresh work floats up
+    /// without overriding a much stronger match.
+    fn recency_bonus(&self, entry: &IndexEntry) -> i64 {
+        let weight = self.recency_weight.unwrap_or_default();
+        if weight <= 0.0 {
+            return 0;
+        }
+        let age_hours = (Utc::now() - entry.last_modified).num_seconds().max(0) as f64 / 3600.0;
+        (weight * self.recency_decay.factor(age_hours, self.recency_window_hours)).round() as i64
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize, Clone)]
+struct SearchResult {
+    files: Vec<IndexEntry>,
+    /// Whether an index was loaded for the searched root; with `index_size`,
+    /// tells "nothing indexed yet" apart from "no matches"
+    index_loaded: bool,
+    /// Entries searched, summed over every root for an `all_roots` search
+    index_size: usize,
+    /// With `group_by_name`, the other paths sharing each result's file name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alternatives: Option<HashMap<String, Vec<String>>>,
+    /// With `group=toplevel`, the results that would be in `files`, which
+    /// is then empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<ResultGroup>>,
+    /// Only when nothing matched: paths of near misses, see `suggest_paths`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestions: Option<Vec<String>>,
+    /// With `format=tree`, the results that would be in `files`, which is
+    /// then empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tree: Option<Vec<TreeNode>>,
+    /// With `limit`, pass this back as `cursor` for the next page; absent
+    /// on the last one. Opaque: its contents may change between versions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+    /// The `cursor` given wasn't made by this search, so the page starts
+    /// from the first result instead
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    cursor_reset: bool,
+    /// Score of each of `files`, for finding a cursor's place again
+    #[serde(skip)]
+    scores: Vec<i64>,
+}
+
+/// Where a page of search results ended: the last result's score and path,
+/// and a fingerprint of the search, so a cursor can't be used with another.
+/// Sent to clients hex-encoded, as an opaque string.
+#[derive(Serialize, Deserialize)]
+struct SearchCursor {
+    search: String,
+    score: i64,
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root: Option<String>,
+}
+
+impl SearchCursor {
+    fn encode(&self) -> String {
+        serde_json::to_vec(self).unwrap_or_default().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let bytes = (0..cursor.len()).step_by(2)
+            .map(|i| cursor.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Index of the first result after this cursor: right after its own
+    /// result if that's still there, otherwise the first that sorts after
+    /// where it was, so a changed index neither repeats nor skips results.
+    fn resume_at(&self, files: &[IndexEntry], scores: &[i64], tie_breaker: TieBreaker) -> usize {
+        if let Some(position) = files.iter().position(|entry| entry.path == self.path && entry.root == self.root) {
+            return position + 1;
+        }
+        files.iter().zip(scores)
+            .position(|(entry, &score)| {
+                score < self.score
+                    || (score == self.score && tie_breaker.compare_paths(&entry.path, &self.path).is_gt())
+            })
+            .unwrap_or(files.len())
+    }
+}
+
+/// Cuts `result.files` down to the page after `cursor`, at most `limit`
+/// long, and sets `next_cursor` if more follow. `search_key` identifies the
+/// search, so cursors from other searches start over.
+fn paginate(mut result: SearchResult, cursor: Option<&str>, limit: Option<usize>, search_key: &str, tie_breaker: TieBreaker) -> SearchResult {
+    let search = format!("{:x}", md5::compute(search_key));
+    let start = match cursor.map(|cursor| SearchCursor::decode(cursor).filter(|cursor| cursor.search == search)) {
+        None => 0,
+        Some(Some(cursor)) => cursor.resume_at(&result.files, &result.scores, tie_breaker),
+        Some(None) => {
+            println!("Cursor is not from this search; starting from the first result");
+            result.cursor_reset = true;
+            0
+        }
+    };
+    let end = limit.map_or(result.files.len(), |limit| start.saturating_add(limit.max(1)).min(result.files.len()));
+    if end < result.files.len() {
+        let last = &result.files[end - 1];
+        result.next_cursor = Some(SearchCursor {
+            search,
+            score: result.scores[end - 1],
+            path: last.path.clone(),
+            root: last.root.clone(),
+        }.encode());
+    }
+    println!("Returning results {} to {} of {}", start, end, result.files.len());
+    result.files.truncate(end);
+    result.files.drain(..start);
+    result.scores.truncate(end);
+    result.scores.drain(..start);
+    result
+}
+
+/// A directory or file on the way to a search result. Only branches that
+/// lead to a match are included.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize, Clone)]
+struct TreeNode {
+    name: String,
+    /// Relative to the root, like `IndexEntry::path`; for `all_roots`
+    /// searches the top-level nodes are the roots themselves
+    path: String,
+    /// Only on nodes that matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<i64>,
+    /// Only on nodes that matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entry: Option<IndexEntry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "openapi", schema(no_recursion))]
+    children: Vec<TreeNode>,
+}
+
+/// Splits `path` into its components, archives counting as directories,
+/// each paired with the prefix of `path` that ends with it.
+fn path_prefixes(path: &str) -> Vec<(&str, &str)> {
+    let mut prefixes = Vec::new();
+    let mut start = 0;
+    for (i, _) in path.match_indices('/') {
+        let end = if path[..=i].ends_with(ARCHIVE_SEPARATOR) { i + 1 - ARCHIVE_SEPARATOR.len() } else { i };
+        prefixes.push((&path[start..end], &path[..end]));
+        start = i + 1;
+    }
+    prefixes.push((&path[start..], path));
+    prefixes
+}
+
+/// Assembles scored results, best first, into a tree. Siblings keep the
+/// order in which the results first reach them, so better branches come
+/// first. Built and nested without recursion, so any depth is fine.
+fn build_result_tree(matches: &[(i64, IndexEntry)]) -> Vec<TreeNode> {
+    // Flat arena first; children always come after their parent
+    let mut nodes: Vec<TreeNode> = Vec::new();
+    let mut children: Vec<Vec<usize>> = Vec::new();
+    let mut top_level: Vec<usize> = Vec::new();
+    let mut positions: HashMap<(Option<usize>, String), usize> = HashMap::new();
+
+    for (score, entry) in matches {
+        let mut components = path_prefixes(&entry.path);
+        if let Some(root) = entry.root.as_deref() {
+            components.insert(0, (root, root));
+        }
+        let mut parent = None;
+        for (name, path) in components {
+            let node = *positions.entry((parent, path.to_string())).or_insert_with(|| {
+                nodes.push(TreeNode {
+                    name: name.to_string(),
+                    path: path.to_string(),
+                    score: None,
+                    entry: None,
+                    children: Vec::new(),
+                });
+                children.push(Vec::new());
+                let node = nodes.len() - 1;
+                match parent {
+                    Some(parent) => children[parent].push(node),
+                    None => top_level.push(node),
                 }
+                node
+            });
+            parent = Some(node);
+        }
+        if let Some(leaf) = parent {
+            nodes[leaf].score = Some(*score);
+            nodes[leaf].entry = Some(entry.clone());
+        }
+    }
 
-                // Modify existing changePath function
-                async function changePath(path) {
-                    if (!path) return;
-                    
-                    const statusSpan = document.getElementById('indexStatus');
-                    if (statusSpan) {
-                        statusSpan.textContent = 'Changed to: ' + path;
-                    }
-                    updatePathDisplay(path);
-                    
-                    try {
-                        const response = await fetch('/change-path', {
-                            method: 'POST',
-                            headers: {
-                                'Content-Type': 'application/json',
-                            },
-                            body: JSON.stringify({ path }),
+    // Nest from the last node back, so every child is complete before its parent
+    let mut built: Vec<Option<TreeNode>> = nodes.into_iter().map(Some).collect();
+    for node in (0..built.len()).rev() {
+        let nested: Vec<TreeNode> = children[node].iter().filter_map(|&child| built[child].take()).collect();
+        if let Some(parent) = built[node].as_mut() {
+            parent.children = nested;
+        }
+    }
+    top_level.into_iter().filter_map(|node| built[node].take()).collect()
+}
+
+/// Number of matched nodes in `tree`, counted without recursion.
+fn count_tree_matches(tree: &[TreeNode]) -> usize {
+    let mut count = 0;
+    let mut pending: Vec<&TreeNode> = tree.iter().collect();
+    while let Some(node) = pending.pop() {
+        count += usize::from(node.entry.is_some());
+        pending.extend(node.children.iter());
+    }
+    count
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize, Clone)]
+struct ResultGroup {
+    /// Top-level directory, or `.` for files directly under the root
+    name: String,
+    count: usize,
+    files: Vec<IndexEntry>,
+}
+
+/// Name of the group `group_by_top_level` puts files directly under the root in.
+const ROOT_GROUP: &str = ".";
+
+/// Nests `results` under the first component of their path. Expects them
+/// sorted best-first, and keeps that order both within each group and
+/// between groups, so a group ranks by its best result.
+fn group_by_top_level(results: Vec<IndexEntry>) -> Vec<ResultGroup> {
+    let mut groups: Vec<ResultGroup> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for entry in results {
+        // An archive's members belong wherever the archive itself is
+        let on_disk = entry.path.split(ARCHIVE_SEPARATOR).next().unwrap_or(&entry.path);
+        let name = match on_disk.split_once('/') {
+            Some((top, _)) => top.to_string(),
+            None => ROOT_GROUP.to_string(),
+        };
+        let position = *positions.entry(name.clone()).or_insert_with(|| {
+            groups.push(ResultGroup { name, count: 0, files: Vec::new() });
+            groups.len() - 1
+        });
+        groups[position].count += 1;
+        groups[position].files.push(entry);
+    }
+    groups
+}
+
+/// Most search results kept in the cache at once.
+const SEARCH_CACHE_CAPACITY: usize = 64;
+
+/// How long a cached search result stays valid.
+const SEARCH_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(30);
+
+struct CachedSearch {
+    key: String,
+    /// Root the result was computed from; `None` for an `all_roots` search
+    root: Option<String>,
+    created: std::time::Instant,
+    result: SearchResult,
+}
+
+/// Small LRU cache of recent search results, most recently used last.
+#[derive(Default)]
+struct SearchCache {
+    entries: std::collections::VecDeque<CachedSearch>,
+}
+
+impl SearchCache {
+    fn get(&mut self, key: &str) -> Option<SearchResult> {
+        self.entries.retain(|cached| cached.created.elapsed() < SEARCH_CACHE_TTL);
+        let position = self.entries.iter().position(|cached| cached.key == key)?;
+        let cached = self.entries.remove(position)?;
+        let result = cached.result.clone();
+        self.entries.push_back(cached);
+        Some(result)
+    }
+
+    fn insert(&mut self, key: String, root: Option<String>, result: SearchResult) {
+        self.entries.retain(|cached| cached.key != key);
+        if self.entries.len() >= SEARCH_CACHE_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(CachedSearch {
+            key,
+            root,
+            created: std::time::Instant::now(),
+            result,
+        });
+    }
+
+    /// Drops results that depend on `root`'s index, including every
+    /// `all_roots` result.
+    fn invalidate_root(&mut self, root: &str) {
+        self.entries.retain(|cached| cached.root.as_deref().is_some_and(|r| r != root));
+    }
+}
+
+/// A search match with its score.
+type ScoredEntry = (i64, IndexEntry);
+
+/// Keeps the best-scoring entry for each file name, collecting the paths of
+/// the rest. Expects `results` already sorted best-first.
+fn group_by_name(results: Vec<ScoredEntry>) -> (Vec<ScoredEntry>, HashMap<String, Vec<String>>) {
+    let mut distinct = Vec::new();
+    let mut alternatives: HashMap<String, Vec<String>> = HashMap::new();
+    for (score, entry) in results {
+        match alternatives.get_mut(&entry.name) {
+            Some(paths) => paths.push(entry.path),
+            None => {
+                alternatives.insert(entry.name.clone(), Vec::new());
+                distinct.push((score, entry));
+            }
+        }
+    }
+    alternatives.retain(|_, paths| !paths.is_empty());
+    (distinct, alternatives)
+}
+
+/// Merges entries whose absolute paths are equal ignoring case, keeping the
+/// best-ranked position. Of the duplicates, the one whose casing matches the
+/// directory listing on disk wins so downloads use the real name. Expects
+/// `results` already sorted best-first.
+fn collapse_case_duplicates(results: Vec<ScoredEntry>, current_path: &str) -> Vec<ScoredEntry> {
+    let absolute = |entry: &IndexEntry| {
+        std::path::Path::new(entry.root.as_deref().unwrap_or(current_path)).join(&entry.path)
+    };
+    let mut distinct: Vec<ScoredEntry> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (score, entry) in results {
+        let key = absolute(&entry).to_string_lossy().to_lowercase();
+        match positions.get(&key) {
+            Some(&position) => {
+                if !has_on_disk_casing(&absolute(&distinct[position].1)) && has_on_disk_casing(&absolute(&entry)) {
+                    distinct[position].1 = entry;
+                }
+            }
+            None => {
+                positions.insert(key, distinct.len());
+                distinct.push((score, entry));
+            }
+        }
+    }
+    distinct
+}
+
+/// Whether `path`'s file name appears with exactly this casing in its parent
+/// directory. Case-insensitive filesystems resolve any casing, so `exists`
+/// can't tell the difference.
+fn has_on_disk_casing(path: &std::path::Path) -> bool {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    fs::read_dir(parent)
+        .map(|entries| entries.flatten().any(|entry| entry.file_name() == name))
+        .unwrap_or(false)
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct IndexStatus {
+    total_files: usize,
+    last_updated: DateTime<Utc>,
+    root_path: String,
+    /// Set when the run was cancelled and the previous index kept
+    cancelled: bool,
+    /// Entries the walk couldn't read and left out of the index
+    skipped: SkippedEntries,
+    /// Why the index couldn't be written to disk; it is still in memory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    save_error: Option<String>,
+}
+
+/// Most example paths kept in `SkippedEntries`.
+const SKIPPED_EXAMPLES: usize = 10;
+
+/// Tally of entries a directory walk had to leave out because reading them
+/// failed, so an incomplete index doesn't go unnoticed.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize, Default)]
+struct SkippedEntries {
+    count: usize,
+    permission_denied: usize,
+    /// Any other I/O failure, including symlink loops
+    io: usize,
+    /// The first few affected paths
+    examples: Vec<String>,
+}
+
+impl SkippedEntries {
+    fn record(&mut self, path: Option<&std::path::Path>, error: Option<&io::Error>) {
+        self.count += 1;
+        if error.is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied) {
+            self.permission_denied += 1;
+        } else {
+            self.io += 1;
+        }
+        if let Some(path) = path.filter(|_| self.examples.len() < SKIPPED_EXAMPLES) {
+            self.examples.push(path.display().to_string());
+        }
+    }
+}
+
+/// Title used for the embedded page when `app_title` isn't configured.
+const DEFAULT_APP_TITLE: &str = "Fuzzy File Search";
+
+/// Escapes text for inclusion in HTML element content.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Icon for the embedded UI's browser tab.
+const FAVICON: &[u8] = include_bytes!("favicon.ico");
+
+async fn favicon() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "image/x-icon"),
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        FAVICON,
+    )
+}
+
+/// Answers unknown routes: a page linking back to the UI for browser
+/// navigation, a JSON error for everything else so API clients can parse it.
+async fn not_found(
+    base_path: String,
+    method: Method,
+    axum::extract::OriginalUri(uri): axum::extract::OriginalUri,
+    headers: axum::http::HeaderMap,
+) -> Response {
+    println!("Not found: {} {}", method, uri);
+    let wants_html = method == Method::GET
+        && headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"));
+
+    if !wants_html {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": format!("No route for {} {}", method, uri.path()) })),
+        ).into_response();
+    }
+
+    let page = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Page not found</title>
+    <link rel="icon" href="{{base_path}}/favicon.ico">
+    <style>
+        body { font-family: Arial, sans-serif; max-width: 800px; margin: 2rem auto; padding: 0 1rem; }
+    </style>
+</head>
+<body>
+    <h1>Page not found</h1>
+    <p>Nothing lives at <code>{{path}}</code>.</p>
+    <p><a href="{{base_path}}/">Back to search</a></p>
+</body>
+</html>"#
+        .replace("{{path}}", &escape_html(uri.path()))
+        .replace("{{base_path}}", &base_path);
+    (StatusCode::NOT_FOUND, Html(page)).into_response()
+}
+
+async fn index(State(state): State<AppState>) -> Html<String> {
+    let (title, banner) = {
+        let config = state.config.read().await;
+        (
+            escape_html(config.app_title.as_deref().unwrap_or(DEFAULT_APP_TITLE)),
+            config.banner.as_deref().map(escape_html),
+        )
+    };
+    let banner = banner
+        .map(|text| format!(
+            r#"<div id="banner" style="background: #fff3cd; padding: 10px; margin: 10px 0; border-radius: 4px;">{}</div>"#,
+            text,
+        ))
+        .unwrap_or_default();
+
+    Html(r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            <title>{{app_title}}</title>
+            <link rel="icon" href="{{base_path}}/favicon.ico">
+            <style>
+                body { 
+                    font-family: Arial, sans-serif; 
+                    max-width: 800px; 
+                    margin: 2rem auto;
+                    padding: 0 1rem;
+                }
+                .controls {
+                    display: grid;
+                    grid-template-columns: 1fr 1fr;
+                    gap: 0.5rem;
+                    margin-bottom: 1rem;
+                }
+                .search-container {
+                    display: grid;
+                    grid-template-columns: 1fr auto auto;
+                    align-items: center;
+                    gap: 0.5rem;
+                    margin-bottom: 1rem;
+                }
+                #results { 
+                    height: 400px;
+                    overflow-y: auto;
+                    border: 1px solid #ddd;
+                    padding: 0.5rem;
+                    margin-top: 0.5rem;
+                }
+                button {
+                    padding: 0.5rem 1rem;
+                    width: 100%;
+                }
+                input, select {
+                    padding: 0.5rem;
+                    width: 100%;
+                }
+                .file-link {
+                    display: block;
+                    padding: 0.5rem;
+                    text-decoration: none;
+                    color: #0066cc;
+                }
+                .file-link:hover {
+                    background-color: #f0f0f0;
+                }
+            </style>
+        </head>
+        <body>
+            <h1>{{app_title}}</h1>
+            {{banner}}
+            <div id="selectedPath" style="background: #f0f0f0; padding: 10px; margin: 10px 0; border-radius: 4px;">
+                Selected Directory: <span id="pathDisplay"></span>
+                <div id="indexStatus" style="font-size: 0.9em; color: #666;"></div>
+            </div>
+            <div class="controls">
+                <select id="pathSelect" onchange="changePath(this.value)">
+                    <option value="">Select recent path...</option>
+                    <optgroup id="bookmarkGroup" label="Bookmarks"></optgroup>
+                </select>
+                <button onclick="createIndex()">Create/Update Index</button>
+                <button onclick="purgeIndices()" style="background-color: #ff4444; color: white;">Purge All Indices</button>
+                <button onclick="clearRecentPaths()" style="background-color: #ff4444; color: white;">Clear Recent Paths</button>
+                <button onclick="openDirectoryBrowser()">Browse Directories</button>
+                <button onclick="bookmarkCurrentPath()">Bookmark Directory</button>
+            </div>
+            <div class="search-container">
+                <input type="text" id="search" placeholder="Search query...">
+                <label style="white-space: nowrap;"><input type="checkbox" id="allRoots" style="width: auto;"> All roots</label>
+                <button onclick="search()">Search</button>
+            </div>
+            <div id="results">
+                <div class="results-header">Search results: (only 25 rows visible)</div>
+            </div>
+            <button onclick="cancelSearch()" id="cancelBtn" style="display: none;">Cancel</button>
+
+            <script>
+                // Prefix for every request, set by --base-path when served under a subpath
+                const BASE_PATH = '{{base_path}}';
+                // Icons for the `file_type` the server puts on each result
+                const FILE_TYPE_ICONS = {{file_type_icons}};
+                let currentController = null;
+                let readOnly = false;
+                // Set when the server has an `editor_link_template` to open files with
+                let editorLinks = false;
+
+                function addBookmarkOption(pathConfig) {
+                    const option = document.createElement('option');
+                    option.value = pathConfig.path;
+                    option.textContent = pathConfig.path;
+                    document.getElementById('bookmarkGroup').appendChild(option);
+                }
+
+                // Load bookmarks on page load
+                window.addEventListener('load', async () => {
+                    const response = await fetch(`${BASE_PATH}/bookmarks`);
+                    const bookmarks = await response.json();
+                    bookmarks.forEach(addBookmarkOption);
+                });
+
+                async function bookmarkCurrentPath() {
+                    const path = document.getElementById('pathDisplay').textContent;
+                    const statusSpan = document.getElementById('indexStatus');
+                    if (!path) return;
+
+                    try {
+                        const response = await fetch(`${BASE_PATH}/bookmarks`, {
+                            method: 'POST',
+                            headers: {
+                                'Content-Type': 'application/json',
+                            },
+                            body: JSON.stringify({ path }),
                         });
                         const result = await response.json();
-                        if (statusSpan) {
-                            statusSpan.textContent = `Changed to ${path} (${result.total_files} files indexed)`;
+                        if (!response.ok) {
+                            statusSpan.textContent = result;
+                            return;
                         }
+                        document.getElementById('bookmarkGroup').innerHTML = '';
+                        result.forEach(addBookmarkOption);
+                        statusSpan.textContent = 'Bookmarked ' + path;
                     } catch (err) {
-                        if (statusSpan) {
-                            statusSpan.textContent = 'Error changing path: ' + err.message;
-                        }
-                        console.error('Error changing path:', err);
+                        statusSpan.textContent = 'Error adding bookmark: ' + err.message;
+                    }
+                }
+
+                // Load recent paths on page load
+                window.addEventListener('load', async () => {
+                    const response = await fetch(`${BASE_PATH}/recent-paths`);
+                    const paths = await response.json();
+                    const select = document.getElementById('pathSelect');
+                    
+                    paths.forEach(pathConfig => {
+                        const option = document.createElement('option');
+                        option.value = pathConfig.path;
+                        const lastIndexed = pathConfig.last_indexed ? 
+                            new Date(pathConfig.last_indexed).toLocaleString() : 'Never';
+                        option.textContent = `${pathConfig.path} (${pathConfig.total_files} files, indexed: ${lastIndexed})`;
+                        select.appendChild(option);
+                    });
+                });
+
+                async function changePath(path) {
+                    if (!path) return;
+                    
+                    const statusSpan = document.getElementById('indexStatus');
+                    statusSpan.textContent = 'Loading index for ' + path + '...';
+                    
+                    try {
+                        const response = await fetch(`${BASE_PATH}/change-path`, {
+                            method: 'POST',
+                            headers: {
+                                'Content-Type': 'application/json',
+                            },
+                            body: JSON.stringify({ path }),
+                        });
+                        const result = await response.json();
+                        statusSpan.textContent = `Loaded index with ${result.total_files} files`;
+                    } catch (err) {
+                        statusSpan.textContent = 'Error changing path: ' + err.message;
+                    }
+                }
+
+                async function clearRecentPaths() {
+                    if (!confirm('Are you sure you want to clear all recent paths?')) {
+                        return;
+                    }
+                    
+                    const statusSpan = document.getElementById('indexStatus');
+                    statusSpan.textContent = 'Clearing recent paths...';
+                    
+                    try {
+                        const response = await fetch(`${BASE_PATH}/clear-recent-paths`, {
+                            method: 'POST'
+                        });
+                        const result = await response.json();
+                        statusSpan.textContent = result;
+                        
+                        // Clear the recent paths from the dropdown, keeping the
+                        // "Select recent path..." placeholder and the bookmarks
+                        const select = document.getElementById('pathSelect');
+                        Array.from(select.children)
+                            .filter(el => el.tagName === 'OPTION' && el.value)
+                            .forEach(el => el.remove());
+                    } catch (err) {
+                        statusSpan.textContent = 'Error clearing recent paths: ' + err.message;
+                    }
+                }
+
+                async function purgeIndices() {
+                    if (!confirm('Are you sure you want to delete all saved indices?')) {
+                        return;
+                    }
+                    
+                    const statusSpan = document.getElementById('indexStatus');
+                    statusSpan.textContent = 'Purging all indices...';
+                    
+                    try {
+                        const response = await fetch(`${BASE_PATH}/purge-indices`, {
+                            method: 'POST'
+                        });
+                        const result = await response.json();
+                        statusSpan.textContent = result;
+                    } catch (err) {
+                        statusSpan.textContent = 'Error purging indices: ' + err.message;
                     }
                 }
 
-                // Initialize path display on load
-                window.addEventListener('load', async () => {
-                    try {
-                        // Get the current working directory and recent paths
-                        const [cwdResponse, pathsResponse] = await Promise.all([
-                            fetch('/current-path'),
-                            fetch('/recent-paths')
-                        ]);
-                        
-                        const currentPath = await cwdResponse.json();
-                        const paths = await pathsResponse.json();
-                        
-                        // Update the path display with current working directory
-                        updatePathDisplay(currentPath.path);
-                        
-                        // Update the select dropdown
-                        const pathSelect = document.getElementById('pathSelect');
-                        if (pathSelect) {
-                            pathSelect.value = currentPath.path;
-                        }
-                    } catch (err) {
-                        console.error('Error initializing path display:', err);
-                    }
-                });
-            </script>
-        </body>
-        </html>
-    "#)
+                async function createIndex() {
+                    const statusSpan = document.getElementById('indexStatus');
+                    statusSpan.textContent = 'Creating index...';
+                    
+                    try {
+                        const response = await fetch(`${BASE_PATH}/create-index`, {
+                            method: 'POST'
+                        });
+                        const status = await response.json();
+                        statusSpan.textContent = status.cancelled
+                            ? `Indexing cancelled, kept previous index (${status.total_files} files)`
+                            : `Indexed ${status.total_files} files`;
+                        if (status.skipped.count > 0) {
+                            statusSpan.textContent += `, skipped ${status.skipped.count}` +
+                                ` (${status.skipped.permission_denied} permission denied)`;
+                            statusSpan.title = status.skipped.examples.join('\n');
+                        }
+                        if (status.save_error) {
+                            statusSpan.textContent += ` (not saved to disk: ${status.save_error})`;
+                        }
+                    } catch (err) {
+                        statusSpan.textContent = 'Error creating index: ' + err.message;
+                    }
+                }
+
+                async function search() {
+                    const searchInput = document.getElementById('search');
+                    const resultsDiv = document.getElementById('results');
+                    const cancelBtn = document.getElementById('cancelBtn');
+                    
+                    if (currentController) {
+                        currentController.abort();
+                    }
+
+                    currentController = new AbortController();
+                    cancelBtn.style.display = 'inline';
+                    resultsDiv.textContent = 'Searching...';
+
+                    try {
+                        const allRoots = document.getElementById('allRoots').checked ? '&all_roots=true' : '';
+                        const response = await fetch(`${BASE_PATH}/search?q=${encodeURIComponent(searchInput.value)}${allRoots}`, {
+                            signal: currentController.signal
+                        });
+                        const data = await response.json();
+                        
+                        // Clear previous results
+                        resultsDiv.innerHTML = '';
+                        
+                        // Create links for each file
+                        // Only show first 25 results
+                        data.files.slice(0, 25).forEach(file => {
+                            if (file.kind === 'dir') {
+                                // Directories open as the new search root
+                                const root = file.root || document.getElementById('pathDisplay').textContent;
+                                const dirLink = document.createElement('a');
+                                dirLink.href = '#';
+                                dirLink.className = 'file-link';
+                                dirLink.textContent = `${FILE_TYPE_ICONS.directory} ${file.name}/`;
+                                dirLink.title = `${root}/${file.path}`;
+                                dirLink.onclick = (e) => {
+                                    e.preventDefault();
+                                    changePath(`${root.replace(/\/$/, '')}/${file.path}`);
+                                };
+                                resultsDiv.appendChild(dirLink);
+                                return;
+                            }
+                            const link = document.createElement('a');
+                            const params = new URLSearchParams();
+                            if (file.root) params.set('root', file.root);
+                            // Names that aren't valid UTF-8 are only found again by their raw bytes
+                            if (file.raw_path) params.set('raw', file.raw_path);
+                            link.href = `${BASE_PATH}/download/${encodeURIComponent(file.path)}` +
+                                (params.size ? `?${params}` : '');
+                            link.className = 'file-link';
+                            link.textContent = `${FILE_TYPE_ICONS[file.file_type] || FILE_TYPE_ICONS.other} ` +
+                                (file.lines == null ? file.name : `${file.name} (${file.lines} lines)`);
+                            link.title = file.root ? `${file.root}/${file.path}` : file.path; // Show full path on hover
+                            if (file.raw_path) {
+                                link.textContent += ' (name is not valid UTF-8)';
+                            }
+                            resultsDiv.appendChild(link);
+                            if (editorLinks && !file.root) {
+                                const editorBtn = document.createElement('button');
+                                editorBtn.textContent = 'Open in editor';
+                                editorBtn.onclick = () => openInEditor(file.path);
+                                resultsDiv.appendChild(editorBtn);
+                            }
+                        });
+                        
+                        if (data.index_size === 0) {
+                            resultsDiv.textContent = 'The index is empty. ';
+                            if (!readOnly) {
+                                const createBtn = document.createElement('button');
+                                createBtn.textContent = 'Create one?';
+                                createBtn.onclick = createIndex;
+                                resultsDiv.appendChild(createBtn);
+                            }
+                        } else if (data.files.length === 0) {
+                            resultsDiv.textContent = 'No files found';
+                            if (data.suggestions && data.suggestions.length > 0) {
+                                resultsDiv.appendChild(document.createElement('br'));
+                                resultsDiv.appendChild(document.createTextNode('Did you mean: '));
+                                data.suggestions.forEach(path => {
+                                    const suggestion = document.createElement('a');
+                                    suggestion.href = '#';
+                                    suggestion.className = 'file-link';
+                                    suggestion.textContent = path;
+                                    suggestion.onclick = (e) => {
+                                        e.preventDefault();
+                                        searchInput.value = path;
+                                        search();
+                                    };
+                                    resultsDiv.appendChild(suggestion);
+                                });
+                            }
+                        }
+                    } catch (err) {
+                        if (err.name === 'AbortError') {
+                            resultsDiv.textContent = 'Search cancelled';
+                        } else {
+                            resultsDiv.textContent = 'Error: ' + err.message;
+                        }
+                    } finally {
+                        currentController = null;
+                        cancelBtn.style.display = 'none';
+                    }
+                }
+
+                async function openInEditor(path) {
+                    const response = await fetch(`${BASE_PATH}/editor-link/${encodeURIComponent(path)}`);
+                    if (!response.ok) {
+                        alert(await response.text());
+                        return;
+                    }
+                    const data = await response.json();
+                    window.location.href = data.link;
+                }
+
+                function cancelSearch() {
+                    if (currentController) {
+                        currentController.abort();
+                    }
+                }
+
+                // Enable search on Enter key
+                document.getElementById('search').addEventListener('keypress', function(e) {
+                    if (e.key === 'Enter') {
+                        search();
+                    }
+                });
+                async function openDirectoryBrowser() {
+                    const currentPath = document.getElementById('pathDisplay').textContent || '/';
+                    
+                    // Create modal overlay
+                    const overlay = document.createElement('div');
+                    overlay.style.cssText = `
+                        position: fixed;
+                        top: 0;
+                        left: 0;
+                        width: 100%;
+                        height: 100%;
+                        background: rgba(0,0,0,0.5);
+                        z-index: 999;
+                    `;
+                    
+                    async function loadDirectory(path) {
+                        try {
+                            const response = await fetch(`${BASE_PATH}/list-directories/${encodeURIComponent(path)}`);
+                            const listing = await response.json();
+                            
+                            // Update current path display
+                            pathDisplay.textContent = path;
+                            
+                            // Clear and rebuild directory list
+                            dirList.innerHTML = '';
+                            
+                            const dirs = listing.parent ? [listing.parent, ...listing.dirs] : listing.dirs;
+                            dirs.forEach(dir => {
+                                const link = document.createElement('a');
+                                link.href = '#';
+                                const isParent = dir === listing.parent;
+                                // Windows paths use backslashes, and drive roots (`C:\`) have no trailing name
+                                const name = dir.split(/[\\/]/).filter(Boolean).pop() || dir;
+                                link.textContent = isParent ? '📁 ..' : `📁 ${name}`;
+                                link.style.cssText = `
+                                    display: block;
+                                    padding: 8px;
+                                    text-decoration: none;
+                                    color: #333;
+                                    border-bottom: 1px solid #eee;
+                                `;
+                                link.onmouseover = () => { link.style.backgroundColor = '#f0f0f0'; };
+                                link.onmouseout = () => { link.style.backgroundColor = 'transparent'; };
+                                link.onclick = async (e) => {
+                                    e.preventDefault();
+                                    await loadDirectory(dir);
+                                };
+                                dirList.appendChild(link);
+                            });
+                            if (listing.truncated) {
+                                const more = document.createElement('div');
+                                more.style.cssText = 'padding: 8px; color: #666;';
+                                more.textContent = `Showing ${listing.dirs.length} of ${listing.total} folders`;
+                                dirList.appendChild(more);
+                            }
+                        } catch (err) {
+                            console.error('Error listing directories:', err);
+                        }
+                    }
+                    
+                    // Create modal content
+                    const modal = document.createElement('div');
+                    modal.style.cssText = `
+                        position: fixed;
+                        top: 50%;
+                        left: 50%;
+                        transform: translate(-50%, -50%);
+                        background: white;
+                        padding: 20px;
+                        border-radius: 8px;
+                        box-shadow: 0 2px 10px rgba(0,0,0,0.1);
+                        width: 80%;
+                        max-width: 600px;
+                        max-height: 80vh;
+                        display: flex;
+                        flex-direction: column;
+                        z-index: 1000;
+                    `;
+                    
+                    // Create header with current path display
+                    const header = document.createElement('div');
+                    header.style.marginBottom = '10px';
+                    const pathDisplay = document.createElement('div');
+                    pathDisplay.style.cssText = `
+                        font-weight: bold;
+                        padding: 8px;
+                        background: #f5f5f5;
+                        border-radius: 4px;
+                        margin-bottom: 10px;
+                    `;
+                    header.appendChild(pathDisplay);
+                    
+                    // Create scrollable directory list
+                    const dirList = document.createElement('div');
+                    dirList.style.cssText = `
+                        overflow-y: auto;
+                        flex-grow: 1;
+                        border: 1px solid #eee;
+                        border-radius: 4px;
+                    `;
+                    
+                    // Create footer with buttons
+                    const footer = document.createElement('div');
+                    footer.style.cssText = `
+                        margin-top: 15px;
+                        display: flex;
+                        justify-content: flex-end;
+                        gap: 10px;
+                    `;
+                    
+                    const selectButton = document.createElement('button');
+                    selectButton.textContent = 'Select Directory';
+                    selectButton.onclick = () => {
+                        const selectedPath = pathDisplay.textContent;
+                        changePath(selectedPath);
+                        document.body.removeChild(overlay);
+                    };
+                    
+                    const cancelButton = document.createElement('button');
+                    cancelButton.textContent = 'Cancel';
+                    cancelButton.onclick = () => {
+                        document.body.removeChild(overlay);
+                    };
+                    
+                    footer.appendChild(cancelButton);
+                    footer.appendChild(selectButton);
+                    
+                    // Assemble modal
+                    modal.appendChild(header);
+                    modal.appendChild(dirList);
+                    modal.appendChild(footer);
+                    
+                    // Add modal to overlay
+                    overlay.appendChild(modal);
+                    document.body.appendChild(overlay);
+                    
+                    // Load initial directory
+                    await loadDirectory(currentPath);
+                }
+
+                // Update path display when path changes
+                function updatePathDisplay(path) {
+                    document.getElementById('pathDisplay').textContent = path;
+                }
+
+                // Modify existing changePath function
+                async function changePath(path) {
+                    if (!path) return;
+                    
+                    const statusSpan = document.getElementById('indexStatus');
+                    if (statusSpan) {
+                        statusSpan.textContent = 'Changed to: ' + path;
+                    }
+                    updatePathDisplay(path);
+                    
+                    try {
+                        const response = await fetch(`${BASE_PATH}/change-path`, {
+                            method: 'POST',
+                            headers: {
+                                'Content-Type': 'application/json',
+                            },
+                            body: JSON.stringify({ path }),
+                        });
+                        const result = await response.json();
+                        if (statusSpan) {
+                            statusSpan.textContent = `Changed to ${path} (${result.total_files} files indexed)`;
+                        }
+                    } catch (err) {
+                        if (statusSpan) {
+                            statusSpan.textContent = 'Error changing path: ' + err.message;
+                        }
+                        console.error('Error changing path:', err);
+                    }
+                }
+
+                // Initialize path display on load
+                window.addEventListener('load', async () => {
+                    try {
+                        // Get the current working directory and recent paths
+                        const [cwdResponse, pathsResponse] = await Promise.all([
+                            fetch(`${BASE_PATH}/current-path`),
+                            fetch(`${BASE_PATH}/recent-paths`)
+                        ]);
+                        
+                        const currentPath = await cwdResponse.json();
+                        const paths = await pathsResponse.json();
+                        
+                        // Update the path display with current working directory
+                        updatePathDisplay(currentPath.path);
+                        if (currentPath.read_only) {
+                            readOnly = true;
+                            document.querySelector('.controls').style.display = 'none';
+                        }
+                        editorLinks = currentPath.editor_link;
+                        
+                        // Update the select dropdown
+                        const pathSelect = document.getElementById('pathSelect');
+                        if (pathSelect) {
+                            pathSelect.value = currentPath.path;
+                        }
+                    } catch (err) {
+                        console.error('Error initializing path display:', err);
+                    }
+                });
+            </script>
+        </body>
+        </html>
+    "#
+    .replace("{{app_title}}", &title)
+    .replace("{{banner}}", &banner)
+    .replace("{{base_path}}", &state.args.base_path)
+    .replace("{{file_type_icons}}", &file_type_icons_json()))
+}
+
+/// `FileType::icon` of every file type as a JSON object, for the page's script.
+fn file_type_icons_json() -> String {
+    let icons: serde_json::Map<String, serde_json::Value> = FileType::ALL.iter()
+        .filter_map(|file_type| {
+            let name = serde_json::to_value(file_type).ok()?.as_str()?.to_string();
+            Some((name, file_type.icon().into()))
+        })
+        .collect();
+    serde_json::Value::Object(icons).to_string()
+}
+
+/// The app's own index and config directories, if they live under `root`,
+/// expressed relative to `root` as given so they compare against walked paths.
+fn app_data_dirs_under(root: &std::path::Path) -> Vec<PathBuf> {
+    let config_dir = get_config_path().ok()
+        .and_then(|path| path.parent().map(PathBuf::from));
+    let canonical_root = root.canonicalize().ok();
+
+    let data_dir = get_query_log_path().ok()
+        .and_then(|path| path.parent().map(PathBuf::from));
+
+    [get_index_dir().ok(), config_dir, data_dir]
+        .into_iter()
+        .flatten()
+        .filter_map(|dir| {
+            if dir.starts_with(root) {
+                return Some(dir);
+            }
+            let canonical_dir = dir.canonicalize().ok()?;
+            let relative = canonical_dir.strip_prefix(canonical_root.as_ref()?).ok()?;
+            Some(root.join(relative))
+        })
+        .collect()
+}
+
+/// Separator between an archive's path and a member inside it, e.g.
+/// `docs.zip!/inner/file.txt`.
+const ARCHIVE_SEPARATOR: &str = "!/";
+
+#[derive(Clone, Copy)]
+struct ArchiveLimits {
+    max_size: u64,
+    max_depth: usize,
+}
+
+#[derive(Clone, Copy)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file stored inside an archive.
+struct ArchiveMember {
+    path: String,
+    size: u64,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+fn zip_datetime(dt: zip::DateTime) -> Option<DateTime<Utc>> {
+    chrono::NaiveDate::from_ymd_opt(dt.year() as i32, dt.month() as u32, dt.day() as u32)?
+        .and_hms_opt(dt.hour() as u32, dt.minute() as u32, dt.second() as u32)
+        .map(|naive| naive.and_utc())
+}
+
+/// Decompressed tar stream, capped so a gzip bomb can't run forever.
+fn tar_reader<'a>(data: &'a [u8], kind: ArchiveKind, limits: &ArchiveLimits) -> Box<dyn io::Read + 'a> {
+    use io::Read;
+
+    match kind {
+        ArchiveKind::TarGz => Box::new(flate2::read::GzDecoder::new(data).take(limits.max_size)),
+        _ => Box::new(data),
+    }
+}
+
+fn list_archive_members(data: &[u8], kind: ArchiveKind, limits: &ArchiveLimits) -> io::Result<Vec<ArchiveMember>> {
+    let mut members = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(io::Cursor::new(data)).map_err(io::Error::other)?;
+            for i in 0..archive.len() {
+                let member = archive.by_index_raw(i).map_err(io::Error::other)?;
+                if member.is_dir() {
+                    continue;
+                }
+                if member.encrypted() {
+                    println!("Skipping encrypted archive member: {}", member.name());
+                    continue;
+                }
+                members.push(ArchiveMember {
+                    path: member.name().to_string(),
+                    size: member.size(),
+                    last_modified: member.last_modified().and_then(zip_datetime),
+                });
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut archive = tar::Archive::new(tar_reader(data, kind, limits));
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                members.push(ArchiveMember {
+                    path: entry.path()?.to_string_lossy().to_string(),
+                    size: entry.size(),
+                    last_modified: entry.header().mtime().ok()
+                        .and_then(|secs| DateTime::from_timestamp(secs as i64, 0)),
+                });
+            }
+        }
+    }
+    Ok(members)
+}
+
+/// Reads one member of an archive into memory, refusing anything over the size limit.
+fn read_archive_member(data: &[u8], kind: ArchiveKind, member_path: &str, limits: &ArchiveLimits) -> io::Result<Vec<u8>> {
+    use io::Read;
+
+    let too_large = || io::Error::other(format!("{} exceeds the archive size limit", member_path));
+    let mut contents = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(io::Cursor::new(data)).map_err(io::Error::other)?;
+            let member = archive.by_name(member_path).map_err(io::Error::other)?;
+            if member.size() > limits.max_size {
+                return Err(too_large());
+            }
+            member.take(limits.max_size + 1).read_to_end(&mut contents)?;
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut archive = tar::Archive::new(tar_reader(data, kind, limits));
+            let mut found = false;
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if entry.path()?.to_string_lossy() == member_path {
+                    entry.take(limits.max_size + 1).read_to_end(&mut contents)?;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found in archive", member_path)));
+            }
+        }
+    }
+    if contents.len() as u64 > limits.max_size {
+        return Err(too_large());
+    }
+    Ok(contents)
+}
+
+/// Adds a virtual entry for every file inside an archive, descending into
+/// nested archives until `depth` reaches the configured limit.
+fn index_archive(
+    data: &[u8],
+    kind: ArchiveKind,
+    prefix: &str,
+    fallback_modified: DateTime<Utc>,
+    limits: &ArchiveLimits,
+    depth: usize,
+    entries: &mut Vec<IndexEntry>,
+) -> io::Result<()> {
+    for member in list_archive_members(data, kind, limits)? {
+        let path = format!("{}{}", prefix, member.path);
+        let last_modified = member.last_modified.unwrap_or(fallback_modified);
+        println!("Indexing archive member: {}", path);
+
+        if let Some(nested_kind) = ArchiveKind::from_name(&member.path) {
+            if depth < limits.max_depth && member.size <= limits.max_size {
+                let nested = read_archive_member(data, kind, &member.path, limits).and_then(|nested| {
+                    let nested_prefix = format!("{}{}", path, ARCHIVE_SEPARATOR);
+                    index_archive(&nested, nested_kind, &nested_prefix, last_modified, limits, depth + 1, entries)
+                });
+                if let Err(e) = nested {
+                    println!("Could not index nested archive {}: {}", path, e);
+                }
+            }
+        }
+
+        entries.push(IndexEntry {
+            name: member.path.rsplit('/').next().unwrap_or(&member.path).to_string(),
+            path,
+            last_modified,
+            size: member.size,
+            kind: EntryKind::File,
+            root: None,
+            id: None,
+            absolute_path: None,
+            file_type: None,
+            owner: None,
+            raw_path: None,
+            lines: None,
+            content_hash: None,
+            romanized: None,
+            tags: Vec::new(),
+            is_symlink: false,
+            link_target: None,
+            folded: Default::default(),
+        });
+    }
+    Ok(())
+}
+
+/// Extracts `inner` (which may itself cross nested archives) from the archive at `archive_path`.
+fn extract_from_archive(archive_path: &std::path::Path, inner: &str, limits: &ArchiveLimits) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Not a supported archive");
+    let mut kind = ArchiveKind::from_name(&archive_path.to_string_lossy()).ok_or_else(invalid)?;
+    if fs::metadata(archive_path)?.len() > limits.max_size {
+        return Err(io::Error::other("Archive exceeds the archive size limit"));
+    }
+
+    let mut data = fs::read(archive_path)?;
+    let mut remaining = inner;
+    for depth in 1.. {
+        match remaining.split_once(ARCHIVE_SEPARATOR) {
+            Some((member, rest)) if depth < limits.max_depth => {
+                data = read_archive_member(&data, kind, member, limits)?;
+                kind = ArchiveKind::from_name(member).ok_or_else(invalid)?;
+                remaining = rest;
+            }
+            _ => break,
+        }
+    }
+    read_archive_member(&data, kind, remaining, limits)
+}
+
+/// Settings that shape what a directory walk records, derived from the CLI
+/// and the per-path settings in the config.
+#[derive(Clone, Default)]
+struct WalkOptions {
+    /// Look inside zip/tar archives when set
+    archives: Option<ArchiveLimits>,
+    /// Record directories as well as files
+    directories: bool,
+    /// Record file owners, on Unix
+    owners: bool,
+    /// Record symlinks rather than skipping them
+    symlinks: bool,
+    /// Count lines of text files up to this many bytes
+    line_counts: Option<u64>,
+    /// Hash the contents of files up to this many bytes
+    content_hashes: Option<u64>,
+    hash_algorithm: HashAlgorithm,
+    /// Bumped for every file recorded, for live progress reporting
+    progress: Option<Arc<std::sync::atomic::AtomicUsize>>,
+    /// Resume from the root's `IndexCheckpoint` and save a new one this often
+    checkpoint: Option<std::time::Duration>,
+    /// Record `IndexEntry::romanized`
+    transliterate: bool,
+    /// Stay on the filesystem of the walk's root
+    one_file_system: bool,
+    max_file_size: Option<u64>,
+    min_file_size: Option<u64>,
+}
+
+impl WalkOptions {
+    fn from_args(args: &Args) -> Self {
+        WalkOptions {
+            archives: args.index_archives.then_some(ArchiveLimits {
+                max_size: args.max_archive_size,
+                max_depth: args.max_archive_depth,
+            }),
+            directories: args.index_directories,
+            owners: args.index_owners,
+            symlinks: args.index_symlinks,
+            line_counts: args.index_line_counts.then_some(args.max_line_count_size),
+            content_hashes: args.index_content_hashes.then_some(args.max_content_hash_size),
+            hash_algorithm: args.content_hash_algorithm,
+            ..WalkOptions::default()
+        }
+    }
+
+    /// Options for walking `root`, including any settings saved for that path.
+    async fn for_root(state: &AppState, root: &std::path::Path) -> Self {
+        let mut options = WalkOptions::from_args(&state.args);
+        if let Some(path_config) = state.config.read().await.path_config(&root.to_string_lossy()) {
+            options.max_file_size = path_config.max_file_size;
+            options.min_file_size = path_config.min_file_size;
+            options.transliterate = path_config.transliterate;
+            options.one_file_system = path_config.one_file_system;
+        }
+        options
+    }
+
+    fn size_allowed(&self, size: u64) -> bool {
+        self.max_file_size.is_none_or(|max| size <= max)
+            && self.min_file_size.is_none_or(|min| size >= min)
+    }
+}
+
+/// Counts the lines of the text file at `path`, the last one whether or not
+/// it ends in a newline. Files over `max_bytes`, unreadable ones, and binary
+/// ones (containing a NUL byte) give `None`.
+fn count_lines(path: &std::path::Path, size: u64, max_bytes: u64) -> Option<u64> {
+    use std::io::Read;
+
+    if size > max_bytes {
+        return None;
+    }
+    let mut file = fs::File::open(path).ok()?.take(max_bytes);
+    let mut buffer = [0u8; 64 * 1024];
+    let mut lines = 0;
+    let mut last = None;
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        };
+        let chunk = &buffer[..read];
+        if chunk.contains(&0) {
+            return None;
+        }
+        lines += chunk.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        last = chunk.last().copied();
+    }
+    Some(lines + u64::from(last.is_some_and(|byte| byte != b'\n')))
+}
+
+/// Hash of the contents of the file at `path` in the form stored in
+/// `IndexEntry::content_hash`. Files over `max_bytes` and unreadable ones
+/// give `None`.
+fn content_hash(path: &std::path::Path, size: u64, max_bytes: u64, algorithm: HashAlgorithm) -> Option<String> {
+    use sha2::Digest;
+    use std::io::Read;
+
+    if size > max_bytes {
+        return None;
+    }
+    let file = fs::File::open(path).ok()?.take(max_bytes);
+    match algorithm {
+        HashAlgorithm::Md5 => {
+            let mut context = md5::Context::new();
+            read_chunks(file, |chunk| context.consume(chunk))?;
+            Some(format!("{:x}", context.compute()))
+        }
+        HashAlgorithm::Sha256 => {
+            let mut hasher = sha2::Sha256::new();
+            read_chunks(file, |chunk| hasher.update(chunk))?;
+            Some(format!("sha256:{:x}", hasher.finalize()))
+        }
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(file, |chunk| {
+                hasher.update(chunk);
+            })?;
+            Some(format!("blake3:{}", hasher.finalize().to_hex()))
+        }
+    }
+}
+
+/// Feeds everything `reader` yields to `consume`; `None` on a read error.
+fn read_chunks(mut reader: impl io::Read, mut consume: impl FnMut(&[u8])) -> Option<()> {
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Some(()),
+            Ok(read) => consume(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Identifier of the filesystem holding a file, for telling mount points
+/// apart. Only available on Unix.
+fn device_id(metadata: &fs::Metadata) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// What a directory walk found, plus what it couldn't read.
+#[derive(Default)]
+struct DirectoryWalk {
+    entries: Vec<IndexEntry>,
+    skipped: SkippedEntries,
+}
+
+/// Walks `root` and builds an index entry for every regular file beneath it,
+/// and every directory too if `options.directories` is set.
+/// Unreadable entries are counted and skipped rather than ending the walk.
+/// Stops early, returning what it has so far, once `cancel` is triggered.
+/// With `options.checkpoint`, picks up from the root's `IndexCheckpoint` and
+/// keeps it up to date, including when cancelled.
+/// With `options.one_file_system`, skips directories on another filesystem
+/// than `root`, such as mounted network shares.
+fn walk_directory(root: &std::path::Path, options: &WalkOptions, cancel: &CancellationToken) -> DirectoryWalk {
+    // Never index the app's own state if it happens to sit inside the root
+    let excluded = app_data_dirs_under(root);
+    for dir in &excluded {
+        println!("Excluding app data directory: {}", dir.display());
+    }
+
+    let mut checkpoint = options.checkpoint.map(|_| IndexCheckpoint::load(root).unwrap_or_default());
+    let mut entries = checkpoint.as_mut()
+        .map(|checkpoint| std::mem::take(&mut checkpoint.entries))
+        .unwrap_or_default();
+    let resumed_dirs = checkpoint.as_ref()
+        .map(|checkpoint| checkpoint.completed_dirs.clone())
+        .unwrap_or_default();
+    if !resumed_dirs.is_empty() {
+        println!(
+            "Resuming from checkpoint: {} entries in {} completed directories",
+            entries.len(), resumed_dirs.len()
+        );
+    }
+    // Directories entered but not yet left, innermost last
+    let mut open_dirs: Vec<String> = Vec::new();
+    let mut last_checkpoint = std::time::Instant::now();
+
+    let root_device = match options.one_file_system {
+        true => fs::metadata(root).ok().as_ref().and_then(device_id),
+        false => None,
+    };
+
+    let mut skipped = SkippedEntries::default();
+    let owners = options.owners.then(OwnerLookup::default);
+    for entry in WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| {
+            let resumed = !resumed_dirs.is_empty()
+                && resumed_dirs.contains(&slash_path(e.path().strip_prefix(root).unwrap_or(e.path())));
+            let other_device = root_device.is_some()
+                && e.file_type().is_dir()
+                && e.metadata().ok().as_ref().and_then(device_id) != root_device;
+            if other_device {
+                println!("Not crossing into another filesystem: {}", e.path().display());
+            }
+            !resumed && !other_device && !excluded.iter().any(|dir| e.path().starts_with(dir))
+        })
+    {
+        if let (Some(checkpoint), Some(interval)) = (checkpoint.as_mut(), options.checkpoint) {
+            if cancel.is_cancelled() || last_checkpoint.elapsed() >= interval {
+                checkpoint.entries = std::mem::take(&mut entries);
+                if let Err(e) = checkpoint.save(root) {
+                    println!("Error saving index checkpoint: {}", e);
+                }
+                entries = std::mem::take(&mut checkpoint.entries);
+                last_checkpoint = std::time::Instant::now();
+            }
+        }
+        if cancel.is_cancelled() {
+            println!("Directory walk cancelled after {} files", entries.len());
+            break;
+        }
+
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                println!("Skipping unreadable entry: {}", e);
+                skipped.record(e.path(), e.io_error());
+                continue;
+            }
+        };
+        if let Some(checkpoint) = checkpoint.as_mut().filter(|_| entry.depth() > 0) {
+            // Entries arrive depth-first, so leaving a directory means it is done
+            let relative = slash_path(entry.path().strip_prefix(root).unwrap_or(entry.path()));
+            while let Some(dir) = open_dirs.pop_if(|dir| !relative.starts_with(&format!("{}/", dir))) {
+                checkpoint.complete(dir);
+            }
+            if entry.file_type().is_dir() {
+                open_dirs.push(relative);
+            }
+        }
+        if entry.file_type().is_dir() && options.directories && entry.depth() > 0 {
+            let metadata = entry.metadata().ok();
+            let last_modified: DateTime<Utc> = metadata.as_ref()
+                .and_then(|metadata| metadata.modified().ok())
+                .unwrap_or_else(std::time::SystemTime::now)
+                .into();
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            entries.push(IndexEntry {
+                path: slash_path(relative),
+                raw_path: raw_path(relative),
+                name: entry.file_name().to_string_lossy().to_string(),
+                last_modified,
+                size: 0,
+                kind: EntryKind::Dir,
+                root: None,
+                id: None,
+                absolute_path: None,
+                file_type: None,
+                owner: owners.as_ref().zip(metadata.as_ref()).and_then(|(owners, metadata)| owners.owner(metadata)),
+                lines: None,
+                content_hash: None,
+                romanized: None,
+                tags: Vec::new(),
+                is_symlink: false,
+                link_target: None,
+                folded: Default::default(),
+            });
+        }
+        if entry.path_is_symlink() && options.symlinks && entry.depth() > 0 {
+            if let Some(link) = symlink_entry(root, entry.path(), options) {
+                entries.push(link);
+            }
+            continue;
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                println!("Skipping unreadable file {}: {}", entry.path().display(), e);
+                skipped.record(Some(entry.path()), e.io_error());
+                continue;
+            }
+        };
+        if !options.size_allowed(metadata.len()) {
+            continue;
+        }
+
+        let full_path = entry.path();
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let path = slash_path(relative);
+        let raw_path = raw_path(relative);
+        if raw_path.is_some() {
+            println!("File name is not valid UTF-8, indexing as: {}", path);
+        }
+        
+        println!("Indexing file: {} (relative path: {})", full_path.display(), path);
+
+        let last_modified: DateTime<Utc> = metadata.modified()
+            .unwrap_or_else(|_| std::time::SystemTime::now())
+            .into();
+
+        if let Some(limits) = &options.archives {
+            if let Some(kind) = ArchiveKind::from_name(&path) {
+                if metadata.len() <= limits.max_size {
+                    let prefix = format!("{}{}", path, ARCHIVE_SEPARATOR);
+                    let indexed = fs::read(full_path).and_then(|data| {
+                        index_archive(&data, kind, &prefix, last_modified, limits, 1, &mut entries)
+                    });
+                    if let Err(e) = indexed {
+                        println!("Could not index archive {}: {}", full_path.display(), e);
+                    }
+                } else {
+                    println!("Skipping archive over size limit: {}", full_path.display());
+                }
+            }
+        }
+        
+        entries.push(IndexEntry {
+            path: path.clone(),
+            name: entry.file_name().to_string_lossy().to_string(),
+            last_modified,
+            size: metadata.len(),
+            kind: EntryKind::File,
+            root: None,
+            id: None,
+            absolute_path: None,
+            file_type: None,
+            owner: owners.as_ref().and_then(|owners| owners.owner(&metadata)),
+            raw_path,
+            lines: options.line_counts.and_then(|max_bytes| count_lines(full_path, metadata.len(), max_bytes)),
+            content_hash: options.content_hashes
+                .and_then(|max_bytes| content_hash(full_path, metadata.len(), max_bytes, options.hash_algorithm)),
+            romanized: None,
+            tags: Vec::new(),
+            is_symlink: false,
+            link_target: None,
+            folded: Default::default(),
+        });
+        if let Some(progress) = &options.progress {
+            progress.store(entries.len(), std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+    if skipped.count > 0 {
+        println!("Skipped {} unreadable entries ({} permission denied)", skipped.count, skipped.permission_denied);
+    }
+    if options.transliterate {
+        for entry in entries.iter_mut().filter(|entry| entry.romanized.is_none()) {
+            entry.romanized = romanize(&entry.path);
+        }
+    }
+    entries.iter().for_each(|entry| { entry.folded(); });
+    DirectoryWalk { entries, skipped }
+}
+
+/// Entry for the symlink at `path`, without following it further than
+/// reading the target's kind and size. A dangling link is recorded as an
+/// empty file; a file target outside the size bounds leaves it out.
+fn symlink_entry(root: &std::path::Path, path: &std::path::Path, options: &WalkOptions) -> Option<IndexEntry> {
+    let link_metadata = fs::symlink_metadata(path).ok();
+    let target_metadata = fs::metadata(path).ok();
+    let is_dir = target_metadata.as_ref().is_some_and(|metadata| metadata.is_dir());
+    let size = target_metadata.as_ref().filter(|metadata| metadata.is_file()).map_or(0, |metadata| metadata.len());
+    if !is_dir && target_metadata.is_some() && !options.size_allowed(size) {
+        return None;
+    }
+    let link_target = match fs::read_link(path) {
+        Ok(target) => Some(target.to_string_lossy().to_string()),
+        Err(e) => {
+            println!("Could not read symlink {}: {}", path.display(), e);
+            None
+        }
+    };
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    println!("Indexing symlink: {} -> {}", path.display(), link_target.as_deref().unwrap_or("?"));
+
+    Some(IndexEntry {
+        path: slash_path(relative),
+        raw_path: raw_path(relative),
+        name: path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+        last_modified: link_metadata.as_ref()
+            .and_then(|metadata| metadata.modified().ok())
+            .unwrap_or_else(std::time::SystemTime::now)
+            .into(),
+        size,
+        kind: if is_dir { EntryKind::Dir } else { EntryKind::File },
+        root: None,
+        id: None,
+        absolute_path: None,
+        file_type: None,
+        owner: None,
+        lines: None,
+        content_hash: None,
+        romanized: None,
+        tags: Vec::new(),
+        is_symlink: true,
+        link_target,
+        folded: Default::default(),
+    })
+}
+
+/// ASCII rendering of `path` for `IndexEntry::romanized`, or `None` if it is
+/// ASCII already.
+fn romanize(path: &str) -> Option<String> {
+    (!path.is_ascii()).then(|| deunicode::deunicode(path))
+}
+
+/// Runs `walk_directory` on the blocking pool so a large tree doesn't stall
+/// the async workers serving other requests.
+async fn walk_directory_blocking(root: PathBuf, options: WalkOptions, cancel: CancellationToken) -> DirectoryWalk {
+    tokio::task::spawn_blocking(move || walk_directory(&root, &options, &cancel))
+        .await
+        .unwrap_or_else(|e| {
+            println!("Directory walk failed: {}", e);
+            DirectoryWalk::default()
+        })
+}
+
+/// Saves a snapshot of the tags on the blocking pool, so callers can release
+/// the tags lock before any file I/O.
+async fn save_tags_blocking(tags: FileTags) -> io::Result<()> {
+    tokio::task::spawn_blocking(move || tags.save())
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+/// Runs `IndexEntry::save_index` on the blocking pool: serializing a large
+/// index takes a while, and retries sleep between attempts.
+async fn save_index_blocking(entries: Vec<IndexEntry>, root: PathBuf, options: SaveOptions) -> io::Result<()> {
+    tokio::task::spawn_blocking(move || IndexEntry::save_index(&entries, &root, &options))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct CreateIndexQuery {
+    /// Skip files larger than this many bytes; zero removes the limit. Saved for the path.
+    max_file_size: Option<u64>,
+    /// Skip files smaller than this many bytes; zero removes the limit. Saved for the path.
+    min_file_size: Option<u64>,
+    /// Also index romanized forms of non-ASCII paths. Saved for the path.
+    transliterate: Option<bool>,
+    /// Don't descend into other filesystems mounted under the directory;
+    /// Unix only. Saved for the path.
+    one_file_system: Option<bool>,
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/create-index", params(CreateIndexQuery), responses((status = 200, body = IndexStatus))))]
+async fn create_index(
+    Query(query): Query<CreateIndexQuery>,
+    State(state): State<AppState>,
+) -> Json<IndexStatus> {
+    println!("\n=== Creating Index ===");
+    let user_selected_dir = state.user_selected_dir.read().await.clone();
+    println!("Creating index for directory: {}", user_selected_dir.display());
+
+    // Remember size limits, transliteration and mount handling for this path
+    // so later runs apply them too
+    if query.max_file_size.is_some()
+        || query.min_file_size.is_some()
+        || query.transliterate.is_some()
+        || query.one_file_system.is_some()
+    {
+        let mut config = state.config.write().await;
+        let root_path = user_selected_dir.to_string_lossy().to_string();
+        if config.path_config(&root_path).is_none() {
+            config.add_path(root_path.clone(), 0);
+        }
+        // Bookmarks keep their own copy of the settings, which must change too
+        for path_config in config.path_configs_mut(&root_path) {
+            if let Some(max) = query.max_file_size {
+                path_config.max_file_size = (max > 0).then_some(max);
+            }
+            if let Some(min) = query.min_file_size {
+                path_config.min_file_size = (min > 0).then_some(min);
+            }
+            if let Some(transliterate) = query.transliterate {
+                path_config.transliterate = transliterate;
+            }
+            if let Some(one_file_system) = query.one_file_system {
+                path_config.one_file_system = one_file_system;
+            }
+        }
+        if let Err(e) = config.save() {
+            println!("Error saving config: {}", e);
+        }
+    }
+
+    Json(run_index(&state, user_selected_dir, Arc::new(CancellationToken::new()), None).await)
+}
+
+/// Rebuilds and saves the index for `user_selected_dir`, registering `cancel`
+/// as the active run so `/cancel-index` can stop it. When given, `progress`
+/// counts files as the walk records them.
+async fn run_index(
+    state: &AppState,
+    user_selected_dir: PathBuf,
+    cancel: Arc<CancellationToken>,
+    progress: Option<Arc<std::sync::atomic::AtomicUsize>>,
+) -> IndexStatus {
+    let start_time = std::time::Instant::now();
+    
+    // A new run supersedes one still in progress, such as a scheduled reindex
+    if let Some(previous) = state.index_cancel.write().await.replace(cancel.clone()) {
+        previous.cancel();
+    }
+
+    let mut options = WalkOptions::for_root(state, &user_selected_dir).await;
+    options.progress = progress;
+    options.checkpoint = (state.args.index_checkpoint_secs > 0)
+        .then(|| std::time::Duration::from_secs(state.args.index_checkpoint_secs));
+    let DirectoryWalk { entries: mut new_index, skipped } = walk_directory_blocking(
+        user_selected_dir.clone(),
+        options,
+        (*cancel).clone(),
+    ).await;
+    state.tags.read().await.apply(&user_selected_dir.to_string_lossy(), &mut new_index);
+
+    // Only clear the token if a newer run hasn't replaced it
+    {
+        let mut index_cancel = state.index_cancel.write().await;
+        if index_cancel.as_ref().is_some_and(|token| Arc::ptr_eq(token, &cancel)) {
+            *index_cancel = None;
+        }
+    }
+
+    if cancel.is_cancelled() {
+        println!("Indexing cancelled; keeping the previous index");
+        let root_path = user_selected_dir.to_string_lossy().to_string();
+        return IndexStatus {
+            total_files: state.indices.read().await.get(&root_path).map(|idx| idx.len()).unwrap_or(0),
+            last_updated: Utc::now(),
+            root_path,
+            cancelled: true,
+            skipped,
+            save_error: None,
+        };
+    }
+
+    // Update the indices map with the new index
+    let previous_index = {
+        let mut indices = state.indices.write().await;
+        let previous_index = indices.insert(user_selected_dir.to_string_lossy().to_string(), new_index.clone());
+        println!("Index updated with {} files", new_index.len());
+        println!("Indexing completed in {:.2?}", start_time.elapsed());
+        previous_index
+    };
+    state.search_cache.write().await.invalidate_root(&user_selected_dir.to_string_lossy());
+    // A first index has nothing to compare against, so everything would look new
+    if let Some(previous_index) = previous_index.filter(|previous| !previous.is_empty()) {
+        let known: std::collections::HashSet<&str> = previous_index.iter().map(|entry| entry.path.as_str()).collect();
+        let added = new_index.iter()
+            .filter(|entry| !known.contains(entry.path.as_str()))
+            .cloned()
+            .collect();
+        record_new_files(state, &user_selected_dir.to_string_lossy(), added).await;
+    }
+    unload_least_recently_used(state).await;
+    metrics::counter!("index_runs_total").increment(1);
+    metrics::histogram!("index_duration_seconds").record(start_time.elapsed().as_secs_f64());
+
+    // Record the run so the reindex schedule counts from now
+    {
+        let mut config = state.config.write().await;
+        config.mark_indexed(&user_selected_dir.to_string_lossy(), new_index.len());
+        if let Err(e) = config.save() {
+            println!("Error saving config: {}", e);
+        }
+    }
+
+    // Save the index to disk
+    let total_files = new_index.len();
+    let save_options = SaveOptions::new(&state.args, &*state.config.read().await);
+    let save_error = match save_index_blocking(new_index, user_selected_dir.clone(), save_options).await {
+        Ok(()) => {
+            println!("Index saved successfully");
+            None
+        }
+        Err(e) => {
+            println!("Error saving index: {}", e);
+            metrics::counter!("errors_total", "kind" => "index_save").increment(1);
+            Some(e.to_string())
+        }
+    };
+
+    IndexStatus {
+        total_files,
+        last_updated: Utc::now(),
+        root_path: user_selected_dir.to_string_lossy().to_string(),
+        cancelled: false,
+        skipped,
+        save_error,
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct VerifyQuery {
+    /// Number of example paths to include per category
+    #[serde(default)]
+    examples: usize,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize, Default)]
+struct VerifyCategory {
+    count: usize,
+    examples: Vec<String>,
+}
+
+impl VerifyCategory {
+    fn record(&mut self, path: &str, limit: usize) {
+        self.count += 1;
+        if self.examples.len() < limit {
+            self.examples.push(path.to_string());
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct VerifyReport {
+    root_path: String,
+    indexed_files: usize,
+    files_on_disk: usize,
+    /// Indexed entries whose file no longer exists
+    missing: VerifyCategory,
+    /// Files on disk that the index doesn't know about
+    unindexed: VerifyCategory,
+    /// Entries whose size or modification time no longer match
+    changed: VerifyCategory,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct ReindexSubtreeRequest {
+    /// Subdirectory relative to the selected directory
+    path: String,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct ReindexSubtreeResult {
+    subtree: String,
+    added: usize,
+    removed: usize,
+    updated: usize,
+    total_files: usize,
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/reindex-subtree", request_body = ReindexSubtreeRequest, responses((status = 200, body = ReindexSubtreeResult), (status = 403), (status = 404))))]
+async fn reindex_subtree(
+    State(state): State<AppState>,
+    Json(req): Json<ReindexSubtreeRequest>,
+) -> Response {
+    println!("\n=== Reindexing Subtree ===");
+    let user_selected_dir = state.user_selected_dir.read().await.clone();
+    let subtree_path = match resolve_in_root(&user_selected_dir, &req.path) {
+        Ok(subtree_path) => subtree_path,
+        Err(rejection) => return rejection.into_response(),
+    };
+    if !subtree_path.is_dir() {
+        println!("Rejected: Path {} is not a directory", subtree_path.display());
+        return Response::builder()
+            .status(404)
+            .body(Body::from("Not a directory"))
+            .unwrap();
+    }
+
+    match merge_subtree(&state, user_selected_dir, subtree_path, &CancellationToken::new()).await {
+        Some(result) => Json(result).into_response(),
+        None => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+/// Walks `subtree_path` and swaps its entries in the index of
+/// `user_selected_dir` for what it found, keeping the rest of the index and
+/// serving it throughout. Returns `None`, leaving the index alone, if
+/// `cancel` fires during the walk.
+async fn merge_subtree(
+    state: &AppState,
+    user_selected_dir: PathBuf,
+    subtree_path: PathBuf,
+    cancel: &CancellationToken,
+) -> Option<ReindexSubtreeResult> {
+    let prefix = subtree_path.strip_prefix(&user_selected_dir)
+        .unwrap_or(&subtree_path)
+        .to_path_buf();
+    println!("Reindexing subtree: {}", subtree_path.display());
+    let start_time = std::time::Instant::now();
+
+    // Walk the subtree, then make its paths relative to the root again
+    let options = WalkOptions::for_root(state, &user_selected_dir).await;
+    let transliterate = options.transliterate;
+    let walk = walk_directory_blocking(subtree_path, options, cancel.clone()).await;
+    if cancel.is_cancelled() {
+        println!("Subtree reindex cancelled; keeping the previous index");
+        return None;
+    }
+    let mut fresh: HashMap<String, IndexEntry> = walk.entries
+        .into_iter()
+        .map(|mut entry| {
+            entry.path = slash_path(&prefix.join(&entry.path));
+            if transliterate {
+                entry.romanized = romanize(&entry.path);
+            }
+            (entry.path.clone(), entry)
+        })
+        .collect();
+
+    let root_path = user_selected_dir.to_string_lossy().to_string();
+    let tags = state.tags.read().await;
+    for entry in fresh.values_mut() {
+        entry.tags = tags.get(&root_path, &entry.path);
+    }
+    drop(tags);
+
+    let mut result = ReindexSubtreeResult {
+        subtree: slash_path(&prefix),
+        added: 0,
+        removed: 0,
+        updated: 0,
+        total_files: 0,
+    };
+
+    let mut added = Vec::new();
+    let updated_index = {
+        let mut indices = state.indices.write().await;
+        let index = indices.entry(root_path.clone()).or_default();
+
+        let mut previous = HashMap::new();
+        index.retain(|entry| {
+            if std::path::Path::new(&entry.path).starts_with(&prefix) {
+                previous.insert(entry.path.clone(), (entry.size, entry.last_modified));
+                false
+            } else {
+                true
+            }
+        });
+
+        for entry in fresh.values() {
+            match previous.remove(&entry.path) {
+                None => {
+                    result.added += 1;
+                    added.push(entry.clone());
+                }
+                Some((size, last_modified)) if size != entry.size || last_modified != entry.last_modified => {
+                    result.updated += 1
+                }
+                Some(_) => {}
+            }
+        }
+        result.removed = previous.len();
+
+        index.extend(fresh.into_values());
+        result.total_files = index.len();
+        index.clone()
+    };
+    state.search_cache.write().await.invalidate_root(&user_selected_dir.to_string_lossy());
+
+    println!(
+        "Subtree reindexed in {:.2?}: {} added, {} removed, {} updated",
+        start_time.elapsed(), result.added, result.removed, result.updated
+    );
+    record_new_files(state, &root_path, added).await;
+
+    let save_options = SaveOptions::new(&state.args, &*state.config.read().await);
+    if let Err(e) = save_index_blocking(updated_index, user_selected_dir, save_options).await {
+        println!("Error saving index: {}", e);
+        metrics::counter!("errors_total", "kind" => "index_save").increment(1);
+    }
+
+    Some(result)
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize, Default)]
+struct RefreshMetadataResult {
+    updated: usize,
+    removed: usize,
+    total_files: usize,
+    /// Files found unchanged by comparing size and modification time
+    unchanged_by_mtime: usize,
+    /// Files found unchanged by comparing size and content hash, see
+    /// `--index-content-hashes`
+    unchanged_by_hash: usize,
+}
+
+/// Re-stats every entry of `index` under `root`, updating sizes and
+/// modification times in place and dropping entries whose files are gone.
+/// Archive members are kept as long as their archive still exists.
+/// Line counts of changed files that had one are redone with the limit in
+/// `options`, see `count_lines`. With `options.content_hashes`, files are
+/// rehashed and a same-size file whose hash matches counts as unchanged
+/// whatever its modification time says.
+fn refresh_entries(root: &std::path::Path, index: &mut Vec<IndexEntry>, options: &WalkOptions) -> RefreshMetadataResult {
+    let mut result = RefreshMetadataResult::default();
+    index.retain_mut(|entry| {
+        let (on_disk, member) = match entry.path.split_once(ARCHIVE_SEPARATOR) {
+            Some((archive, _)) => (PathBuf::from(archive), true),
+            None => match entry.raw_path.as_deref().and_then(decode_raw_path) {
+                Some(raw_path) => (raw_path, false),
+                None => (PathBuf::from(&entry.path), false),
+            },
+        };
+        // A symlink entry is stat'ed itself, so a dangling one isn't taken for deleted
+        let metadata = match entry.is_symlink {
+            true => fs::symlink_metadata(root.join(&on_disk)),
+            false => fs::metadata(root.join(&on_disk)),
+        };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                result.removed += 1;
+                return false;
+            }
+            Err(e) => {
+                // Keep what we had rather than lose the entry to a transient error
+                println!("Could not stat {}: {}", entry.path, e);
+                return true;
+            }
+        };
+        if member {
+            return true;
+        }
+        if entry.is_symlink {
+            let Some(fresh) = symlink_entry(root, &root.join(&on_disk), options) else {
+                result.removed += 1;
+                return false;
+            };
+            if (fresh.size, fresh.last_modified, &fresh.link_target) == (entry.size, entry.last_modified, &entry.link_target) {
+                result.unchanged_by_mtime += 1;
+            } else {
+                result.updated += 1;
+            }
+            (entry.size, entry.last_modified, entry.kind, entry.link_target) = (fresh.size, fresh.last_modified, fresh.kind, fresh.link_target);
+            return true;
+        }
+
+        let last_modified: DateTime<Utc> = metadata.modified()
+            .unwrap_or_else(|_| std::time::SystemTime::now())
+            .into();
+        let size = if metadata.is_dir() { 0 } else { metadata.len() };
+        let full_path = root.join(on_disk);
+        let hash = options.content_hashes
+            .filter(|_| !metadata.is_dir())
+            .and_then(|max_bytes| content_hash(&full_path, size, max_bytes, options.hash_algorithm));
+        // Hashes from another algorithm can't be compared, so trust times instead
+        let by_hash = size == entry.size
+            && hash.as_deref().zip(entry.content_hash.as_deref())
+                .is_some_and(|(hash, stored)| hash_prefix(hash) == hash_prefix(stored));
+        let changed = if by_hash {
+            hash != entry.content_hash
+        } else {
+            size != entry.size || last_modified != entry.last_modified
+        };
+        // Keep the time current even when the hash shows the contents are the same
+        entry.last_modified = last_modified;
+        if options.content_hashes.is_some() || changed {
+            entry.content_hash = hash;
+        }
+        if changed {
+            entry.size = size;
+            if let (Some(max_bytes), Some(_)) = (options.line_counts, entry.lines) {
+                entry.lines = count_lines(&full_path, size, max_bytes);
+            }
+            result.updated += 1;
+        } else if by_hash {
+            result.unchanged_by_hash += 1;
+        } else {
+            result.unchanged_by_mtime += 1;
+        }
+        true
+    });
+    result.total_files = index.len();
+    result
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/refresh-metadata", responses((status = 200, body = RefreshMetadataResult), (status = 404))))]
+async fn refresh_metadata(State(state): State<AppState>) -> Response {
+    println!("\n=== Refreshing Metadata ===");
+    let user_selected_dir = state.user_selected_dir.read().await.clone();
+    let root_path = user_selected_dir.to_string_lossy().to_string();
+    let start_time = std::time::Instant::now();
+
+    let Some(snapshot) = state.indices.read().await.get(&root_path).cloned() else {
+        return Response::builder()
+            .status(404)
+            .body(Body::from("No index loaded for the selected directory"))
+            .unwrap();
+    };
+
+    // Refresh a copy, then keep only what changed, so the index can be
+    // patched rather than replaced by the time the refresh is done
+    let root = user_selected_dir.clone();
+    let options = WalkOptions::for_root(&state, &user_selected_dir).await;
+    let refreshed = tokio::task::spawn_blocking(move || {
+        let mut index = snapshot.clone();
+        let result = refresh_entries(&root, &mut index, &options);
+        let metadata = |entry: &IndexEntry| {
+            (entry.size, entry.last_modified, entry.kind, entry.lines, entry.content_hash.clone(), entry.link_target.clone())
+        };
+        let mut fresh: HashMap<String, IndexEntry> = index.into_iter().map(|entry| (entry.path.clone(), entry)).collect();
+        let mut removed = std::collections::HashSet::new();
+        for entry in &snapshot {
+            match fresh.get(&entry.path) {
+                None => {
+                    removed.insert(entry.path.clone());
+                }
+                Some(refreshed) if metadata(refreshed) == metadata(entry) => {
+                    fresh.remove(&entry.path);
+                }
+                Some(_) => {}
+            }
+        }
+        (fresh, removed, result)
+    }).await;
+    let (updated, removed, mut result) = match refreshed {
+        Ok(refreshed) => refreshed,
+        Err(e) => {
+            println!("Metadata refresh failed: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    println!(
+        "Metadata refreshed in {:.2?}: {} updated, {} removed, {} unchanged by mtime, {} unchanged by hash",
+        start_time.elapsed(), result.updated, result.removed, result.unchanged_by_mtime, result.unchanged_by_hash
+    );
+
+    // Entries renamed, tagged or reindexed meanwhile keep those changes
+    let index = {
+        let mut indices = state.indices.write().await;
+        let Some(index) = indices.get_mut(&root_path) else {
+            println!("Index for {} was unloaded during the refresh", root_path);
+            return Json(result).into_response();
+        };
+        index.retain(|entry| !removed.contains(&entry.path));
+        for entry in index.iter_mut() {
+            if let Some(fresh) = updated.get(&entry.path) {
+                entry.size = fresh.size;
+                entry.last_modified = fresh.last_modified;
+                entry.kind = fresh.kind;
+                entry.lines = fresh.lines;
+                entry.content_hash = fresh.content_hash.clone();
+                entry.link_target = fresh.link_target.clone();
+            }
+        }
+        result.total_files = index.len();
+        index.clone()
+    };
+    state.search_cache.write().await.invalidate_root(&root_path);
+    let save_options = SaveOptions::new(&state.args, &*state.config.read().await);
+    if let Err(e) = save_index_blocking(index, user_selected_dir, save_options).await {
+        println!("Error saving index: {}", e);
+        metrics::counter!("errors_total", "kind" => "index_save").increment(1);
+    }
+
+    Json(result).into_response()
+}
+
+/// Most renames `/rename-preview` lists and `/rename-apply` carries out in
+/// one request.
+const RENAME_MAX_FILES: usize = 10_000;
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct RenameRequest {
+    /// Regex matched against each indexed file's path, relative to the
+    /// selected directory
+    pattern: String,
+    /// Replaces the first match; `$1` or `${name}` insert capture groups
+    replacement: String,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct Rename {
+    from: String,
+    to: String,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct RenameRejection {
+    path: String,
+    reason: String,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct RenamePreview {
+    /// Renames that would be made, at most `RENAME_MAX_FILES`
+    renames: Vec<Rename>,
+    /// Matching files that would be left alone, and why
+    rejected: Vec<RenameRejection>,
+    /// Renames that would be made in all
+    total: usize,
+    truncated: bool,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct RenameResult {
+    renamed: Vec<Rename>,
+    /// Matching files that were left alone, including renames that failed
+    rejected: Vec<RenameRejection>,
+}
+
+/// Why `entry` can't be renamed to `to` under `root`, if it can't.
+fn rename_rejection(root: &std::path::Path, entry: &IndexEntry, to: &str) -> Option<&'static str> {
+    let stays_inside = !to.is_empty()
+        && std::path::Path::new(to).components().all(|c| matches!(c, std::path::Component::Normal(_)));
+    if entry.raw_path.is_some() {
+        Some("Name is not valid UTF-8")
+    } else if entry.path.contains(ARCHIVE_SEPARATOR) || to.contains(ARCHIVE_SEPARATOR) {
+        Some("Files inside archives can't be renamed")
+    } else if !stays_inside || resolve_in_root(root, to).is_err() || !resolves_inside_root(root, &root.join(to)) {
+        Some("New path would be outside the selected directory")
+    } else if fs::symlink_metadata(root.join(to)).is_ok() {
+        Some("Something already exists at the new path")
+    } else {
+        None
+    }
+}
+
+/// Whether `path`, which may not exist yet, stays inside `root` once the
+/// symlinks along the part of it that does exist are followed, as
+/// `canonicalize_in_root` checks for existing paths.
+fn resolves_inside_root(root: &std::path::Path, path: &std::path::Path) -> bool {
+    let Ok(canonical_root) = root.canonicalize() else {
+        return false;
+    };
+    // A dangling symlink can't be canonicalized, and is refused with it
+    path.ancestors()
+        .find(|ancestor| fs::symlink_metadata(ancestor).is_ok())
+        .and_then(|ancestor| ancestor.canonicalize().ok())
+        .is_some_and(|canonical| canonical.starts_with(&canonical_root))
+}
+
+/// Applies `pattern` to the path of every indexed file and works out which
+/// of the changed paths can be renamed. Two files that would end up at the
+/// same path are both rejected.
+fn plan_renames(root: &std::path::Path, index: &[IndexEntry], pattern: &regex::Regex, replacement: &str) -> (Vec<Rename>, Vec<RenameRejection>) {
+    let mut renames = Vec::new();
+    let mut rejected = Vec::new();
+    for entry in index.iter().filter(|entry| entry.kind == EntryKind::File) {
+        let to = pattern.replace(&entry.path, replacement);
+        if to == entry.path {
+            continue;
+        }
+        match rename_rejection(root, entry, &to) {
+            Some(reason) => rejected.push(RenameRejection { path: entry.path.clone(), reason: reason.to_string() }),
+            None => renames.push(Rename { from: entry.path.clone(), to: to.to_string() }),
+        }
+    }
+
+    let mut targets: HashMap<String, usize> = HashMap::new();
+    for rename in &renames {
+        *targets.entry(rename.to.clone()).or_default() += 1;
+    }
+    let (renames, colliding): (Vec<Rename>, Vec<Rename>) = renames.into_iter()
+        .partition(|rename| targets[&rename.to] == 1);
+    rejected.extend(colliding.into_iter().map(|rename| RenameRejection {
+        reason: format!("Another file would also be renamed to {}", rename.to),
+        path: rename.from,
+    }));
+    (renames, rejected)
+}
+
+/// Lists the renames `/rename-apply` would make for the same request, without
+/// touching any file.
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/rename-preview", request_body = RenameRequest, responses((status = 200, body = RenamePreview), (status = 400, body = String))))]
+async fn rename_preview(
+    State(state): State<AppState>,
+    Json(req): Json<RenameRequest>,
+) -> Response {
+    println!("\n=== Rename Preview ===");
+    let pattern = match regex::Regex::new(&req.pattern) {
+        Ok(pattern) => pattern,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(format!("Invalid pattern: {}", e))).into_response(),
+    };
+    let root = state.user_selected_dir.read().await.clone();
+    let indices = state.indices.read().await;
+    let index = indices.get(root.to_string_lossy().as_ref()).map(Vec::as_slice).unwrap_or_default();
+    let (mut renames, rejected) = plan_renames(&root, index, &pattern, &req.replacement);
+    let total = renames.len();
+    renames.truncate(RENAME_MAX_FILES);
+    println!("{} files would be renamed, {} rejected", total, rejected.len());
+
+    Json(RenamePreview { renames, rejected, total, truncated: total > RENAME_MAX_FILES }).into_response()
+}
+
+/// Carries out planned renames under `root`, creating missing parent
+/// directories. Returns the renames made and those that failed, and why.
+fn apply_renames(root: &std::path::Path, renames: Vec<Rename>) -> (Vec<Rename>, Vec<RenameRejection>) {
+    let mut renamed = Vec::new();
+    let mut failed = Vec::new();
+    for rename in renames {
+        let to = root.join(&rename.to);
+        // Checked again in case a symlink appeared since planning
+        if !resolves_inside_root(root, &to) {
+            println!("Could not rename {}: new path would be outside the selected directory", rename.from);
+            failed.push(RenameRejection { path: rename.from, reason: "New path would be outside the selected directory".to_string() });
+            continue;
+        }
+        let moved = to.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| {
+            // Checked again in case something appeared since planning
+            if fs::symlink_metadata(&to).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Something already exists at the new path"));
+            }
+            fs::rename(root.join(&rename.from), &to)
+        });
+        match moved {
+            Ok(()) => {
+                println!("Renamed {} to {}", rename.from, rename.to);
+                renamed.push(rename);
+            }
+            Err(e) => {
+                println!("Could not rename {}: {}", rename.from, e);
+                failed.push(RenameRejection { path: rename.from, reason: e.to_string() });
+            }
+        }
+    }
+    (renamed, failed)
+}
+
+/// Renames every indexed file whose path `pattern` changes, as previewed by
+/// `/rename-preview`, and updates the index and tags to match. Missing parent
+/// directories are created.
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/rename-apply", request_body = RenameRequest, responses((status = 200, body = RenameResult), (status = 400, body = String), (status = 404, body = String))))]
+async fn rename_apply(
+    State(state): State<AppState>,
+    Json(req): Json<RenameRequest>,
+) -> Response {
+    println!("\n=== Applying Renames ===");
+    let pattern = match regex::Regex::new(&req.pattern) {
+        Ok(pattern) => pattern,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(format!("Invalid pattern: {}", e))).into_response(),
+    };
+    let root = state.user_selected_dir.read().await.clone();
+    let root_path = root.to_string_lossy().to_string();
+    let (transliterate, save_options) = {
+        let config = state.config.read().await;
+        let transliterate = config.path_config(&root_path).is_some_and(|path_config| path_config.transliterate);
+        (transliterate, SaveOptions::new(&state.args, &config))
+    };
+
+    // Planned under a read lock; nothing is locked while files move
+    let planned = state.indices.read().await
+        .get(&root_path)
+        .map(|index| plan_renames(&root, index, &pattern, &req.replacement));
+    let Some((renames, mut rejected)) = planned else {
+        return (StatusCode::NOT_FOUND, Json("No index loaded for the selected directory".to_string())).into_response();
+    };
+    if renames.len() > RENAME_MAX_FILES {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(format!("{} files would be renamed, more than {} at once; narrow the pattern", renames.len(), RENAME_MAX_FILES)),
+        ).into_response();
+    }
+
+    let moved = tokio::task::spawn_blocking({
+        let root = root.clone();
+        move || apply_renames(&root, renames)
+    }).await;
+    let renamed = match moved {
+        Ok((renamed, failed)) => {
+            rejected.extend(failed);
+            renamed
+        }
+        Err(e) => {
+            println!("Renaming failed: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // Only the renamed entries change, so whatever else happened to the
+    // index meanwhile is kept
+    let moved: HashMap<&str, &str> = renamed.iter().map(|rename| (rename.from.as_str(), rename.to.as_str())).collect();
+    let mut tag_moves = Vec::new();
+    let renamed_index = {
+        let mut indices = state.indices.write().await;
+        match indices.get_mut(&root_path) {
+            Some(index) if !moved.is_empty() => {
+                for entry in index.iter_mut() {
+                    let Some(&to) = moved.get(entry.path.as_str()) else {
+                        continue;
+                    };
+                    if !entry.tags.is_empty() {
+                        tag_moves.push((root.join(&entry.path), root.join(to), entry.tags.clone()));
+                    }
+                    entry.path = to.to_string();
+                    entry.name = to.rsplit('/').next().unwrap_or(to).to_string();
+                    entry.romanized = if transliterate { romanize(to) } else { None };
+                    entry.folded = Default::default();
+                    entry.folded();
+                }
+                Some(index.clone())
+            }
+            _ => None,
+        }
+    };
+
+    if !tag_moves.is_empty() {
+        let file_tags = {
+            let mut file_tags = state.tags.write().await;
+            for (from, to, tags) in tag_moves {
+                file_tags.set(&from, Vec::new());
+                file_tags.set(&to, tags);
+            }
+            file_tags.clone()
+        };
+        if let Err(e) = save_tags_blocking(file_tags).await {
+            println!("Error saving tags: {}", e);
+        }
+    }
+    if let Some(renamed_index) = renamed_index {
+        state.search_cache.write().await.invalidate_root(&root_path);
+        if let Err(e) = save_index_blocking(renamed_index, root, save_options).await {
+            println!("Error saving index: {}", e);
+            metrics::counter!("errors_total", "kind" => "index_save").increment(1);
+        }
+    }
+    println!("Renamed {} files, {} rejected", renamed.len(), rejected.len());
+
+    Json(RenameResult { renamed, rejected }).into_response()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/cancel-index", responses((status = 200, description = "`{\"cancelled\": bool}`"))))]
+async fn cancel_index(State(state): State<AppState>) -> Json<serde_json::Value> {
+    println!("\n=== Cancelling Index ===");
+    let cancelled = cancel_active_index(&state).await;
+    println!("Indexing run cancelled: {}", cancelled);
+
+    Json(serde_json::json!({ "cancelled": cancelled }))
+}
+
+/// How often `/ws/index` reports progress while a run is going.
+const INDEX_SOCKET_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Commands a client sends over `/ws/index`.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum IndexSocketCommand {
+    Start {
+        /// Cancel the run if the client goes away before it finishes
+        #[serde(default)]
+        cancel_on_disconnect: bool,
+    },
+    Cancel,
+}
+
+/// Messages `/ws/index` sends back.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum IndexSocketEvent {
+    Progress { files: usize },
+    Complete { status: IndexStatus },
+    Cancelled { cancelled: bool },
+    Error { message: String },
+}
+
+/// Starts, monitors and cancels indexing over one WebSocket.
+async fn index_socket(
+    ws: axum::extract::ws::WebSocketUpgrade,
+    State(state): State<AppState>,
+) -> Response {
+    ws.on_upgrade(move |socket| handle_index_socket(socket, state))
+}
+
+async fn handle_index_socket(mut socket: axum::extract::ws::WebSocket, state: AppState) {
+    use axum::extract::ws::Message;
+
+    println!("\n=== Index WebSocket Connected ===");
+    let progress = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let mut run: Option<(tokio::task::JoinHandle<IndexStatus>, Arc<CancellationToken>, bool)> = None;
+    let mut ticker = tokio::time::interval(INDEX_SOCKET_PROGRESS_INTERVAL);
+
+    loop {
+        let event = tokio::select! {
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                match serde_json::from_str::<IndexSocketCommand>(&text) {
+                    Ok(IndexSocketCommand::Start { .. }) if run.is_some() => IndexSocketEvent::Error {
+                        message: "Indexing is already running".to_string(),
+                    },
+                    Ok(IndexSocketCommand::Start { cancel_on_disconnect }) => {
+                        let user_selected_dir = state.user_selected_dir.read().await.clone();
+                        println!("Starting index over WebSocket for: {}", user_selected_dir.display());
+                        let cancel = Arc::new(CancellationToken::new());
+                        progress.store(0, std::sync::atomic::Ordering::Relaxed);
+                        let handle = tokio::spawn({
+                            let (state, cancel, progress) = (state.clone(), cancel.clone(), progress.clone());
+                            async move { run_index(&state, user_selected_dir, cancel, Some(progress)).await }
+                        });
+                        run = Some((handle, cancel, cancel_on_disconnect));
+                        IndexSocketEvent::Progress { files: 0 }
+                    }
+                    Ok(IndexSocketCommand::Cancel) => IndexSocketEvent::Cancelled {
+                        cancelled: cancel_active_index(&state).await,
+                    },
+                    Err(e) => IndexSocketEvent::Error {
+                        message: format!("Invalid command: {}", e),
+                    },
+                }
+            }
+            _ = ticker.tick(), if run.is_some() => IndexSocketEvent::Progress {
+                files: progress.load(std::sync::atomic::Ordering::Relaxed),
+            },
+            finished = async { (&mut run.as_mut().expect("guarded by is_some").0).await }, if run.is_some() => {
+                run = None;
+                match finished {
+                    Ok(status) => IndexSocketEvent::Complete { status },
+                    Err(e) => IndexSocketEvent::Error {
+                        message: format!("Indexing failed: {}", e),
+                    },
+                }
+            }
+        };
+
+        let Ok(text) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+
+    // The run carries on without the client unless it asked otherwise
+    if let Some((_, cancel, true)) = run {
+        println!("Index WebSocket closed mid-run; cancelling");
+        cancel.cancel();
+    }
+    println!("Index WebSocket disconnected");
+}
+
+/// Cancels the indexing run in progress; returns whether there was one.
+async fn cancel_active_index(state: &AppState) -> bool {
+    match state.index_cancel.write().await.take() {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/verify-index", params(VerifyQuery), responses((status = 200, body = VerifyReport))))]
+async fn verify_index(
+    Query(query): Query<VerifyQuery>,
+    State(state): State<AppState>,
+) -> Json<VerifyReport> {
+    println!("\n=== Verifying Index ===");
+    let user_selected_dir = state.user_selected_dir.read().await.clone();
+    let root_path = user_selected_dir.to_string_lossy().to_string();
+    println!("Verifying index for directory: {}", user_selected_dir.display());
+
+    let on_disk: HashMap<String, IndexEntry> = walk_directory_blocking(
+        user_selected_dir.clone(),
+        WalkOptions::for_root(&state, &user_selected_dir).await,
+        CancellationToken::new(),
+    ).await
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+
+    let indices = state.indices.read().await;
+    let empty_vec = Vec::new();
+    let index = indices.get(&root_path).unwrap_or(&empty_vec);
+
+    let mut report = VerifyReport {
+        root_path,
+        indexed_files: index.len(),
+        files_on_disk: on_disk.len(),
+        missing: VerifyCategory::default(),
+        unindexed: VerifyCategory::default(),
+        changed: VerifyCategory::default(),
+    };
+
+    let mut indexed_paths = std::collections::HashSet::new();
+    for entry in index {
+        indexed_paths.insert(entry.path.as_str());
+        match on_disk.get(&entry.path) {
+            None => report.missing.record(&entry.path, query.examples),
+            Some(current) if current.size != entry.size
+                || current.last_modified != entry.last_modified => {
+                report.changed.record(&entry.path, query.examples)
+            }
+            Some(_) => {}
+        }
+    }
+
+    let mut unindexed: Vec<&String> = on_disk.keys()
+        .filter(|path| !indexed_paths.contains(path.as_str()))
+        .collect();
+    unindexed.sort();
+    for path in unindexed {
+        report.unindexed.record(path, query.examples);
+    }
+
+    println!(
+        "Verification complete: {} missing, {} unindexed, {} changed",
+        report.missing.count, report.unindexed.count, report.changed.count
+    );
+
+    Json(report)
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct DiffIndicesRequest {
+    /// Root whose saved index is the baseline
+    from: String,
+    /// Root whose saved index is compared against it
+    to: String,
+    /// Entries of each list to skip
+    #[serde(default)]
+    offset: usize,
+    /// Most entries of each list to return, at most `DIFF_MAX_LIMIT`
+    #[serde(default = "default_diff_limit")]
+    limit: usize,
+}
+
+fn default_diff_limit() -> usize {
+    100
+}
+
+/// Largest page `/diff-indices` returns per list.
+const DIFF_MAX_LIMIT: usize = 1000;
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct DiffSide {
+    root_path: String,
+    total_files: usize,
+    /// When the saved index file was last written
+    saved_at: Option<DateTime<Utc>>,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct ModifiedEntry {
+    path: String,
+    old_size: u64,
+    new_size: u64,
+    old_modified: DateTime<Utc>,
+    new_modified: DateTime<Utc>,
+}
+
+/// One page of a diff list, plus how long the whole list is.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct DiffPage<T> {
+    total: usize,
+    entries: Vec<T>,
+}
+
+impl<T> DiffPage<T> {
+    fn new(all: Vec<T>, offset: usize, limit: usize) -> Self {
+        DiffPage {
+            total: all.len(),
+            entries: all.into_iter().skip(offset).take(limit).collect(),
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct IndexDiff {
+    from: DiffSide,
+    to: DiffSide,
+    /// In `to` but not `from`
+    added: DiffPage<IndexEntry>,
+    /// In `from` but not `to`
+    removed: DiffPage<IndexEntry>,
+    /// In both, with a different size or modification time
+    modified: DiffPage<ModifiedEntry>,
+}
+
+/// Loads the saved index for `root`, failing with 404 if it was never saved.
+fn load_saved_index(root: &str) -> Result<(DiffSide, Vec<IndexEntry>), (StatusCode, String)> {
+    let root_path = PathBuf::from(root);
+    let saved_files = IndexEntry::saved_files(&root_path)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error locating index: {}", e)))?;
+    if saved_files.is_empty() {
+        return Err((StatusCode::NOT_FOUND, format!("No saved index for {}", root)));
+    }
+    let entries = IndexEntry::load_index(&root_path)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error loading index for {}: {}", root, e)))?;
+    let saved_at = saved_files.iter()
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+        .map(DateTime::<Utc>::from);
+    let side = DiffSide {
+        root_path: root.to_string(),
+        total_files: entries.len(),
+        saved_at,
+    };
+    Ok((side, entries))
+}
+
+/// Compares two indices by relative path; each list is sorted by path.
+fn diff_indices_entries(from: Vec<IndexEntry>, to: Vec<IndexEntry>) -> (Vec<IndexEntry>, Vec<IndexEntry>, Vec<ModifiedEntry>) {
+    let mut old: HashMap<String, IndexEntry> = from.into_iter().map(|entry| (entry.path.clone(), entry)).collect();
+    let mut added = Vec::new();
+    let mut modified = Vec::new();
+    for entry in to {
+        match old.remove(&entry.path) {
+            None => added.push(entry),
+            Some(previous) if previous.size != entry.size || previous.last_modified != entry.last_modified => {
+                modified.push(ModifiedEntry {
+                    path: entry.path,
+                    old_size: previous.size,
+                    new_size: entry.size,
+                    old_modified: previous.last_modified,
+                    new_modified: entry.last_modified,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    let mut removed: Vec<IndexEntry> = old.into_values().collect();
+
+    added.sort_by(|a, b| a.path.cmp(&b.path));
+    removed.sort_by(|a, b| a.path.cmp(&b.path));
+    modified.sort_by(|a, b| a.path.cmp(&b.path));
+    (added, removed, modified)
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/diff-indices", request_body = DiffIndicesRequest, responses((status = 200, body = IndexDiff), (status = 404), (status = 500))))]
+async fn diff_indices(Json(req): Json<DiffIndicesRequest>) -> Response {
+    println!("\n=== Diffing Indices ===");
+    println!("Comparing index of {} against {}", req.to, req.from);
+
+    let limit = req.limit.min(DIFF_MAX_LIMIT);
+    let diff = tokio::task::spawn_blocking(move || -> Result<IndexDiff, (StatusCode, String)> {
+        let (from, from_entries) = load_saved_index(&req.from)?;
+        let (to, to_entries) = load_saved_index(&req.to)?;
+        let (added, removed, modified) = diff_indices_entries(from_entries, to_entries);
+        println!("Diff complete: {} added, {} removed, {} modified", added.len(), removed.len(), modified.len());
+        Ok(IndexDiff {
+            from,
+            to,
+            added: DiffPage::new(added, req.offset, limit),
+            removed: DiffPage::new(removed, req.offset, limit),
+            modified: DiffPage::new(modified, req.offset, limit),
+        })
+    }).await;
+
+    match diff {
+        Ok(Ok(diff)) => Json(diff).into_response(),
+        Ok(Err(rejection)) => {
+            println!("Rejected: {}", rejection.1);
+            rejection.into_response()
+        }
+        Err(e) => {
+            println!("Index diff task failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct RekeyIndexRequest {
+    /// Root the index was saved for
+    old_root: String,
+    /// Directory now holding the same files
+    new_root: String,
+    /// Delete the old root's index and point its saved settings at the new
+    /// root, instead of copying both
+    #[serde(default, rename = "move")]
+    move_index: bool,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct RekeyIndexResult {
+    old_root: String,
+    new_root: String,
+    total_files: usize,
+    /// Indexed files looked up under the new root, see `sample_new_root`
+    sampled: usize,
+    /// Sampled files found there with their indexed size
+    sample_matched: usize,
+    moved: bool,
+    /// Set when too few sampled files matched for the index to be trusted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+}
+
+/// Indexed files `sample_new_root` checks for under the new root.
+const REKEY_SAMPLE_SIZE: usize = 50;
+
+/// Looks up an evenly spread sample of the indexed files under `root`,
+/// returning how many were sampled and how many exist there with the same
+/// size. A cheap stand-in for walking the new root to compare file counts.
+fn sample_new_root(root: &std::path::Path, entries: &[IndexEntry]) -> (usize, usize) {
+    let files: Vec<&IndexEntry> = entries.iter()
+        .filter(|entry| entry.kind == EntryKind::File && !entry.path.contains(ARCHIVE_SEPARATOR))
+        .collect();
+    let step = (files.len() / REKEY_SAMPLE_SIZE).max(1);
+    let sample: Vec<&IndexEntry> = files.into_iter().step_by(step).take(REKEY_SAMPLE_SIZE).collect();
+    let matched = sample.iter()
+        .filter(|entry| {
+            let relative = entry.raw_path.as_deref()
+                .and_then(decode_raw_path)
+                .unwrap_or_else(|| PathBuf::from(&entry.path));
+            fs::metadata(root.join(relative)).is_ok_and(|metadata| metadata.is_file() && metadata.len() == entry.size)
+        })
+        .count();
+    (sample.len(), matched)
+}
+
+/// Saves the index of a directory that moved under its new location, so it
+/// doesn't need rebuilding. Tags and saved settings for the old root follow
+/// it. Warns rather than refuses when a sample of the files doesn't match.
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/rekey-index", request_body = RekeyIndexRequest, responses((status = 200, body = RekeyIndexResult), (status = 400, body = String), (status = 404, body = String), (status = 500, body = String))))]
+async fn rekey_index(
+    State(state): State<AppState>,
+    Json(req): Json<RekeyIndexRequest>,
+) -> Response {
+    println!("\n=== Rekeying Index ===");
+    let old_root = normalize_root(std::path::Path::new(&req.old_root)).to_string_lossy().to_string();
+    let new_root_path = normalize_root(std::path::Path::new(&req.new_root));
+    let new_root = new_root_path.to_string_lossy().to_string();
+    println!("Rekeying index of {} to {} (move: {})", old_root, new_root, req.move_index);
+    if !new_root_path.is_dir() {
+        println!("Rejected: {} is not a directory", new_root);
+        return (StatusCode::BAD_REQUEST, Json(format!("Directory does not exist: {}", new_root))).into_response();
+    }
+    if old_root == new_root {
+        return (StatusCode::BAD_REQUEST, Json("Old and new root are the same directory".to_string())).into_response();
+    }
+
+    let save_options = SaveOptions::new(&state.args, &*state.config.read().await);
+    let rekeyed = tokio::task::spawn_blocking({
+        let (old_root, new_root_path) = (old_root.clone(), new_root_path.clone());
+        move || {
+            let (_, entries) = load_saved_index(&old_root)?;
+            let sample = sample_new_root(&new_root_path, &entries);
+            IndexEntry::save_index(&entries, &new_root_path, &save_options)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Error saving index: {}", e)))?;
+            Ok::<_, (StatusCode, String)>((entries, sample))
+        }
+    }).await;
+    let (mut entries, (sampled, sample_matched)) = match rekeyed {
+        Ok(Ok(rekeyed)) => rekeyed,
+        Ok(Err((status, message))) => {
+            println!("Rejected: {}", message);
+            return (status, Json(message)).into_response();
+        }
+        Err(e) => {
+            println!("Index rekey task failed: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let warning = (sample_matched * 10 < sampled * 9).then(|| format!(
+        "Only {} of {} sampled files were found with the same size under {}; reindex if the contents differ",
+        sample_matched, sampled, new_root
+    ));
+    if let Some(warning) = &warning {
+        println!("Warning: {}", warning);
+    }
+
+    if req.move_index {
+        let old_files = IndexEntry::saved_files(std::path::Path::new(&old_root)).unwrap_or_default();
+        for file in old_files {
+            if let Err(e) = fs::remove_file(&file) {
+                println!("Error removing old index file {}: {}", file.display(), e);
+            }
+        }
+    }
+
+    {
+        let mut tags = state.tags.write().await;
+        if tags.rekey_root(&old_root, &new_root, !req.move_index) {
+            if let Err(e) = tags.save() {
+                println!("Error saving tags: {}", e);
+            }
+        }
+        tags.apply(&new_root, &mut entries);
+    }
+
+    let total_files = entries.len();
+    {
+        let mut indices = state.indices.write().await;
+        if req.move_index {
+            indices.remove(&old_root);
+        }
+        indices.insert(new_root.clone(), entries);
+    }
+    {
+        let mut search_cache = state.search_cache.write().await;
+        search_cache.invalidate_root(&old_root);
+        search_cache.invalidate_root(&new_root);
+    }
+
+    {
+        let mut config = state.config.write().await;
+        let settings = config.path_config(&old_root).cloned();
+        if req.move_index {
+            let bookmarked = config.remove_bookmark(&old_root);
+            config.recent_paths.retain(|p| p.path != old_root);
+            if bookmarked {
+                config.add_bookmark(new_root.clone());
+            }
+            if config.last_root.as_deref() == Some(old_root.as_str()) {
+                config.last_root = Some(new_root.clone());
+            }
+        }
+        if config.path_config(&new_root).is_none() {
+            config.add_path(new_root.clone(), total_files);
+        }
+        // The index came from the old root, so its settings and stats apply
+        if let Some(settings) = settings {
+            for path_config in config.path_configs_mut(&new_root) {
+                *path_config = PathConfig { path: new_root.clone(), ..settings.clone() };
+            }
+        }
+        if let Err(e) = config.save() {
+            println!("Error saving config: {}", e);
+        }
+    }
+    if req.move_index {
+        let mut user_selected_dir = state.user_selected_dir.write().await;
+        if user_selected_dir.to_string_lossy() == old_root {
+            *user_selected_dir = new_root_path;
+        }
+    }
+
+    Json(RekeyIndexResult {
+        old_root,
+        new_root,
+        total_files,
+        sampled,
+        sample_matched,
+        moved: req.move_index,
+        warning,
+    }).into_response()
+}
+
+/// Where `--query-log` appends searches.
+fn get_query_log_path() -> io::Result<PathBuf> {
+    let proj_dirs = directories::ProjectDirs::from("", "", "rsconfig")
+        .ok_or_else(|| io::Error::other("Could not determine data directory"))?;
+    Ok(proj_dirs.data_dir().join("query_log.jsonl"))
+}
+
+/// One line of the query log.
+#[derive(Serialize)]
+struct QueryLogRecord {
+    timestamp: DateTime<Utc>,
+    query: String,
+    /// Root searched; `None` for an `all_roots` search
+    root: Option<String>,
+    results: usize,
+    duration_ms: f64,
+    /// Whether the result came from the search cache
+    cached: bool,
+}
+
+/// Records searched for to `get_query_log_path`, for analytics. Records are
+/// handed to a background writer so searches never wait on the disk.
+struct QueryLog {
+    sender: tokio::sync::mpsc::Sender<QueryLogRecord>,
+}
+
+/// Records waiting for the query log writer; past this, new ones are dropped.
+const QUERY_LOG_QUEUE: usize = 1024;
+
+impl QueryLog {
+    fn start(path: PathBuf, max_bytes: u64) -> Self {
+        let (sender, receiver) = tokio::sync::mpsc::channel(QUERY_LOG_QUEUE);
+        tokio::spawn(write_query_log(path, max_bytes, receiver));
+        QueryLog { sender }
+    }
+
+    fn record(&self, record: QueryLogRecord) {
+        if self.sender.try_send(record).is_err() {
+            println!("Query log is backed up; dropping a record");
+        }
+    }
+}
+
+async fn open_query_log(path: &std::path::Path) -> io::Result<(tokio::io::BufWriter<tokio::fs::File>, u64)> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    let size = file.metadata().await?.len();
+    Ok((tokio::io::BufWriter::new(file), size))
+}
+
+/// Appends records as JSON lines, flushing after each burst. Once the file
+/// passes `max_bytes` it's renamed to `<name>.1`, replacing the previous
+/// one, and a fresh file is started.
+async fn write_query_log(path: PathBuf, max_bytes: u64, mut receiver: tokio::sync::mpsc::Receiver<QueryLogRecord>) {
+    use tokio::io::AsyncWriteExt;
+
+    let (mut writer, mut size) = match open_query_log(&path).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            println!("Error opening query log {}: {}; not logging queries", path.display(), e);
+            return;
+        }
+    };
+    println!("Logging search queries to: {}", path.display());
+
+    while let Some(record) = receiver.recv().await {
+        let mut lines = Vec::new();
+        let mut next = Some(record);
+        while let Some(record) = next {
+            if let Ok(line) = serde_json::to_vec(&record) {
+                lines.extend_from_slice(&line);
+                lines.push(b'\n');
+            }
+            next = receiver.try_recv().ok();
+        }
+        if let Err(e) = async { writer.write_all(&lines).await?; writer.flush().await }.await {
+            println!("Error writing query log: {}", e);
+            continue;
+        }
+        size += lines.len() as u64;
+
+        if size >= max_bytes {
+            let mut rotated = path.clone().into_os_string();
+            rotated.push(".1");
+            let reopened = async {
+                tokio::fs::rename(&path, &rotated).await?;
+                open_query_log(&path).await
+            }.await;
+            match reopened {
+                Ok(opened) => (writer, size) = opened,
+                Err(e) => println!("Error rotating query log: {}", e),
+            }
+        }
+    }
+}
+
+/// Absolute paths of every file downloaded so far, in the form the access log
+/// records them, for `exclude_downloaded` searches.
+async fn downloaded_paths(state: &AppState) -> std::collections::HashSet<String> {
+    state.access_log.read().await.files.iter()
+        .filter(|(_, record)| record.count > 0)
+        .map(|(full_path, _)| full_path.clone())
+        .collect()
+}
+
+fn was_downloaded(downloaded: &std::collections::HashSet<String>, root: &str, entry: &IndexEntry) -> bool {
+    downloaded.contains(std::path::Path::new(root).join(&entry.path).to_string_lossy().as_ref())
+}
+
+/// Decayed access score of every downloaded file, keyed like the access log,
+/// for searches with a `frecency_weight`.
+async fn frecency_scores(state: &AppState) -> HashMap<String, f64> {
+    let now = Utc::now();
+    state.access_log.read().await.files.iter()
+        .map(|(full_path, record)| (full_path.clone(), record.frecency(now)))
+        .collect()
+}
+
+/// Score nudge for files downloaded often and lately. It approaches but never
+/// reaches `weight`, however often a file is fetched, so a modest weight only
+/// reorders comparable matches.
+fn frecency_bonus(weight: f64, scores: &HashMap<String, f64>, root: &str, entry: &IndexEntry) -> i64 {
+    let Some(&frecency) = scores.get(std::path::Path::new(root).join(&entry.path).to_string_lossy().as_ref()) else {
+        return 0;
+    };
+    (weight * frecency / (frecency + 1.0)).round() as i64
+}
+
+/// Core fuzzy search shared by the GET and POST variants of `/search`.
+async fn run_search(state: &AppState, mut query: SearchQuery) -> SearchResult {
+    println!("Search query: {}", query.q);
+    let start_time = std::time::Instant::now();
+    // Every page is cut from the same full result list, cached once
+    let (cursor, limit) = (query.cursor.take(), query.limit.take());
+    let query = query.with_defaults(&*state.config.read().await);
+    let tie_breaker = query.tie_breaker();
+    let current_path = state.user_selected_dir.read().await.to_string_lossy().to_string();
+    touch_index(state, &current_path).await;
+    let cache_root = (!query.all_roots).then(|| current_path.clone());
+    let cache_key = format!("{:?}\0{:?}", cache_root, query);
+    let logged_query = state.query_log.as_ref().map(|_| query.q.clone());
+    // Downloads don't invalidate the cache, so these results can't come from it
+    let cacheable = !query.exclude_downloaded && query.frecency_weight.unwrap_or_default() <= 0.0;
+    // Bound first so the cache lock is released before scanning
+    let hit = state.search_cache.write().await.get(&cache_key).filter(|_| cacheable);
+    let (result, cached) = match hit {
+        Some(result) => {
+            println!("Returning cached results ({} files)", result.files.len());
+            metrics::counter!("searches_total", "mode" => query.mode()).increment(1);
+            (result, true)
+        }
+        None => (scan_indices(state, query, &current_path).await, false),
+    };
+    if cacheable && !cached {
+        state.search_cache.write().await.insert(cache_key.clone(), cache_root.clone(), result.clone());
+    }
+
+    if let (Some(log), Some(query)) = (&state.query_log, logged_query) {
+        log.record(QueryLogRecord {
+            timestamp: Utc::now(),
+            query,
+            root: cache_root,
+            results: result.files.len()
+                + result.groups.iter().flatten().map(|group| group.count).sum::<usize>()
+                + result.tree.as_deref().map_or(0, count_tree_matches),
+            duration_ms: start_time.elapsed().as_secs_f64() * 1000.0,
+            cached,
+        });
+    }
+    match (cursor, limit) {
+        (None, None) => result,
+        (cursor, limit) => paginate(result, cursor.as_deref(), limit, &cache_key, tie_breaker),
+    }
+}
+
+async fn scan_indices(state: &AppState, query: SearchQuery, current_path: &str) -> SearchResult {
+    let query = query.extract_operators();
+    let start_time = std::time::Instant::now();
+    
+    let matcher = query.matcher();
+    let indices = state.indices.read().await;
+    
+    // Get the current path's index, or every loaded index
+    let roots: Vec<(&String, &Vec<IndexEntry>)> = if query.all_roots {
+        indices.iter().collect()
+    } else {
+        indices.get_key_value(current_path).into_iter().collect()
+    };
+    
+    let downloaded = match query.exclude_downloaded {
+        true => Some(downloaded_paths(state).await),
+        false => None,
+    };
+    let frecency_weight = query.frecency_weight.unwrap_or_default();
+    let frecency = match frecency_weight > 0.0 {
+        true => Some(frecency_scores(state).await),
+        false => None,
+    };
+    let mut matches: Vec<(i64, IndexEntry)> = Vec::new();
+    for &(root, index) in &roots {
+        for chunk in index.chunks(SEARCH_SCAN_CHUNK) {
+            matches.extend(chunk.iter().filter_map(|entry| {
+                let mut score = query.score(&*matcher, entry)?;
+                if downloaded.as_ref().is_some_and(|downloaded| was_downloaded(downloaded, root, entry)) {
+                    return None;
+                }
+                if let Some(frecency) = &frecency {
+                    score = score.saturating_add(frecency_bonus(frecency_weight, frecency, root, entry));
+                }
+                let mut entry = entry.clone();
+                entry.id = Some(result_id(root, &entry.path));
+                entry.file_type = Some(FileType::of(&entry));
+                if query.abs {
+                    entry.absolute_path = Some(std::path::Path::new(root).join(&entry.path).to_string_lossy().to_string());
+                }
+                // Tag results so downloads resolve against the right root
+                if query.all_roots {
+                    entry.root = Some(root.clone());
+                }
+                Some((score, entry))
+            }));
+            // Axum drops this future when the client disconnects; yielding
+            // gives it the chance to, so an abandoned search stops scanning
+            tokio::task::yield_now().await;
+        }
+    }
+
+    // Sort by score descending, breaking ties so the order is stable between requests
+    matches.sort_by(|(a_score, a), (b_score, b)| {
+        b_score.cmp(a_score).then_with(|| query.tie_breaker().compare(a, b))
+    });
+
+    let tree = (query.format == ResultFormat::Tree).then(|| build_result_tree(&matches));
+    if query.collapse_case {
+        matches = collapse_case_duplicates(matches, current_path);
+    }
+    println!("Found {} matching files", matches.len());
+    metrics::counter!("searches_total", "mode" => query.mode()).increment(1);
+    metrics::histogram!("search_duration_seconds", "mode" => query.mode())
+        .record(start_time.elapsed().as_secs_f64());
+
+    // Only worth the extra pass when the user would otherwise get nothing
+    let suggestions = (matches.is_empty() && query.suggest).then(|| {
+        suggest_paths(&query, roots.iter().flat_map(|(_, index)| index.iter()))
+    });
+
+    let (scores, files) = matches.into_iter().unzip();
+    let mut result = SearchResult {
+        files,
+        index_loaded: !roots.is_empty(),
+        index_size: roots.iter().map(|(_, index)| index.len()).sum(),
+        alternatives: None,
+        groups: None,
+        suggestions,
+        tree: None,
+        next_cursor: None,
+        cursor_reset: false,
+        scores,
+    };
+    if let Some(tree) = tree {
+        result.files.clear();
+        result.scores.clear();
+        result.tree = Some(tree);
+        return result;
+    }
+    if query.group_by_name {
+        let (distinct, alternatives) = group_by_name(result.scores.drain(..).zip(result.files.drain(..)).collect());
+        (result.scores, result.files) = distinct.into_iter().unzip();
+        result.alternatives = Some(alternatives);
+    }
+    if let Some(ResultGrouping::TopLevel) = query.group {
+        result.groups = Some(group_by_top_level(std::mem::take(&mut result.files)));
+        result.scores.clear();
+    }
+    result
+}
+
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/search", params(SearchQuery), responses((status = 200, body = SearchResult), (status = 400, description = "Unknown name in `fields`"))))]
+async fn search(
+    Query(query): Query<SearchQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== Search Request ===");
+    projected_search(&state, query).await
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/search", request_body = SearchQuery, responses((status = 200, body = SearchResult), (status = 400, description = "Unknown name in `fields`"))))]
+async fn search_post(
+    State(state): State<AppState>,
+    Json(query): Json<SearchQuery>,
+) -> Response {
+    println!("\n=== Search Request (POST) ===");
+    projected_search(&state, query).await
+}
+
+/// Fields of a serialized `IndexEntry` that `fields` can select.
+const PROJECTABLE_FIELDS: &[&str] = &[
+    "path", "name", "last_modified", "size", "kind", "root", "id", "absolute_path", "file_type",
+    "owner", "raw_path", "lines", "content_hash", "romanized", "tags", "is_symlink", "link_target",
+];
+
+/// `run_search` answering with only the entry fields listed in the query's
+/// `fields`, or 400 if it names one that doesn't exist.
+async fn projected_search(state: &AppState, mut query: SearchQuery) -> Response {
+    let Some(fields) = query.fields.take() else {
+        return Json(run_search(state, query).await).into_response();
+    };
+    let fields: Vec<&str> = fields.split(',').map(str::trim).filter(|field| !field.is_empty()).collect();
+    if let Some(unknown) = fields.iter().find(|field| !PROJECTABLE_FIELDS.contains(field)) {
+        println!("Rejected unknown field: {}", unknown);
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Unknown field `{}`; expected some of {}", unknown, PROJECTABLE_FIELDS.join(", ")),
+        ).into_response();
+    }
+    Json(project_result(&run_search(state, query).await, &fields)).into_response()
+}
+
+/// `result` as JSON with every entry in it, in `files`, `groups` or `tree`,
+/// cut down to `fields`. Fields an entry doesn't have stay absent.
+fn project_result(result: &SearchResult, fields: &[&str]) -> serde_json::Value {
+    fn project(entry: &mut serde_json::Value, fields: &[&str]) {
+        if let Some(entry) = entry.as_object_mut() {
+            entry.retain(|key, _| fields.contains(&key.as_str()));
+        }
+    }
+    fn project_tree(nodes: &mut serde_json::Value, fields: &[&str]) {
+        for node in nodes.as_array_mut().into_iter().flatten() {
+            if let Some(entry) = node.get_mut("entry") {
+                project(entry, fields);
+            }
+            if let Some(children) = node.get_mut("children") {
+                project_tree(children, fields);
+            }
+        }
+    }
+
+    let mut value = serde_json::to_value(result).unwrap_or_default();
+    for entry in value.get_mut("files").and_then(serde_json::Value::as_array_mut).into_iter().flatten() {
+        project(entry, fields);
+    }
+    for group in value.get_mut("groups").and_then(serde_json::Value::as_array_mut).into_iter().flatten() {
+        for entry in group.get_mut("files").and_then(serde_json::Value::as_array_mut).into_iter().flatten() {
+            project(entry, fields);
+        }
+    }
+    if let Some(tree) = value.get_mut("tree") {
+        project_tree(tree, fields);
+    }
+    value
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct SearchPageQuery {
+    /// Results to skip
+    #[serde(default)]
+    offset: usize,
+    /// Most results to render
+    #[serde(default = "default_search_page_limit")]
+    limit: usize,
+}
+
+fn default_search_page_limit() -> usize {
+    25
+}
+
+const SEARCH_PAGE_MAX_LIMIT: usize = 1000;
+
+/// `/search` as an HTML fragment to swap straight into a page: a `<ul>` with
+/// a link per result in `offset..offset + limit`, marked up like the
+/// embedded UI's, or a `<p>` when there is nothing to list. Results are
+/// always flat.
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/search/html", params(SearchPageQuery, SearchQuery), responses((status = 200, content_type = "text/html"))))]
+async fn search_html(
+    Query(page): Query<SearchPageQuery>,
+    Query(query): Query<SearchQuery>,
+    State(state): State<AppState>,
+) -> Html<String> {
+    println!("\n=== Search Request (HTML) ===");
+    // `limit` is the page's, read into `SearchPageQuery`, not a cursor page size
+    let query = SearchQuery { group: None, format: ResultFormat::Flat, limit: None, cursor: None, ..query };
+    let result = run_search(&state, query).await;
+    if result.index_size == 0 {
+        return Html("<p class=\"no-results\">The index is empty.</p>\n".to_string());
+    }
+    if result.files.is_empty() {
+        return Html("<p class=\"no-results\">No files found</p>\n".to_string());
+    }
+
+    let selected_root = state.user_selected_dir.read().await.to_string_lossy().to_string();
+    let limit = page.limit.min(SEARCH_PAGE_MAX_LIMIT);
+    let mut html = String::from("<ul class=\"results\">\n");
+    for file in result.files.iter().skip(page.offset).take(limit) {
+        html.push_str(&result_item_html(&state.args.base_path, &selected_root, file));
+    }
+    let next = page.offset.saturating_add(limit);
+    let remaining = result.files.len().saturating_sub(next);
+    if remaining > 0 {
+        html.push_str(&format!("<li class=\"more\" data-offset=\"{}\">{} more</li>\n", next, remaining));
+    }
+    html.push_str("</ul>\n");
+    Html(html)
+}
+
+/// One `<li>` of `/search/html`. Files link to their download; directories
+/// carry the path to select in `data-change-path`. Everything taken from the
+/// index is escaped, since file names can hold markup.
+fn result_item_html(base_path: &str, selected_root: &str, file: &IndexEntry) -> String {
+    let encode = |text: &str| percent_encoding::utf8_percent_encode(text, percent_encoding::NON_ALPHANUMERIC).to_string();
+    let icon = file.file_type.unwrap_or_else(|| FileType::of(file)).icon();
+    let root = file.root.as_deref().unwrap_or(selected_root);
+    let full_path = format!("{}/{}", root.trim_end_matches('/'), file.path);
+    if file.kind == EntryKind::Dir {
+        return format!(
+            "<li><a href=\"#\" class=\"file-link\" data-change-path=\"{}\" title=\"{}\">{} {}/</a></li>\n",
+            escape_html(&full_path), escape_html(&full_path), icon, escape_html(&file.name)
+        );
+    }
+
+    // Names that aren't valid UTF-8 are only found again by their raw bytes
+    let params: Vec<String> = [("root", file.root.as_deref()), ("raw", file.raw_path.as_deref())].into_iter()
+        .filter_map(|(name, value)| Some(format!("{}={}", name, encode(value?))))
+        .collect();
+    let href = match params.is_empty() {
+        true => format!("{}/download/{}", base_path, encode(&file.path)),
+        false => format!("{}/download/{}?{}", base_path, encode(&file.path), params.join("&")),
+    };
+    let mut label = match file.lines {
+        Some(lines) => format!("{} ({} lines)", file.name, lines),
+        None => file.name.clone(),
+    };
+    if file.raw_path.is_some() {
+        label.push_str(" (name is not valid UTF-8)");
+    }
+    let title = match file.root {
+        Some(_) => &full_path,
+        None => &file.path,
+    };
+    format!(
+        "<li><a href=\"{}\" class=\"file-link\" title=\"{}\">{} {}</a></li>\n",
+        escape_html(&href), escape_html(title), icon, escape_html(&label)
+    )
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct DirSearchQuery {
+    #[serde(alias = "query")]
+    q: String,
+    /// Most directories to return
+    #[serde(default = "default_dir_search_limit")]
+    limit: usize,
+}
+
+fn default_dir_search_limit() -> usize {
+    20
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct DirMatch {
+    /// Relative to the selected directory
+    path: String,
+    /// What to send to `/change-path` to jump there
+    absolute_path: String,
+    score: i64,
+}
+
+/// Directories under the selected one known from its index: indexed
+/// directories plus every parent of an indexed file, so this works whether or
+/// not the index was built with `--index-directories`.
+fn indexed_directories(index: &[IndexEntry]) -> std::collections::BTreeSet<&str> {
+    let mut dirs = std::collections::BTreeSet::new();
+    for entry in index {
+        if entry.path.contains(ARCHIVE_SEPARATOR) {
+            continue;
+        }
+        if entry.kind == EntryKind::Dir {
+            dirs.insert(entry.path.as_str());
+        }
+        let mut path = entry.path.as_str();
+        while let Some((parent, _)) = path.rsplit_once(['/', '\\']) {
+            if !dirs.insert(parent) {
+                break;
+            }
+            path = parent;
+        }
+    }
+    dirs
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/search-dirs", params(DirSearchQuery), responses((status = 200, body = Vec<DirMatch>))))]
+async fn search_dirs(
+    Query(query): Query<DirSearchQuery>,
+    State(state): State<AppState>,
+) -> Json<Vec<DirMatch>> {
+    println!("\n=== Directory Search Request ===");
+    println!("Directory search query: {}", query.q);
+
+    let root = state.user_selected_dir.read().await.clone();
+    let indices = state.indices.read().await;
+    let Some(index) = indices.get(root.to_string_lossy().as_ref()) else {
+        return Json(Vec::new());
+    };
+
+    let matcher = SkimMatcherV2::default().smart_case();
+    let mut matches: Vec<(i64, &str)> = indexed_directories(index)
+        .into_iter()
+        .filter_map(|dir| matcher.fuzzy_match(dir, &query.q).map(|score| (score, dir)))
+        .collect();
+    // Prefer higher scores, then shallower directories
+    matches.sort_by(|(a_score, a), (b_score, b)| {
+        b_score.cmp(a_score).then_with(|| a.len().cmp(&b.len())).then_with(|| a.cmp(b))
+    });
+
+    let dirs: Vec<DirMatch> = matches
+        .into_iter()
+        .take(query.limit)
+        .map(|(score, dir)| DirMatch {
+            path: dir.to_string(),
+            absolute_path: root.join(dir).to_string_lossy().to_string(),
+            score,
+        })
+        .collect();
+    println!("Found {} matching directories", dirs.len());
+    Json(dirs)
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct LargestFilesQuery {
+    /// Most entries to return, at most `LARGEST_FILES_MAX_LIMIT`
+    #[serde(default = "default_largest_files_limit")]
+    limit: usize,
+    /// Comma-separated extensions to keep, case-insensitive, e.g. `mp4,mkv`
+    ext: Option<String>,
+    /// Only return files or only directories; both when unset
+    kind: Option<EntryKind>,
+}
+
+fn default_largest_files_limit() -> usize {
+    20
+}
+
+/// Upper bound on `/largest-files?limit=`.
+const LARGEST_FILES_MAX_LIMIT: usize = 1000;
+
+/// Every indexed file, across all loaded roots, whose content hash is
+/// `hash`, with `root` set on each. Only indices built with
+/// `--index-content-hashes` have hashes to find.
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/by-hash/{hash}", params(("hash" = String, Path, description = "Hex digest, optionally prefixed with `sha256:` or `blake3:`")), responses((status = 200, body = Vec<IndexEntry>))))]
+async fn files_by_hash(
+    Path(hash): Path<String>,
+    State(state): State<AppState>,
+) -> Json<Vec<IndexEntry>> {
+    println!("\n=== Lookup By Hash ===");
+    let indices = state.indices.read().await;
+    let mut entries: Vec<IndexEntry> = indices.iter()
+        .flat_map(|(root, index)| {
+            index.iter()
+                .filter(|entry| entry.content_hash.as_deref().is_some_and(|stored| hash_matches(stored, &hash)))
+                .map(move |entry| IndexEntry { root: Some(root.clone()), ..entry.clone() })
+        })
+        .collect();
+    entries.sort_by(|a, b| a.root.cmp(&b.root).then_with(|| a.path.cmp(&b.path)));
+    println!("Found {} files with hash {}", entries.len(), hash);
+
+    Json(entries)
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/largest-files", params(LargestFilesQuery), responses((status = 200, body = Vec<IndexEntry>))))]
+async fn largest_files(
+    Query(query): Query<LargestFilesQuery>,
+    State(state): State<AppState>,
+) -> Json<Vec<IndexEntry>> {
+    let root = state.user_selected_dir.read().await.clone();
+    let indices = state.indices.read().await;
+    let Some(index) = indices.get(root.to_string_lossy().as_ref()) else {
+        return Json(Vec::new());
+    };
+
+    let extensions: Vec<String> = query.ext.as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .collect();
+    let mut entries: Vec<&IndexEntry> = index.iter()
+        .filter(|entry| query.kind.is_none_or(|kind| entry.kind == kind))
+        .filter(|entry| {
+            extensions.is_empty() || entry.extension().is_some_and(|ext| extensions.contains(&ext))
+        })
+        .collect();
+
+    // Biggest first, alphabetical among equal sizes
+    let order = |a: &&IndexEntry, b: &&IndexEntry| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path));
+    let limit = query.limit.min(LARGEST_FILES_MAX_LIMIT);
+    if entries.len() > limit && limit > 0 {
+        entries.select_nth_unstable_by(limit - 1, order);
+    }
+    entries.truncate(limit);
+    entries.sort_by(order);
+
+    Json(entries.into_iter().cloned().collect())
+}
+
+/// A file that appeared in an index update.
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize, Clone)]
+struct NewFile {
+    /// When the update that found it finished, plus a few nanoseconds to keep
+    /// every file's distinct
+    indexed_at: DateTime<Utc>,
+    #[serde(skip)]
+    root: String,
+    #[serde(flatten)]
+    entry: IndexEntry,
+}
+
+/// Most arrivals kept for `/new-files`; older ones are dropped first.
+const NEW_FILES_CAPACITY: usize = 10_000;
+
+/// Adds `added`, found by an index update of `root`, to the arrivals served
+/// by `/new-files` and wakes anyone waiting there.
+async fn record_new_files(state: &AppState, root: &str, added: Vec<IndexEntry>) {
+    if added.is_empty() {
+        return;
+    }
+    println!("Recording {} new files", added.len());
+    {
+        let mut new_files = state.new_files.write().await;
+        // Every file gets its own instant, so a `limit` can't cut a batch in
+        // a way that makes the next `since` skip the rest of it
+        let mut indexed_at = Utc::now();
+        if let Some(last) = new_files.back() {
+            indexed_at = indexed_at.max(last.indexed_at + chrono::Duration::nanoseconds(1));
+        }
+        new_files.extend(added.into_iter().enumerate().map(|(i, entry)| NewFile {
+            indexed_at: indexed_at + chrono::Duration::nanoseconds(i as i64),
+            root: root.to_string(),
+            entry,
+        }));
+        let excess = new_files.len().saturating_sub(NEW_FILES_CAPACITY);
+        new_files.drain(..excess);
+    }
+    state.new_files_signal.send_modify(|generation| *generation += 1);
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct NewFilesQuery {
+    /// Only files indexed after this time (RFC 3339); pass the previous
+    /// response's `until` to continue where it left off
+    since: DateTime<Utc>,
+    /// Seconds to wait for new files when there are none yet, at most
+    /// `NEW_FILES_MAX_WAIT_SECS`
+    #[serde(default = "default_new_files_wait")]
+    wait: u64,
+    /// Most files to return, at most `NEW_FILES_MAX_LIMIT`
+    #[serde(default = "default_new_files_limit")]
+    limit: usize,
+}
+
+fn default_new_files_wait() -> u64 {
+    30
+}
+
+fn default_new_files_limit() -> usize {
+    100
+}
+
+/// Upper bound on `/new-files?wait=`.
+const NEW_FILES_MAX_WAIT_SECS: u64 = 60;
+
+/// Upper bound on `/new-files?limit=`.
+const NEW_FILES_MAX_LIMIT: usize = 1000;
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct NewFiles {
+    /// Oldest first
+    files: Vec<NewFile>,
+    /// `since` for the next poll
+    until: DateTime<Utc>,
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/new-files", params(NewFilesQuery), responses((status = 200, body = NewFiles))))]
+async fn new_files(
+    Query(query): Query<NewFilesQuery>,
+    State(state): State<AppState>,
+) -> Json<NewFiles> {
+    let wait = std::time::Duration::from_secs(query.wait.min(NEW_FILES_MAX_WAIT_SECS));
+    let limit = query.limit.min(NEW_FILES_MAX_LIMIT);
+    let deadline = tokio::time::Instant::now() + wait;
+    // Subscribe before looking so an update landing in between still wakes us
+    let mut signal = state.new_files_signal.subscribe();
+
+    loop {
+        let root = state.user_selected_dir.read().await.to_string_lossy().to_string();
+        let files: Vec<NewFile> = state.new_files.read().await
+            .iter()
+            .filter(|file| file.indexed_at > query.since && file.root == root)
+            .take(limit)
+            .cloned()
+            .collect();
+        if !files.is_empty() {
+            let until = files.last().map_or(query.since, |file| file.indexed_at);
+            return Json(NewFiles { files, until });
+        }
+        let woken = tokio::time::timeout_at(deadline, signal.changed()).await;
+        if !matches!(woken, Ok(Ok(()))) {
+            return Json(NewFiles { files, until: query.since });
+        }
+    }
+}
+
+/// Number of index entries scored between yields of a `/search` scan.
+const SEARCH_SCAN_CHUNK: usize = 4096;
+
+/// Number of results sent per write of the streaming search.
+const SEARCH_STREAM_BATCH: usize = 1000;
+
+/// Most results `/search/stream` sends when the query has no smaller `limit`.
+const SEARCH_STREAM_MAX_RESULTS: usize = 100_000;
+
+/// A `/search/stream` match. Ordered best-first like `/search` results, so
+/// the top of a `BinaryHeap` of them is the worst one kept.
+struct StreamMatch {
+    score: i64,
+    entry: IndexEntry,
+    tie_breaker: TieBreaker,
+}
+
+impl Ord for StreamMatch {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.score.cmp(&self.score).then_with(|| self.tie_breaker.compare(&self.entry, &other.entry))
+    }
+}
+
+impl PartialOrd for StreamMatch {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for StreamMatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for StreamMatch {}
+
+/// The best `limit` matches of `query`, best first, scanning the current
+/// root's index or every loaded one for `all_roots`. `None` once `tx` is
+/// closed, so a client that went away stops the scan.
+async fn best_stream_matches(
+    state: &AppState,
+    query: &SearchQuery,
+    current_path: &str,
+    limit: usize,
+    tx: &tokio::sync::mpsc::Sender<Result<String, io::Error>>,
+) -> Option<Vec<ScoredEntry>> {
+    let downloaded = match query.exclude_downloaded {
+        true => Some(downloaded_paths(state).await),
+        false => None,
+    };
+    let frecency_weight = query.frecency_weight.unwrap_or_default();
+    let frecency = match frecency_weight > 0.0 {
+        true => Some(frecency_scores(state).await),
+        false => None,
+    };
+    let matcher = query.matcher();
+    let tie_breaker = query.tie_breaker();
+
+    let indices = state.indices.read().await;
+    let roots: Vec<(&String, &Vec<IndexEntry>)> = if query.all_roots {
+        indices.iter().collect()
+    } else {
+        indices.get_key_value(current_path).into_iter().collect()
+    };
+
+    let mut best: std::collections::BinaryHeap<StreamMatch> = std::collections::BinaryHeap::new();
+    for &(root, index) in &roots {
+        for chunk in index.chunks(SEARCH_SCAN_CHUNK) {
+            // Nothing is sent until the scan is over, so a disconnect would
+            // otherwise go unnoticed until then
+            if tx.is_closed() {
+                return None;
+            }
+            for entry in chunk {
+                let Some(mut score) = query.score(&*matcher, entry) else {
+                    continue;
+                };
+                if downloaded.as_ref().is_some_and(|downloaded| was_downloaded(downloaded, root, entry)) {
+                    continue;
+                }
+                if let Some(frecency) = &frecency {
+                    score = score.saturating_add(frecency_bonus(frecency_weight, frecency, root, entry));
+                }
+                // Only entries that make the cut are cloned
+                let beats_worst = |worst: &StreamMatch| {
+                    score.cmp(&worst.score).then_with(|| tie_breaker.compare(&worst.entry, entry)).is_gt()
+                };
+                if best.len() >= limit && !best.peek().is_some_and(beats_worst) {
+                    continue;
+                }
+                let mut entry = entry.clone();
+                entry.id = Some(result_id(root, &entry.path));
+                entry.file_type = Some(FileType::of(&entry));
+                if query.abs {
+                    entry.absolute_path = Some(std::path::Path::new(root).join(&entry.path).to_string_lossy().to_string());
+                }
+                // Tag results so downloads resolve against the right root
+                if query.all_roots {
+                    entry.root = Some(root.clone());
+                }
+                best.push(StreamMatch { score, entry, tie_breaker });
+                if best.len() > limit {
+                    best.pop();
+                }
+            }
+            // Let other tasks run between chunks of a long scan
+            tokio::task::yield_now().await;
+        }
+    }
+
+    Some(best.into_sorted_vec().into_iter().map(|best| (best.score, best.entry)).collect())
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/search/stream", params(SearchQuery), responses((status = 200, description = "One `IndexEntry` JSON object per line, best match first, up to `limit` of them", content_type = "application/x-ndjson"))))]
+async fn search_stream(
+    Query(query): Query<SearchQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== Streaming Search Request ===");
+    println!("Search query: {}", query.q);
+    let query = query
+        .with_defaults(&*state.config.read().await)
+        .extract_operators();
+    let current_path = state.user_selected_dir.read().await.to_string_lossy().to_string();
+    let limit = query.limit.unwrap_or(SEARCH_STREAM_MAX_RESULTS).min(SEARCH_STREAM_MAX_RESULTS);
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, io::Error>>(4);
+
+    metrics::counter!("searches_total", "mode" => query.mode()).increment(1);
+
+    tokio::spawn(async move {
+        let Some(mut matches) = best_stream_matches(&state, &query, &current_path, limit, &tx).await else {
+            println!("Streaming search cancelled while scanning");
+            return;
+        };
+        if query.collapse_case {
+            matches = collapse_case_duplicates(matches, &current_path);
+        }
+
+        let mut sent = 0;
+        for batch in matches.chunks(SEARCH_STREAM_BATCH) {
+            let mut lines = String::new();
+            for (_, entry) in batch {
+                if let Ok(line) = serde_json::to_string(entry) {
+                    lines.push_str(&line);
+                    lines.push('\n');
+                }
+            }
+            // The receiver is dropped once the client disconnects
+            if tx.send(Ok(lines)).await.is_err() {
+                println!("Streaming search cancelled after {} matches", sent);
+                return;
+            }
+            sent += batch.len();
+        }
+
+        println!("Streamed {} matching files", sent);
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)))
+        .unwrap()
+}
+
+/// Bounds a file-reading operation by the configured read timeout so a file on
+/// an unresponsive filesystem can't hold a request open indefinitely.
+async fn with_read_timeout<T>(
+    state: &AppState,
+    path: &std::path::Path,
+    read: impl std::future::Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    let limit = std::time::Duration::from_secs(state.args.read_timeout_secs);
+    match tokio::time::timeout(limit, read).await {
+        Ok(result) => result,
+        Err(_) => {
+            println!("Timed out after {:?} reading {}", limit, path.display());
+            metrics::counter!("errors_total", "kind" => "read_timeout").increment(1);
+            Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out reading file"))
+        }
+    }
+}
+
+/// Status for a failed file read: 504 for timeouts, 404 otherwise.
+fn read_error_status(e: &io::Error) -> StatusCode {
+    if e.kind() == io::ErrorKind::TimedOut {
+        StatusCode::GATEWAY_TIMEOUT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Number of rows written per chunk of the CSV export.
+const CSV_EXPORT_BATCH: usize = 1000;
+
+/// Writes `entries` as CSV rows, optionally preceded by the header row.
+fn csv_chunk(entries: &[IndexEntry], header: bool) -> io::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if header {
+        writer.write_record(["path", "name", "size", "last_modified", "kind"])?;
+    }
+    for entry in entries {
+        writer.write_record([
+            entry.path.as_str(),
+            entry.name.as_str(),
+            &entry.size.to_string(),
+            &entry.last_modified.to_rfc3339(),
+            entry.kind.as_str(),
+        ])?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct SearchExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Most results `/search/export` writes; the rest are dropped and the file
+/// says so.
+const SEARCH_EXPORT_MAX_RESULTS: usize = 100_000;
+
+/// Path of a result as written to an export: absolute with `abs=true`, or
+/// joined to its root for `all_roots` results, otherwise relative.
+fn export_path(entry: &IndexEntry) -> String {
+    match (&entry.absolute_path, &entry.root) {
+        (Some(absolute_path), _) => absolute_path.clone(),
+        (None, Some(root)) => std::path::Path::new(root).join(&entry.path).to_string_lossy().to_string(),
+        (None, None) => entry.path.clone(),
+    }
+}
+
+/// Every result of a search as a downloadable file. Takes the same
+/// parameters as `/search`, except that `format` picks the file format;
+/// results are always flat.
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/search/export", params(SearchExportQuery), responses((status = 200, description = "Result paths as an attachment; also accepts every `/search` parameter other than `format`"))))]
+async fn search_export(
+    Query(export): Query<SearchExportQuery>,
+    axum::extract::RawQuery(raw_query): axum::extract::RawQuery,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== Search Export ===");
+    // `format` means something else to `SearchQuery`, so parse the rest
+    // of the parameters without it.
+    let search_params = raw_query.as_deref().unwrap_or_default()
+        .split('&')
+        .filter(|pair| pair.split('=').next() != Some("format"))
+        .collect::<Vec<_>>()
+        .join("&");
+    let query = match format!("/?{}", search_params).parse::<axum::http::Uri>() {
+        Ok(uri) => match Query::<SearchQuery>::try_from_uri(&uri) {
+            Ok(Query(query)) => query,
+            Err(rejection) => return rejection.into_response(),
+        },
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    // Every result, not one cursor page
+    let query = SearchQuery { group: None, limit: None, cursor: None, ..query };
+
+    let mut files = run_search(&state, query).await.files;
+    let total = files.len();
+    let truncated = total > SEARCH_EXPORT_MAX_RESULTS;
+    files.truncate(SEARCH_EXPORT_MAX_RESULTS);
+    println!("Exporting {} of {} results as {}", files.len(), total, export.format.extension());
+
+    let truncation_note = format!("# truncated: first {} of {} results\n", files.len(), total);
+    let body = match export.format {
+        ExportFormat::Txt => {
+            let mut body = files.iter().map(|entry| export_path(entry) + "\n").collect::<String>();
+            if truncated {
+                body.push_str(&truncation_note);
+            }
+            body.into_bytes()
+        }
+        ExportFormat::Json => serde_json::to_vec(&serde_json::json!({
+            "paths": files.iter().map(export_path).collect::<Vec<_>>(),
+            "total": total,
+            "truncated": truncated,
+        })).unwrap_or_default(),
+        ExportFormat::Csv => {
+            let mut body = match csv_chunk(&files, true) {
+                Ok(body) => body,
+                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            };
+            if truncated {
+                body.extend_from_slice(truncation_note.as_bytes());
+            }
+            body
+        }
+    };
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, export.format.content_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"search-results.{}\"", export.format.extension()),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/export/csv", responses((status = 200, content_type = "text/csv"))))]
+async fn export_csv(State(state): State<AppState>) -> Response {
+    println!("\n=== CSV Export ===");
+    let user_selected_dir = state.user_selected_dir.read().await.clone();
+    let index = state.indices.read().await
+        .get(&user_selected_dir.to_string_lossy().to_string())
+        .cloned()
+        .unwrap_or_default();
+    println!("Exporting {} entries for {}", index.len(), user_selected_dir.display());
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<io::Result<Vec<u8>>>(4);
+    tokio::spawn(async move {
+        if tx.send(csv_chunk(&[], true)).await.is_err() {
+            return;
+        }
+        for chunk in index.chunks(CSV_EXPORT_BATCH) {
+            // The receiver is dropped once the client disconnects
+            if tx.send(csv_chunk(chunk, false)).await.is_err() {
+                println!("CSV export cancelled");
+                return;
+            }
+        }
+    });
+
+    let filename = user_selected_dir.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "index".to_string());
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.csv\"", filename.replace('"', "")),
+        )
+        .body(Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)))
+        .unwrap()
+}
+
+/// Joins a client-supplied relative path onto the selected directory, rejecting
+/// anything that could escape it.
+fn resolve_in_root(root: &std::path::Path, requested: &str) -> Result<PathBuf, (StatusCode, &'static str)> {
+    // Clean the file path and convert to PathBuf
+    // Results use `/` separators; `PathBuf` maps them to the OS form when joining
+    resolve_path_in_root(root, std::path::Path::new(requested.trim_start_matches(std::path::is_separator)))
+}
+
+/// `resolve_in_root` for a path that may not be valid UTF-8.
+fn resolve_path_in_root(root: &std::path::Path, file_path: &std::path::Path) -> Result<PathBuf, (StatusCode, &'static str)> {
+    if file_path.components().any(|c| matches!(c, std::path::Component::ParentDir)) {
+        println!("Rejected due to parent directory traversal attempt");
+        return Err((StatusCode::FORBIDDEN, "Invalid path"));
+    }
+
+    let full_path = root.join(file_path);
+    println!("Full path constructed: {}", full_path.display());
+    
+    // Additional check to ensure we're only serving files within root_path
+    if !full_path.starts_with(root) {
+        println!("Rejected: Path {} is outside selected directory {}", full_path.display(), root.display());
+        return Err((StatusCode::NOT_FOUND, "File path outside root directory"));
+    }
+
+    if app_data_dirs_under(root).iter().any(|dir| full_path.starts_with(dir)) {
+        println!("Rejected: Path {} is inside the app's data directory", full_path.display());
+        return Err((StatusCode::FORBIDDEN, "Invalid path"));
+    }
+
+    Ok(full_path)
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct DownloadQuery {
+    /// Root the path is relative to, as tagged on `all_roots` search results;
+    /// defaults to the selected directory
+    root: Option<String>,
+    /// A result's `raw_path`, if it has one; used instead of the path in the
+    /// URL, which can't carry a name that isn't valid UTF-8
+    raw: Option<String>,
+    /// Show the file in the browser with its own content type. HTML, SVG and
+    /// XML are downloaded anyway unless the server runs with
+    /// `--allow-inline-active`.
+    #[serde(default)]
+    inline: bool,
+}
+
+/// Policy for served files, so whatever a file contains can't run script or
+/// fetch anything in this server's origin, even when shown inline.
+const DOWNLOAD_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src data:; style-src 'unsafe-inline'; sandbox";
+
+/// Whether a browser showing content of this type may run script in it:
+/// HTML, and SVG or any other XML.
+fn is_active_content(mime: &mime_guess::Mime) -> bool {
+    matches!(mime.essence_str(), "text/html" | "application/xhtml+xml" | "text/xml" | "application/xml")
+        || mime.suffix().is_some_and(|suffix| suffix.as_str() == "xml")
+}
+
+/// Starts the response for a downloaded file. With `inline` the file keeps
+/// its own content type and is shown in the browser, unless it's active
+/// content and `--allow-inline-active` is off; everything else is an
+/// octet-stream attachment.
+fn download_response(filename: &str, inline: bool, args: &Args) -> axum::http::response::Builder {
+    let mime = mime_guess::from_path(filename).first_or_octet_stream();
+    let active = is_active_content(&mime);
+    if inline && active && !args.allow_inline_active {
+        println!("Serving {} as an attachment: {} could run script inline", filename, mime);
+    }
+    let (disposition, content_type) = match inline && (!active || args.allow_inline_active) {
+        true => ("inline", mime.to_string()),
+        false => ("attachment", "application/octet-stream".to_string()),
+    };
+    Response::builder()
+        .header(header::CONTENT_DISPOSITION, format!("{}; filename=\"{}\"", disposition, filename))
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_SECURITY_POLICY, DOWNLOAD_CONTENT_SECURITY_POLICY)
+}
+
+/// The directory a download is relative to. An explicit root must be one with
+/// a loaded index, so clients can't name arbitrary directories.
+async fn download_root(state: &AppState, root: Option<String>) -> Result<PathBuf, (StatusCode, &'static str)> {
+    match root {
+        Some(root) if state.indices.read().await.contains_key(&root) => Ok(PathBuf::from(root)),
+        Some(root) => {
+            println!("Rejected: {} is not a loaded root", root);
+            Err((StatusCode::NOT_FOUND, "Unknown root directory"))
+        }
+        None => Ok(state.user_selected_dir.read().await.clone()),
+    }
+}
+
+/// Waits up to `--download-queue-ms` for a download slot, answering 503 with
+/// `Retry-After` when none frees up in time.
+async fn acquire_download_permit(state: &AppState) -> Result<tokio::sync::OwnedSemaphorePermit, Response> {
+    let permits = state.download_permits.clone();
+    if let Ok(permit) = permits.clone().try_acquire_owned() {
+        return Ok(permit);
+    }
+
+    println!("Download limit of {} reached, queueing request", state.args.max_concurrent_downloads);
+    metrics::counter!("downloads_throttled_total").increment(1);
+    let wait = std::time::Duration::from_millis(state.args.download_queue_ms);
+    match tokio::time::timeout(wait, permits.acquire_owned()).await {
+        Ok(Ok(permit)) => Ok(permit),
+        _ => {
+            println!("Rejected: no download slot freed up within {:?}", wait);
+            metrics::counter!("downloads_rejected_total").increment(1);
+            Err(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(header::RETRY_AFTER, "1")
+                .body(Body::from("Too many downloads in progress"))
+                .unwrap())
+        }
+    }
+}
+
+/// Size of the pieces a download body is sent in, so its slot is held until
+/// the client has actually taken the last one.
+const DOWNLOAD_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Body for a download that keeps its slot until the stream completes or
+/// the connection is dropped, whichever comes first.
+fn download_body(contents: Vec<u8>, permit: tokio::sync::OwnedSemaphorePermit) -> Body {
+    use futures::StreamExt;
+
+    let contents = axum::body::Bytes::from(contents);
+    let chunks = (0..contents.len())
+        .step_by(DOWNLOAD_CHUNK_BYTES)
+        .map(move |start| contents.slice(start..(start + DOWNLOAD_CHUNK_BYTES).min(contents.len())));
+    let stream = futures::stream::iter(chunks).map(move |chunk| {
+        let _permit = &permit;
+        Ok::<_, std::convert::Infallible>(chunk)
+    });
+    Body::from_stream(stream)
+}
+
+/// Answers a download `resolve_in_root` refused as an attempt to reach files
+/// outside the root, leaving a trail of who tried what.
+fn reject_traversal(client: SocketAddr, requested: &str, reason: &str) -> Response {
+    println!("WARN: Rejected path traversal attempt from {}: {:?} ({})", client.ip(), requested, reason);
+    metrics::counter!("security_rejections_total", "kind" => "traversal").increment(1);
+    (
+        StatusCode::FORBIDDEN,
+        Json(serde_json::json!({ "error": reason })),
+    ).into_response()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/download/{path}", params(("path" = String, Path, description = "Path relative to the selected directory"), DownloadQuery), responses((status = 200, content_type = "application/octet-stream"), (status = 400), (status = 403), (status = 404), (status = 503, description = "Too many downloads in progress; retry after `Retry-After` seconds"), (status = 504))))]
+async fn download_file(
+    Path(file_path): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    axum::extract::ConnectInfo(client): axum::extract::ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== Download Request ===");
+    println!("Download request for file: {}", file_path);
+    println!("Selected directory is: {}", state.user_selected_dir.read().await.display());
+
+    let root = match download_root(&state, query.root).await {
+        Ok(root) => root,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let resolved = match query.raw.as_deref() {
+        Some(raw) => match decode_raw_path(raw) {
+            Some(raw_path) => resolve_path_in_root(&root, &raw_path),
+            None => return (StatusCode::BAD_REQUEST, "Raw paths are not supported on this platform").into_response(),
+        },
+        None => resolve_in_root(&root, &file_path),
+    };
+    let full_path = match resolved {
+        Ok(full_path) => full_path,
+        Err((StatusCode::FORBIDDEN, reason)) => return reject_traversal(client, query.raw.as_deref().unwrap_or(&file_path), reason),
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    // A symlink along the way may not lead out of the root
+    if let (Ok(canonical), Ok(canonical_root)) = (full_path.canonicalize(), root.canonicalize()) {
+        if !canonical.starts_with(&canonical_root) {
+            return reject_traversal(client, query.raw.as_deref().unwrap_or(&file_path), "symlink resolves outside the root");
+        }
+    }
+    if full_path.is_dir() {
+        println!("Rejected: Path {} is a directory", full_path.display());
+        return (StatusCode::BAD_REQUEST, "Cannot download a directory").into_response();
+    }
+    if !full_path.is_file() {
+        if let (Some(limits), Some((archive, inner))) = (
+            WalkOptions::from_args(&state.args).archives,
+            file_path.split_once(ARCHIVE_SEPARATOR),
+        ) {
+            let permit = match acquire_download_permit(&state).await {
+                Ok(permit) => permit,
+                Err(rejection) => return rejection,
+            };
+            return download_archive_member(&root, archive, inner.to_string(), limits, query.inline, &state, permit).await;
+        }
+
+        println!("Rejected: Path {} is not a file", full_path.display());
+        return Response::builder()
+            .status(404)
+            .body(Body::from("Not a file"))
+            .unwrap();
+    }
+
+    let permit = match acquire_download_permit(&state).await {
+        Ok(permit) => permit,
+        Err(rejection) => return rejection,
+    };
+    match with_read_timeout(&state, &full_path, tokio::fs::read(&full_path)).await {
+        Ok(contents) => {
+            let filename = full_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("download")
+                .to_string();
+            
+            println!("Successfully read file: {} ({} bytes)", filename, contents.len());
+            metrics::counter!("downloads_total").increment(1);
+
+            {
+                let mut access_log = state.access_log.write().await;
+                access_log.record(&full_path);
+                if let Err(e) = access_log.save() {
+                    println!("Error saving access counts: {}", e);
+                    metrics::counter!("errors_total", "kind" => "access_log_save").increment(1);
+                }
+            }
+            
+            download_response(&filename, query.inline, &state.args)
+                .body(download_body(contents, permit))
+                .unwrap()
+        }
+        Err(e) => {
+            println!("Error reading file {}: {}", full_path.display(), e);
+            metrics::counter!("errors_total", "kind" => "download").increment(1);
+            Response::builder()
+                .status(read_error_status(&e))
+                .body(Body::from(format!("Error reading file: {}", e)))
+                .unwrap()
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct PrefetchRequest {
+    /// Paths relative to the selected directory, most wanted first
+    paths: Vec<String>,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct PrefetchRejection {
+    path: String,
+    reason: String,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize, Default)]
+struct PrefetchResult {
+    /// Paths whose contents are being read into the OS cache
+    scheduled: Vec<String>,
+    rejected: Vec<PrefetchRejection>,
+}
+
+/// Most files one `/prefetch` request schedules.
+const PREFETCH_MAX_FILES: usize = 32;
+
+/// Most bytes one `/prefetch` request schedules, across all its files.
+const PREFETCH_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Asks the OS to pull `path` into its page cache so a download soon after
+/// is served from memory. Linux takes the hint without us reading anything;
+/// elsewhere the file is read and the data thrown away.
+fn warm_page_cache(path: &std::path::Path) -> io::Result<()> {
+    let file = fs::File::open(path)?;
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: the descriptor stays open for the call; offset and length 0 cover the whole file
+        let result = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        io::copy(&mut io::BufReader::new(file), &mut io::sink())?;
+    }
+
+    Ok(())
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/prefetch", request_body = PrefetchRequest, responses((status = 200, body = PrefetchResult))))]
+async fn prefetch(
+    State(state): State<AppState>,
+    Json(req): Json<PrefetchRequest>,
+) -> Json<PrefetchResult> {
+    println!("\n=== Prefetch Request ===");
+    let root = state.user_selected_dir.read().await.clone();
+
+    let mut result = PrefetchResult::default();
+    let mut scheduled_paths = Vec::new();
+    let mut budget = PREFETCH_MAX_BYTES;
+    for path in req.paths {
+        let reject = |reason: &str| PrefetchRejection { path: path.clone(), reason: reason.to_string() };
+        if scheduled_paths.len() >= PREFETCH_MAX_FILES {
+            result.rejected.push(reject("too many files in one request"));
+            continue;
+        }
+        let full_path = match resolve_in_root(&root, &path) {
+            Ok(full_path) => full_path,
+            Err((_, reason)) => {
+                result.rejected.push(reject(reason));
+                continue;
+            }
+        };
+        let size = match tokio::fs::metadata(&full_path).await {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => {
+                result.rejected.push(reject("not a file"));
+                continue;
+            }
+        };
+        if size > budget {
+            result.rejected.push(reject("over the size budget"));
+            continue;
+        }
+        budget -= size;
+        scheduled_paths.push(full_path);
+        result.scheduled.push(path);
+    }
+
+    println!("Prefetching {} files, rejected {}", result.scheduled.len(), result.rejected.len());
+    if !scheduled_paths.is_empty() {
+        tokio::task::spawn_blocking(move || {
+            for path in scheduled_paths {
+                if let Err(e) = warm_page_cache(&path) {
+                    println!("Error prefetching {}: {}", path.display(), e);
+                }
+            }
+        });
+    }
+    Json(result)
+}
+
+/// Resolves a client-supplied path to its canonical absolute form, refusing
+/// anything that ends up outside `root` once symlinks are followed.
+fn canonicalize_in_root(root: &std::path::Path, requested: &str) -> Result<PathBuf, (StatusCode, String)> {
+    let full_path = resolve_in_root(root, requested)
+        .map_err(|(status, message)| (status, message.to_string()))?;
+
+    // Canonicalize both sides so a symlink can't point the result outside the root
+    match (full_path.canonicalize(), root.canonicalize()) {
+        (Ok(canonical), Ok(root)) if canonical.starts_with(&root) => Ok(canonical),
+        (Ok(canonical), Ok(_)) => {
+            println!("Rejected: Path {} resolves outside selected directory", canonical.display());
+            Err((StatusCode::NOT_FOUND, "File path outside root directory".to_string()))
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            println!("Error resolving {}: {}", full_path.display(), e);
+            Err((StatusCode::NOT_FOUND, format!("Error resolving path: {}", e)))
+        }
+    }
+}
+
+/// Fills an editor link template. `{path}` is replaced by the absolute path
+/// as-is and `{path_encoded}` by its percent-encoded form, for query strings.
+fn render_editor_link(template: &str, absolute_path: &str) -> String {
+    let encoded = percent_encoding::utf8_percent_encode(absolute_path, percent_encoding::NON_ALPHANUMERIC);
+    template
+        .replace("{path_encoded}", &encoded.to_string())
+        .replace("{path}", absolute_path)
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/editor-link/{path}", params(("path" = String, Path, description = "Path relative to the selected directory")), responses((status = 200, description = "`{\"link\": string}`"), (status = 403), (status = 404))))]
+async fn editor_link(
+    Path(file_path): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== Editor Link Request ===");
+    println!("Editor link request for: {}", file_path);
+
+    let Some(template) = state.config.read().await.editor_link_template.clone() else {
+        return Response::builder()
+            .status(404)
+            .body(Body::from("No editor link template configured"))
+            .unwrap();
+    };
+
+    let user_dir = state.user_selected_dir.read().await.clone();
+    let canonical = match canonicalize_in_root(&user_dir, &file_path) {
+        Ok(canonical) => canonical,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    Json(serde_json::json!({
+        "link": render_editor_link(&template, &canonical.to_string_lossy())
+    }))
+    .into_response()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/abs-path/{path}", params(("path" = String, Path, description = "Path relative to the selected directory")), responses((status = 200, description = "`{\"path\": string}`"), (status = 403), (status = 404))))]
+async fn absolute_path(
+    Path(file_path): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== Absolute Path Request ===");
+    println!("Absolute path request for: {}", file_path);
+
+    let user_dir = state.user_selected_dir.read().await.clone();
+    let canonical = match canonicalize_in_root(&user_dir, &file_path) {
+        Ok(canonical) => canonical,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    Json(serde_json::json!({
+        "path": canonical.to_string_lossy().to_string()
+    }))
+    .into_response()
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct FrequentFilesQuery {
+    #[serde(default = "default_frequent_limit")]
+    limit: usize,
+}
+
+fn default_frequent_limit() -> usize {
+    20
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct FrequentFile {
+    path: String,
+    count: u64,
+    last_accessed: DateTime<Utc>,
+    score: f64,
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/frequent-files", params(FrequentFilesQuery), responses((status = 200, body = Vec<FrequentFile>))))]
+async fn frequent_files(
+    Query(query): Query<FrequentFilesQuery>,
+    State(state): State<AppState>,
+) -> Json<Vec<FrequentFile>> {
+    let root = state.user_selected_dir.read().await.clone();
+    let access_log = state.access_log.read().await;
+    let now = Utc::now();
+
+    let mut files: Vec<FrequentFile> = access_log.files.iter()
+        .filter_map(|(full_path, record)| {
+            let path = std::path::Path::new(full_path).strip_prefix(&root).ok()?;
+            Some(FrequentFile {
+                path: slash_path(path),
+                count: record.count,
+                last_accessed: record.last_accessed,
+                score: record.frecency(now),
+            })
+        })
+        .collect();
+
+    files.sort_by(|a, b| b.score.total_cmp(&a.score));
+    files.truncate(query.limit);
+    Json(files)
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/metrics", responses((status = 200, content_type = "text/plain"))))]
+async fn prometheus_metrics(State(state): State<AppState>) -> Response {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(state.metrics.render()))
+        .unwrap()
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct DiagnosticCheck {
+    name: String,
+    ok: bool,
+    detail: String,
+}
+
+impl DiagnosticCheck {
+    fn new(name: &str, outcome: Result<String, String>) -> Self {
+        let (ok, detail) = match outcome {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        DiagnosticCheck { name: name.to_string(), ok, detail }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct DiagnosticsReport {
+    /// Whether every check passed
+    ok: bool,
+    /// Where the config file is, or would be created
+    config_path: Option<String>,
+    /// Where indices are saved
+    index_dir: Option<String>,
+    /// Where the query log and other data files go
+    data_dir: Option<String>,
+    /// The selected directory
+    root: String,
+    checks: Vec<DiagnosticCheck>,
+}
+
+/// Free space below which the disk space check fails.
+const DIAGNOSTICS_MIN_FREE_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)] // `statvfs` field types differ between platforms
+fn available_space(path: &std::path::Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `stats` is only read once the call succeeds
+    let result = unsafe { libc::statvfs(c_path.as_ptr(), stats.as_mut_ptr()) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stats = unsafe { stats.assume_init() };
+    Ok(u64::from(stats.f_bavail) * u64::from(stats.f_frsize))
+}
+
+fn check_config(config_path: &io::Result<PathBuf>) -> Result<String, String> {
+    let config_path = config_path.as_ref().map_err(|e| e.to_string())?;
+    if !config_path.exists() {
+        return Ok("Not created yet; using defaults".to_string());
+    }
+    let contents = fs::read_to_string(config_path)
+        .map_err(|e| format!("Cannot read {}: {}", config_path.display(), e))?;
+    // `Config::load` falls back to defaults on a parse error, which hides a broken file
+    serde_json::from_str::<Config>(&contents)
+        .map_err(|e| format!("Cannot parse {}: {}", config_path.display(), e))?;
+    Ok("Readable and valid".to_string())
+}
+
+fn check_data_dir(data_dir: Option<&std::path::Path>) -> Result<String, String> {
+    let data_dir = data_dir.ok_or("Could not determine data directory")?;
+    fs::create_dir_all(data_dir).map_err(|e| format!("Cannot create {}: {}", data_dir.display(), e))?;
+    let probe = data_dir.join(".diagnostics-probe");
+    fs::write(&probe, b"ok").map_err(|e| format!("Cannot write to {}: {}", data_dir.display(), e))?;
+    let _ = fs::remove_file(&probe);
+    Ok("Writable".to_string())
+}
+
+fn check_index_dir(index_dir: &io::Result<PathBuf>) -> Result<String, String> {
+    let index_dir = index_dir.as_ref().map_err(|e| e.to_string())?;
+    match fs::metadata(index_dir) {
+        Ok(metadata) if metadata.is_dir() => {
+            let files = fs::read_dir(index_dir).map(|entries| entries.count()).unwrap_or(0);
+            Ok(format!("Exists with {} files", files))
+        }
+        Ok(_) => Err(format!("{} is not a directory", index_dir.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err("Does not exist; it is created when an index is first saved".to_string())
+        }
+        Err(e) => Err(format!("Cannot access {}: {}", index_dir.display(), e)),
+    }
+}
+
+fn check_root(root: &std::path::Path) -> Result<String, String> {
+    let entries = fs::read_dir(root).map_err(|e| format!("Cannot list {}: {}", root.display(), e))?;
+    Ok(format!("Readable, {} entries at the top level", entries.count()))
+}
+
+fn check_disk_space(index_dir: &io::Result<PathBuf>) -> Result<String, String> {
+    let index_dir = index_dir.as_ref().map_err(|e| e.to_string())?;
+    // Measure the nearest existing ancestor, as the directory may not exist yet
+    let existing = index_dir.ancestors()
+        .find(|dir| dir.exists())
+        .ok_or_else(|| format!("No part of {} exists", index_dir.display()))?;
+
+    #[cfg(unix)]
+    {
+        let available = available_space(existing)
+            .map_err(|e| format!("Cannot check free space at {}: {}", existing.display(), e))?;
+        let detail = format!("{} MiB free at {}", available / (1024 * 1024), existing.display());
+        if available < DIAGNOSTICS_MIN_FREE_BYTES {
+            return Err(detail);
+        }
+        Ok(detail)
+    }
+
+    #[cfg(not(unix))]
+    {
+        Ok(format!("Not checked on this platform ({})", existing.display()))
+    }
+}
+
+/// Liveness probe. Takes no locks, so it answers even mid-index.
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/health", responses((status = 200, body = String))))]
+async fn health() -> &'static str {
+    "ok"
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/diagnostics", responses((status = 200, body = DiagnosticsReport))))]
+async fn diagnostics(State(state): State<AppState>) -> Json<DiagnosticsReport> {
+    println!("\n=== Running Diagnostics ===");
+    let root = state.user_selected_dir.read().await.clone();
+    let config_path = get_config_path();
+    let index_dir = get_index_dir();
+    let data_dir = get_query_log_path().ok()
+        .and_then(|path| path.parent().map(PathBuf::from));
+
+    let checks = vec![
+        DiagnosticCheck::new("config", check_config(&config_path)),
+        DiagnosticCheck::new("data_dir", check_data_dir(data_dir.as_deref())),
+        DiagnosticCheck::new("index_dir", check_index_dir(&index_dir)),
+        DiagnosticCheck::new("root", check_root(&root)),
+        DiagnosticCheck::new("disk_space", check_disk_space(&index_dir)),
+    ];
+    for check in checks.iter().filter(|check| !check.ok) {
+        println!("Diagnostic check {} failed: {}", check.name, check.detail);
+    }
+
+    Json(DiagnosticsReport {
+        ok: checks.iter().all(|check| check.ok),
+        config_path: config_path.ok().map(|path| path.to_string_lossy().to_string()),
+        index_dir: index_dir.ok().map(|path| path.to_string_lossy().to_string()),
+        data_dir: data_dir.map(|path| path.to_string_lossy().to_string()),
+        root: root.to_string_lossy().to_string(),
+        checks,
+    })
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct FileInfoQuery {
+    /// Also compute an MD5 of the file contents
+    #[serde(default)]
+    hash: bool,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct FileInfo {
+    path: String,
+    absolute_path: String,
+    name: String,
+    size: u64,
+    last_modified: Option<DateTime<Utc>>,
+    /// Creation time where the platform records it, otherwise the inode change time on Unix
+    created: Option<DateTime<Utc>>,
+    mime_type: String,
+    readonly: bool,
+    /// Unix permission bits, e.g. `0o644`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    md5: Option<String>,
+}
+
+#[cfg(unix)]
+fn changed_time(metadata: &fs::Metadata) -> Option<DateTime<Utc>> {
+    use std::os::unix::fs::MetadataExt;
+    DateTime::from_timestamp(metadata.ctime(), metadata.ctime_nsec() as u32)
+}
+
+#[cfg(not(unix))]
+fn changed_time(_metadata: &fs::Metadata) -> Option<DateTime<Utc>> {
+    None
+}
+
+#[cfg(unix)]
+fn permission_mode(metadata: &fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(format!("{:#o}", metadata.permissions().mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn permission_mode(_metadata: &fs::Metadata) -> Option<String> {
+    None
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/file-info/{path}", params(("path" = String, Path, description = "Path relative to the selected directory"), FileInfoQuery), responses((status = 200, body = FileInfo), (status = 403), (status = 404), (status = 504))))]
+async fn file_info(
+    Path(file_path): Path<String>,
+    Query(query): Query<FileInfoQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== File Info Request ===");
+    println!("File info request for: {}", file_path);
+
+    let full_path = {
+        let user_dir = state.user_selected_dir.read().await;
+        match resolve_in_root(&user_dir, &file_path) {
+            Ok(full_path) => full_path,
+            Err(rejection) => return rejection.into_response(),
+        }
+    };
+
+    // Stat live rather than trusting the possibly stale index
+    let metadata = match tokio::fs::metadata(&full_path).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => {
+            println!("Rejected: Path {} is not a file", full_path.display());
+            return Response::builder()
+                .status(404)
+                .body(Body::from("Not a file"))
+                .unwrap();
+        }
+    };
+
+    let md5 = if query.hash {
+        match with_read_timeout(&state, &full_path, tokio::fs::read(&full_path)).await {
+            Ok(contents) => Some(format!("{:x}", md5::compute(contents))),
+            Err(e) => {
+                println!("Error reading file {}: {}", full_path.display(), e);
+                return Response::builder()
+                    .status(read_error_status(&e))
+                    .body(Body::from(format!("Error reading file: {}", e)))
+                    .unwrap();
+            }
+        }
+    } else {
+        None
+    };
+
+    let absolute_path = full_path.canonicalize().unwrap_or_else(|_| full_path.clone());
+
+    Json(FileInfo {
+        path: file_path.trim_start_matches('/').to_string(),
+        absolute_path: absolute_path.to_string_lossy().to_string(),
+        name: full_path.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        size: metadata.len(),
+        last_modified: metadata.modified().ok().map(DateTime::from),
+        created: metadata.created().ok().map(DateTime::from).or_else(|| changed_time(&metadata)),
+        mime_type: mime_guess::from_path(&full_path).first_or_octet_stream().to_string(),
+        readonly: metadata.permissions().readonly(),
+        mode: permission_mode(&metadata),
+        md5,
+    })
+    .into_response()
+}
+
+/// Largest file the preview endpoints will render; rendering big inputs is slow.
+const PREVIEW_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Reads `file_path` under the selected directory as UTF-8 text for one of
+/// the preview endpoints, refusing directories, binary and oversized files.
+async fn read_preview_text(state: &AppState, file_path: &str) -> Result<(PathBuf, String), (StatusCode, String)> {
+    let full_path = {
+        let user_dir = state.user_selected_dir.read().await;
+        resolve_in_root(&user_dir, file_path)
+            .map_err(|(status, message)| (status, message.to_string()))?
+    };
+
+    match tokio::fs::metadata(&full_path).await {
+        Ok(metadata) if metadata.is_file() && metadata.len() > PREVIEW_MAX_BYTES => {
+            println!("Rejected: {} is too large to preview ({} bytes)", full_path.display(), metadata.len());
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("File is larger than {} bytes", PREVIEW_MAX_BYTES)));
+        }
+        Ok(metadata) if metadata.is_file() => {}
+        Ok(metadata) if metadata.is_dir() => {
+            println!("Rejected: Path {} is a directory", full_path.display());
+            return Err((StatusCode::BAD_REQUEST, "Cannot preview a directory".to_string()));
+        }
+        _ => {
+            println!("Rejected: Path {} is not a file", full_path.display());
+            return Err((StatusCode::NOT_FOUND, "Not a file".to_string()));
+        }
+    }
+
+    let contents = match with_read_timeout(state, &full_path, tokio::fs::read(&full_path)).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Error reading file {}: {}", full_path.display(), e);
+            return Err((read_error_status(&e), format!("Error reading file: {}", e)));
+        }
+    };
+    match String::from_utf8(contents) {
+        Ok(text) => Ok((full_path, text)),
+        Err(_) => {
+            println!("Rejected: {} is not UTF-8 text", full_path.display());
+            Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, "Not a text file".to_string()))
+        }
+    }
+}
+
+/// Syntax definitions and theme for `/highlight`, loaded once at startup.
+struct Highlighter {
+    syntaxes: syntect::parsing::SyntaxSet,
+    theme: syntect::highlighting::Theme,
+}
+
+impl Highlighter {
+    fn load() -> Self {
+        let mut themes = syntect::highlighting::ThemeSet::load_defaults();
+        Highlighter {
+            syntaxes: syntect::parsing::SyntaxSet::load_defaults_newlines(),
+            theme: themes.themes.remove("InspiredGitHub").unwrap_or_default(),
+        }
+    }
+
+    /// Renders `text` as HTML, picking the language from the file name or,
+    /// failing that, a shebang or modeline. Returns the language name too.
+    fn highlight(&self, text: &str, file_name: &str) -> Result<Highlighted, syntect::Error> {
+        let extension = file_name.rsplit_once('.').map_or(file_name, |(_, ext)| ext);
+        let syntax = self.syntaxes.find_syntax_by_extension(extension)
+            .or_else(|| self.syntaxes.find_syntax_by_first_line(text))
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+        Ok(Highlighted {
+            language: syntax.name.clone(),
+            html: syntect::html::highlighted_html_for_string(text, &self.syntaxes, syntax, &self.theme)?,
+        })
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct Highlighted {
+    language: String,
+    /// A self-contained `<pre>` block with inline styles
+    html: String,
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/highlight/{path}", params(("path" = String, Path, description = "Path relative to the selected directory")), responses((status = 200, body = Highlighted), (status = 400), (status = 403), (status = 404), (status = 413), (status = 415), (status = 504))))]
+async fn highlight_file(
+    Path(file_path): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== Highlight Request ===");
+    println!("Highlight request for: {}", file_path);
+
+    let (full_path, text) = match read_preview_text(&state, &file_path).await {
+        Ok(read) => read,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let file_name = full_path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let highlighter = state.highlighter.clone();
+    match tokio::task::spawn_blocking(move || highlighter.highlight(&text, &file_name)).await {
+        Ok(Ok(highlighted)) => Json(highlighted).into_response(),
+        Ok(Err(e)) => {
+            println!("Error highlighting {}: {}", full_path.display(), e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error highlighting file: {}", e)).into_response()
+        }
+        Err(e) => {
+            println!("Highlighting task failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct MarkdownPreview {
+    /// Whether `html` was rendered from markdown; otherwise it is the file's
+    /// text, escaped inside a `<pre>` block
+    rendered: bool,
+    html: String,
+}
+
+/// Renders markdown to HTML, then sanitizes it: markdown passes raw HTML
+/// through, so an indexed file could otherwise smuggle in scripts.
+fn render_markdown(text: &str) -> String {
+    use pulldown_cmark::{html, Options, Parser};
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(text, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS));
+    ammonia::Builder::default()
+        .url_schemes(["http", "https", "mailto"].into())
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/render-markdown/{path}", params(("path" = String, Path, description = "Path relative to the selected directory")), responses((status = 200, body = MarkdownPreview), (status = 400), (status = 403), (status = 404), (status = 413), (status = 415), (status = 504))))]
+async fn render_markdown_file(
+    Path(file_path): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    println!("\n=== Markdown Preview Request ===");
+    println!("Markdown preview request for: {}", file_path);
+
+    let (full_path, text) = match read_preview_text(&state, &file_path).await {
+        Ok(read) => read,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let is_markdown = full_path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| ext == "md" || ext == "markdown");
+    let preview = if is_markdown {
+        match tokio::task::spawn_blocking(move || render_markdown(&text)).await {
+            Ok(html) => MarkdownPreview { rendered: true, html },
+            Err(e) => {
+                println!("Markdown rendering task failed: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    } else {
+        MarkdownPreview {
+            rendered: false,
+            html: format!("<pre>{}</pre>", escape_html(&text)),
+        }
+    };
+    Json(preview).into_response()
+}
+
+/// Serves a file stored inside an archive under the selected directory.
+async fn download_archive_member(
+    root: &std::path::Path,
+    archive: &str,
+    inner: String,
+    limits: ArchiveLimits,
+    inline: bool,
+    state: &AppState,
+    permit: tokio::sync::OwnedSemaphorePermit,
+) -> Response {
+    let archive_path = match resolve_in_root(root, archive) {
+        Ok(archive_path) => archive_path,
+        Err(rejection) => return rejection.into_response(),
+    };
+    println!("Extracting {} from archive {}", inner, archive_path.display());
+
+    let filename = inner.rsplit('/').next().unwrap_or("download").to_string();
+    let extract = {
+        let archive_path = archive_path.clone();
+        async move {
+            tokio::task::spawn_blocking(move || extract_from_archive(&archive_path, &inner, &limits))
+                .await
+                .unwrap_or_else(|e| Err(io::Error::other(e)))
+        }
+    };
+    let extracted = with_read_timeout(state, &archive_path, extract).await;
+
+    match extracted {
+        Ok(contents) => {
+            println!("Successfully extracted: {} ({} bytes)", filename, contents.len());
+            metrics::counter!("downloads_total").increment(1);
+            download_response(&filename, inline, &state.args)
+                .body(download_body(contents, permit))
+                .unwrap()
+        }
+        Err(e) => {
+            println!("Error extracting {}: {}", filename, e);
+            metrics::counter!("errors_total", "kind" => "download").increment(1);
+            Response::builder()
+                .status(read_error_status(&e))
+                .body(Body::from(format!("Error reading file: {}", e)))
+                .unwrap()
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct ChangePathRequest {
+    path: String,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct TagsRequest {
+    /// Indexed path, relative to the selected directory
+    path: String,
+    tags: Vec<String>,
+    /// Add to the path's tags instead of replacing them
+    #[serde(default)]
+    add: bool,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct TaggedPath {
+    path: String,
+    tags: Vec<String>,
+}
+
+/// Sets or adds tags on an indexed path of the selected directory. Tags are
+/// trimmed, deduplicated and sorted; an empty list clears them.
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/tags", request_body = TagsRequest, responses((status = 200, body = TaggedPath), (status = 404, body = String), (status = 500, body = String))))]
+async fn set_tags(
+    State(state): State<AppState>,
+    Json(req): Json<TagsRequest>,
+) -> Response {
+    println!("\n=== Setting Tags ===");
+    let root = state.user_selected_dir.read().await.clone();
+    let root_path = root.to_string_lossy().to_string();
+    let path = req.path.trim_start_matches('/').to_string();
+
+    let mut indices = state.indices.write().await;
+    let Some(entry) = indices.get_mut(&root_path).and_then(|index| index.iter_mut().find(|entry| entry.path == path)) else {
+        println!("Rejected tags for unindexed path: {}", path);
+        return (StatusCode::NOT_FOUND, Json(format!("Not in the index: {}", path))).into_response();
+    };
+
+    let mut tags: Vec<String> = match req.add {
+        true => entry.tags.clone(),
+        false => Vec::new(),
+    };
+    tags.extend(normalize_tags(&req.tags));
+    tags.sort();
+    tags.dedup();
+    println!("Tags for {}: {:?}", path, tags);
+
+    entry.tags = tags.clone();
+    let file_tags = {
+        let mut file_tags = state.tags.write().await;
+        file_tags.set(&root.join(&path), tags.clone());
+        file_tags.clone()
+    };
+    drop(indices);
+    state.search_cache.write().await.invalidate_root(&root_path);
+    if let Err(e) = save_tags_blocking(file_tags).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(format!("Error saving tags: {}", e))).into_response();
+    }
+
+    Json(TaggedPath { path, tags }).into_response()
+}
+
+/// `tags` trimmed, without blank ones.
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    tags.iter().map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect()
+}
+
+/// Most files one `/tags/bulk` request changes; further matches are left
+/// alone and the response says so.
+const BULK_TAGS_MAX_FILES: usize = 10_000;
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum TagAction {
+    Add,
+    Remove,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct BulkTagsRequest {
+    /// Search whose results are tagged, as for `POST /search`; `q` must not
+    /// be blank
+    query: SearchQuery,
+    tags: Vec<String>,
+    action: TagAction,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct BulkTagsResult {
+    /// Files the search matched
+    matched: usize,
+    /// Files whose tags changed; ones that already had (or lacked) the tags
+    /// aren't counted
+    affected: usize,
+    /// Whether more than `BULK_TAGS_MAX_FILES` matched, so only the best
+    /// ranked ones were considered
+    truncated: bool,
+}
+
+/// Adds or removes tags on every file a search matches, across all roots
+/// with `all_roots`. Removal ignores case, like the `tag:` filter.
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/tags/bulk", request_body = BulkTagsRequest, responses((status = 200, body = BulkTagsResult), (status = 400, body = String), (status = 500, body = String))))]
+async fn bulk_tags(
+    State(state): State<AppState>,
+    Json(req): Json<BulkTagsRequest>,
+) -> Response {
+    println!("\n=== Bulk Tagging ===");
+    if req.query.q.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, Json("A search query is required".to_string())).into_response();
+    }
+    let wanted = normalize_tags(&req.tags);
+    if wanted.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json("No tags given".to_string())).into_response();
+    }
+
+    // Every match as a flat list, however the query asked for results
+    let query = SearchQuery {
+        group: None,
+        group_by_name: false,
+        collapse_case: false,
+        format: ResultFormat::Flat,
+        fields: None,
+        limit: None,
+        cursor: None,
+        ..req.query
+    };
+    let selected = state.user_selected_dir.read().await.to_string_lossy().to_string();
+    let files = run_search(&state, query).await.files;
+    let matched = files.len();
+    let truncated = matched > BULK_TAGS_MAX_FILES;
+    let mut targets: HashMap<String, std::collections::HashSet<String>> = HashMap::new();
+    for entry in files.into_iter().take(BULK_TAGS_MAX_FILES) {
+        targets.entry(entry.root.unwrap_or_else(|| selected.clone())).or_default().insert(entry.path);
+    }
+
+    let mut indices = state.indices.write().await;
+    let mut file_tags = state.tags.write().await;
+    let mut affected = 0;
+    for (root, paths) in &targets {
+        let Some(index) = indices.get_mut(root) else {
+            continue;
+        };
+        for entry in index.iter_mut().filter(|entry| paths.contains(&entry.path)) {
+            let mut tags = entry.tags.clone();
+            match req.action {
+                TagAction::Add => tags.extend(wanted.iter().cloned()),
+                TagAction::Remove => tags.retain(|tag| !wanted.iter().any(|unwanted| unwanted.eq_ignore_ascii_case(tag))),
+            }
+            tags.sort();
+            tags.dedup();
+            if tags != entry.tags {
+                file_tags.set(&std::path::Path::new(root).join(&entry.path), tags.clone());
+                entry.tags = tags;
+                affected += 1;
+            }
+        }
+    }
+    println!("Changed tags on {} of {} matching files{}", affected, matched, if truncated { " (truncated)" } else { "" });
+    let changed_tags = (affected > 0).then(|| file_tags.clone());
+    drop(file_tags);
+    drop(indices);
+    let mut search_cache = state.search_cache.write().await;
+    targets.keys().for_each(|root| search_cache.invalidate_root(root));
+    drop(search_cache);
+    let saved = match changed_tags {
+        Some(file_tags) => save_tags_blocking(file_tags).await,
+        None => Ok(()),
+    };
+    if let Err(e) = saved {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(format!("Error saving tags: {}", e))).into_response();
+    }
+
+    Json(BulkTagsResult { matched, affected, truncated }).into_response()
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct BookmarkRequest {
+    path: String,
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/bookmarks", responses((status = 200, body = Vec<PathConfig>))))]
+async fn get_bookmarks(State(state): State<AppState>) -> Json<Vec<PathConfig>> {
+    let config = state.config.read().await;
+    Json(config.bookmarks.clone())
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/bookmarks", request_body = BookmarkRequest, responses((status = 200, body = Vec<PathConfig>), (status = 400, body = String))))]
+async fn add_bookmark(
+    State(state): State<AppState>,
+    Json(req): Json<BookmarkRequest>,
+) -> Response {
+    println!("\n=== Adding Bookmark ===");
+    if !std::path::Path::new(&req.path).is_dir() {
+        println!("Rejected bookmark for missing directory: {}", req.path);
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(format!("Directory does not exist: {}", req.path)),
+        ).into_response();
+    }
+
+    let mut config = state.config.write().await;
+    if config.add_bookmark(req.path.clone()) {
+        println!("Bookmarked: {}", req.path);
+        if let Err(e) = config.save() {
+            return Json(format!("Error saving config: {}", e)).into_response();
+        }
+    }
+    Json(config.bookmarks.clone()).into_response()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(delete, path = "/bookmarks", request_body = BookmarkRequest, responses((status = 200, body = Vec<PathConfig>), (status = 404, body = String))))]
+async fn remove_bookmark(
+    State(state): State<AppState>,
+    Json(req): Json<BookmarkRequest>,
+) -> Response {
+    println!("\n=== Removing Bookmark ===");
+    let mut config = state.config.write().await;
+    if !config.remove_bookmark(&req.path) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(format!("Not bookmarked: {}", req.path)),
+        ).into_response();
+    }
+
+    println!("Removed bookmark: {}", req.path);
+    if let Err(e) = config.save() {
+        return Json(format!("Error saving config: {}", e)).into_response();
+    }
+    Json(config.bookmarks.clone()).into_response()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/config/search-defaults", request_body = SearchDefaults, responses((status = 200, body = SearchDefaults))))]
+async fn set_search_defaults(
+    State(state): State<AppState>,
+    Json(defaults): Json<SearchDefaults>,
+) -> Response {
+    println!("\n=== Updating Search Defaults ===");
+    println!("New defaults: {:?}", defaults);
+    let mut config = state.config.write().await;
+    config.default_search = defaults;
+    if let Err(e) = config.save() {
+        return Json(format!("Error saving config: {}", e)).into_response();
+    }
+    Json(config.default_search).into_response()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/config/scoring", request_body = ScoringWeights, responses((status = 200, body = ScoringWeights))))]
+async fn set_scoring_weights(
+    State(state): State<AppState>,
+    Json(weights): Json<ScoringWeights>,
+) -> Response {
+    println!("\n=== Updating Scoring Weights ===");
+    println!("New weights: {:?}", weights);
+    let mut config = state.config.write().await;
+    config.scoring = weights;
+    if let Err(e) = config.save() {
+        return Json(format!("Error saving config: {}", e)).into_response();
+    }
+    Json(config.scoring).into_response()
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/recent-paths", responses((status = 200, body = Vec<PathConfig>))))]
+async fn get_recent_paths(State(state): State<AppState>) -> Json<Vec<PathConfig>> {
+    let config = state.config.read().await;
+    Json(config.recent_paths.clone())
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/change-path", request_body = ChangePathRequest, responses((status = 200, body = IndexStatus))))]
+async fn change_path(
+    State(state): State<AppState>,
+    Json(req): Json<ChangePathRequest>,
+) -> Json<IndexStatus> {
+    println!("\n=== Changing Path ===");
+    println!("Request to change path to: {}", req.path);
+    
+    // Update the root path in the existing state
+    let new_path = normalize_root(std::path::Path::new(&req.path));
+    let root_path = new_path.to_string_lossy().to_string();
+    *state.user_selected_dir.write().await = new_path.clone();
+    println!("Updated selected directory to: {}", state.user_selected_dir.read().await.display());
+    
+    // Reuse the index if it's still in memory, otherwise load it from disk
+    let in_memory = state.indices.read().await.get(&root_path).map(Vec::len);
+    let loaded_len = match in_memory {
+        Some(len) => {
+            println!("Using in-memory index with {} entries", len);
+            len
+        }
+        None => {
+            let mut loaded_index = IndexEntry::load_index(&new_path).unwrap_or_else(|e| {
+                println!("Could not load existing index for {}: {}", new_path.display(), e);
+                Vec::new()
+            });
+            state.tags.read().await.apply(&root_path, &mut loaded_index);
+            let len = loaded_index.len();
+            state.indices.write().await.insert(root_path.clone(), loaded_index);
+            state.search_cache.write().await.invalidate_root(&root_path);
+            println!("Loaded existing index with {} entries", len);
+            len
+        }
+    };
+    touch_index(&state, &root_path).await;
+    unload_least_recently_used(&state).await;
+    
+    // Update config with new path
+    {
+        let mut config = state.config.write().await;
+        config.add_path(root_path.clone(), loaded_len);
+        config.last_root = Some(root_path.clone());
+        let _ = config.save();
+        println!("Updated config with new path");
+    }
+
+    // Return current index status
+    Json(IndexStatus {
+        total_files: state.indices.read().await.get(&root_path).map(|idx| idx.len()).unwrap_or(0),
+        last_updated: Utc::now(),
+        root_path: state.user_selected_dir.read().await.to_string_lossy().to_string(),
+        cancelled: false,
+        skipped: SkippedEntries::default(),
+        save_error: None,
+    })
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/current-path", responses((status = 200, description = "`{\"path\": string, \"read_only\": bool, \"editor_link\": bool, \"next_reindex\": string | null}`; `editor_link` says whether `/editor-link` is configured"))))]
+async fn get_current_path(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let current_path = state.user_selected_dir.read().await.to_string_lossy().to_string();
+    let config = state.config.read().await;
+    let next_reindex = config.path_config(&current_path).and_then(PathConfig::next_reindex);
+    Json(serde_json::json!({
+        "path": current_path,
+        "read_only": state.args.read_only,
+        "editor_link": config.editor_link_template.is_some(),
+        "next_reindex": next_reindex,
+    }))
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Deserialize)]
+struct ReindexScheduleRequest {
+    /// Directory to schedule; defaults to the selected directory
+    path: Option<String>,
+    /// Minutes between automatic reindexes; zero disables them
+    interval_minutes: u64,
 }
 
-async fn create_index(State(state): State<AppState>) -> Json<IndexStatus> {
-    println!("\n=== Creating Index ===");
-    let user_selected_dir = state.user_selected_dir.read().await.clone();
-    println!("Creating index for directory: {}", user_selected_dir.display());
-    let start_time = std::time::Instant::now();
-    
-    let mut new_index = Vec::new();
-    for entry in WalkDir::new(&user_selected_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
-        if let Ok(metadata) = entry.metadata() {
-            let full_path = entry.path();
-            let path = entry.path().strip_prefix(&user_selected_dir)
-                .unwrap_or(entry.path())
-                .to_string_lossy()
-                .to_string();
-            
-            println!("Indexing file: {} (relative path: {})", full_path.display(), path);
-            
-            new_index.push(IndexEntry {
-                path: path.clone(),
-                name: entry.file_name().to_string_lossy().to_string(),
-                last_modified: metadata.modified()
-                    .unwrap_or_else(|_| std::time::SystemTime::now())
-                    .into(),
-                size: metadata.len(),
-            });
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct ReindexSchedule {
+    path: String,
+    interval_minutes: Option<u64>,
+    next_reindex: Option<DateTime<Utc>>,
+}
+
+impl ReindexSchedule {
+    fn for_path(config: &Config, path: String) -> Self {
+        let path_config = config.path_config(&path);
+        ReindexSchedule {
+            interval_minutes: path_config.and_then(|p| p.reindex_interval_minutes),
+            next_reindex: path_config.and_then(PathConfig::next_reindex),
+            path,
         }
     }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct ReindexScheduleQuery {
+    /// Directory to look up; defaults to the selected directory
+    path: Option<String>,
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/config/reindex-schedule", params(ReindexScheduleQuery), responses((status = 200, body = ReindexSchedule))))]
+async fn get_reindex_schedule(
+    Query(query): Query<ReindexScheduleQuery>,
+    State(state): State<AppState>,
+) -> Json<ReindexSchedule> {
+    let path = match query.path {
+        Some(path) => path,
+        None => state.user_selected_dir.read().await.to_string_lossy().to_string(),
+    };
+    Json(ReindexSchedule::for_path(&*state.config.read().await, path))
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/config/reindex-schedule", request_body = ReindexScheduleRequest, responses((status = 200, body = ReindexSchedule), (status = 400, body = String), (status = 500, body = String))))]
+async fn set_reindex_schedule(
+    State(state): State<AppState>,
+    Json(req): Json<ReindexScheduleRequest>,
+) -> Response {
+    println!("\n=== Updating Reindex Schedule ===");
+    let path = match req.path {
+        Some(path) => normalize_root(std::path::Path::new(&path)),
+        None => state.user_selected_dir.read().await.clone(),
+    };
+    if !path.is_dir() {
+        println!("Rejected: Path {} is not a directory", path.display());
+        return (StatusCode::BAD_REQUEST, Json(format!("Not a directory: {}", path.display()))).into_response();
+    }
+    let path = path.to_string_lossy().to_string();
+    let interval = (req.interval_minutes > 0).then_some(req.interval_minutes);
+    println!("Reindex interval for {}: {:?} minutes", path, interval);
 
-    // Update the indices map with the new index
+    let mut config = state.config.write().await;
+    if config.path_config(&path).is_none() {
+        config.add_path(path.clone(), 0);
+    }
+    for path_config in config.path_configs_mut(&path) {
+        path_config.reindex_interval_minutes = interval;
+    }
+    if let Err(e) = config.save() {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(format!("Error saving config: {}", e)),
+        ).into_response();
+    }
+    Json(ReindexSchedule::for_path(&config, path)).into_response()
+}
+
+/// How often the background task checks whether the active root is due for
+/// a scheduled reindex.
+const REINDEX_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Brings the selected directory's index up to date when its reindex
+/// interval has elapsed, merging a fresh walk into it like `/reindex-subtree`
+/// does for the whole root. Skipped while any other index run is in
+/// progress; one started meanwhile cancels it.
+async fn run_scheduled_reindex(state: &AppState) {
+    let root = state.user_selected_dir.read().await.clone();
+    let due = state.config.read().await
+        .path_config(&root.to_string_lossy())
+        .and_then(PathConfig::next_reindex)
+        .is_some_and(|next| next <= Utc::now());
+    if !due {
+        return;
+    }
+    // Checked and claimed under one guard so a manual run can't slip in between
+    let cancel = Arc::new(CancellationToken::new());
     {
-        let mut indices = state.indices.write().await;
-        indices.insert(user_selected_dir.to_string_lossy().to_string(), new_index.clone());
-        println!("Index updated with {} files", new_index.len());
-        println!("Indexing completed in {:.2?}", start_time.elapsed());
+        let mut index_cancel = state.index_cancel.write().await;
+        if index_cancel.is_some() {
+            println!("Scheduled reindex of {} postponed: indexing already in progress", root.display());
+            return;
+        }
+        *index_cancel = Some(cancel.clone());
+    }
+    println!("\n=== Scheduled Reindex ===");
+    println!("Reindexing {}", root.display());
+    metrics::counter!("scheduled_reindex_total").increment(1);
+    let result = merge_subtree(state, root.clone(), root.clone(), &cancel).await;
+    {
+        let mut index_cancel = state.index_cancel.write().await;
+        if index_cancel.as_ref().is_some_and(|token| Arc::ptr_eq(token, &cancel)) {
+            *index_cancel = None;
+        }
     }
 
-    let status = IndexStatus {
-        total_files: new_index.len(),
-        last_updated: Utc::now(),
-        root_path: user_selected_dir.to_string_lossy().to_string(),
+    // Record the run so the schedule counts from now
+    if let Some(result) = result {
+        let mut config = state.config.write().await;
+        config.mark_indexed(&root.to_string_lossy(), result.total_files);
+        if let Err(e) = config.save() {
+            println!("Error saving config: {}", e);
+        }
+    }
+}
+
+/// How often the server checks whether `--idle-timeout` has passed.
+const IDLE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Resolves once no request has arrived for `--idle-timeout` minutes, after
+/// saving the config, tags and access counts. Waits for an index run in
+/// progress to finish first. Never resolves without `--idle-timeout`.
+async fn idle_shutdown(state: AppState) {
+    let Some(minutes) = state.args.idle_timeout else {
+        return std::future::pending().await;
     };
+    let timeout_ms = i64::try_from(minutes).unwrap_or(i64::MAX / 60_000).saturating_mul(60_000);
+    let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let last_request = state.last_request.load(std::sync::atomic::Ordering::Relaxed);
+        if Utc::now().timestamp_millis() - last_request >= timeout_ms && state.index_cancel.read().await.is_none() {
+            break;
+        }
+    }
 
-    // Save the index to disk
-    if let Err(e) = IndexEntry::save_index(&new_index, &user_selected_dir) {
-        println!("Error saving index: {}", e);
-    } else {
-        println!("Index saved successfully");
+    println!("\n=== Idle Shutdown ===");
+    println!("No requests within the {} min idle timeout; shutting down", minutes);
+    if let Err(e) = state.config.read().await.save() {
+        println!("Error saving config: {}", e);
+    }
+    if let Err(e) = state.tags.read().await.save() {
+        println!("Error saving tags: {}", e);
+    }
+    if let Err(e) = state.access_log.read().await.save() {
+        println!("Error saving access counts: {}", e);
     }
+}
 
-    Json(status)
+/// Notes the time when each request starts and finishes, for `--idle-timeout`.
+async fn record_activity(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    use std::sync::atomic::Ordering;
+    state.last_request.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    let response = next.run(request).await;
+    state.last_request.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    response
 }
 
-async fn search(
-    Query(query): Query<SearchQuery>,
+/// Refuses the request when the server runs with `--read-only`.
+async fn reject_if_read_only(
     State(state): State<AppState>,
-) -> Json<SearchResult> {
-    println!("\n=== Search Request ===");
-    println!("Search query: {}", query.q);
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    if state.args.read_only {
+        println!("Rejected {} {}: server is read-only", request.method(), request.uri().path());
+        return (StatusCode::FORBIDDEN, "Server is in read-only mode").into_response();
+    }
+    next.run(request).await
+}
+
+/// Every API route, with those that change state behind `reject_if_read_only`.
+fn api_routes(state: &AppState) -> Router<AppState> {
+    // Everything that changes indices, config or the selected directory;
+    // refused with --read-only
+    let mutating = Router::new()
+        .route("/create-index", post(create_index))
+        .route("/cancel-index", post(cancel_index))
+        .route("/ws/index", get(index_socket))
+        .route("/reindex-subtree", post(reindex_subtree))
+        .route("/refresh-metadata", post(refresh_metadata))
+        .route("/rename-apply", post(rename_apply))
+        .route("/bookmarks", post(add_bookmark).delete(remove_bookmark))
+        .route("/rekey-index", post(rekey_index))
+        .route("/tags", post(set_tags))
+        .route("/tags/bulk", post(bulk_tags))
+        .route("/config/search-defaults", post(set_search_defaults))
+        .route("/config/scoring", post(set_scoring_weights))
+        .route("/config/reindex-schedule", post(set_reindex_schedule))
+        .route("/change-path", post(change_path))
+        .route("/purge-indices", post(purge_indices))
+        .route("/prune-indices", post(prune_indices))
+        .route("/clear-recent-paths", post(clear_recent_paths))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), reject_if_read_only));
+
+    Router::new()
+        .route("/search", get(search).post(search_post))
+        .route("/search-dirs", get(search_dirs))
+        .route("/largest-files", get(largest_files))
+        .route("/by-hash/:hash", get(files_by_hash))
+        .route("/new-files", get(new_files))
+        .route("/search/stream", get(search_stream))
+        .route("/search/export", get(search_export))
+        .route("/search/html", get(search_html))
+        .route("/download/*path", get(download_file))
+        .route("/prefetch", post(prefetch))
+        .route("/abs-path/*path", get(absolute_path))
+        .route("/highlight/*path", get(highlight_file))
+        .route("/render-markdown/*path", get(render_markdown_file))
+        .route("/editor-link/*path", get(editor_link))
+        .route("/file-info/*path", get(file_info))
+        .route("/frequent-files", get(frequent_files))
+        .route("/metrics", get(prometheus_metrics))
+        .route("/health", get(health))
+        .route("/diagnostics", get(diagnostics))
+        .route("/stats", get(stats))
+        .route("/export/csv", get(export_csv))
+        .route("/verify-index", post(verify_index))
+        .route("/diff-indices", post(diff_indices))
+        .route("/rename-preview", post(rename_preview))
+        .route("/recent-paths", get(get_recent_paths))
+        .route("/bookmarks", get(get_bookmarks))
+        .route("/list-directories/:path", get(list_directories))
+        .route("/current-path", get(get_current_path))
+        .route("/config/reindex-schedule", get(get_reindex_schedule))
+        .merge(mutating)
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/clear-recent-paths", responses((status = 200, body = String))))]
+async fn clear_recent_paths(State(state): State<AppState>) -> Json<String> {
+    // Clear the recent paths while keeping the current directory
+    let current_dir = state.user_selected_dir.read().await.clone();
+    let current_dir_str = current_dir.to_string_lossy().to_string();
     
-    let matcher = SkimMatcherV2::default();
-    let indices = state.indices.read().await;
+    // Reset the config with empty recent paths
+    let mut config = state.config.write().await;
+    config.recent_paths.clear();
     
-    // Get the current path's index
-    let current_path = state.user_selected_dir.read().await.to_string_lossy().to_string();
-    let empty_vec = Vec::new();
-    let index = indices.get(&current_path).unwrap_or(&empty_vec);
+    // Add back only the current directory
+    if let Ok(metadata) = std::fs::metadata(&current_dir) {
+        if metadata.is_dir() {
+            config.add_path(current_dir_str, 0);
+        }
+    }
     
-    let mut matches: Vec<(i64, IndexEntry)> = index.iter()
-        .filter_map(|entry| {
-            matcher.fuzzy_match(&entry.path, &query.q)
-                .map(|score| (score, entry.clone()))
-        })
+    // Save the updated config
+    if let Err(e) = config.save() {
+        return Json(format!("Error saving config: {}", e));
+    }
+    
+    Json("Recent paths cleared successfully".to_string())
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/purge-indices", responses((status = 200, body = String))))]
+async fn purge_indices() -> Json<String> {
+    if let Ok(index_dir) = get_index_dir() {
+        if let Err(e) = fs::remove_dir_all(&index_dir) {
+            return Json(format!("Error purging indices: {}", e));
+        }
+    }
+    Json("All indices purged successfully".to_string())
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct PruneResult {
+    removed: usize,
+    bytes_reclaimed: u64,
+}
+
+/// How often the background task prunes stale index files.
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Deletes index files that belong to no recent path and haven't been written
+/// within the retention period. The index for the active root is always kept,
+/// and so are checkpoints while an index run is in progress, as it may be
+/// writing one for a root that isn't selected any more.
+async fn prune_stale_indices(state: &AppState) -> io::Result<PruneResult> {
+    let mut keep: std::collections::HashSet<String> = state.config.read().await
+        .recent_paths
+        .iter()
+        .map(|p| index_file_stem(&p.path))
         .collect();
+    keep.extend(state.config.read().await.bookmarks.iter().map(|p| index_file_stem(&p.path)));
+    keep.insert(index_file_stem(&state.user_selected_dir.read().await.to_string_lossy()));
+    let run_active = state.index_cancel.read().await.is_some();
 
-    // Sort by score descending
-    matches.sort_by(|a, b| b.0.cmp(&a.0));
+    let cutoff = Utc::now() - chrono::Duration::days(state.args.index_retention_days);
+    let mut result = PruneResult { removed: 0, bytes_reclaimed: 0 };
 
-    let results: Vec<IndexEntry> = matches.into_iter().map(|(_, entry)| entry).collect();
-    println!("Found {} matching files", results.len());
+    let index_dir = get_index_dir()?;
+    if !index_dir.exists() {
+        return Ok(result);
+    }
 
-    Json(SearchResult {
-        files: results
-    })
-}
+    for entry in fs::read_dir(&index_dir)?.filter_map(|e| e.ok()) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let stem = file_name.split('.').next().unwrap_or_default();
+        if !file_name.starts_with("index_") || keep.contains(stem) {
+            continue;
+        }
+        if run_active && file_name.starts_with(&format!("{}.partial.", stem)) {
+            continue;
+        }
 
-async fn download_file(
-    Path(file_path): Path<String>,
-    State(state): State<AppState>,
-) -> Response {
-    println!("\n=== Download Request ===");
-    println!("Download request for file: {}", file_path);
-    println!("Selected directory is: {}", state.user_selected_dir.read().await.display());
+        let Ok(metadata) = entry.metadata() else { continue };
+        let modified: DateTime<Utc> = metadata.modified()
+            .unwrap_or_else(|_| std::time::SystemTime::now())
+            .into();
+        if modified > cutoff {
+            continue;
+        }
 
-    // Clean the file path and convert to PathBuf
-    let file_path = PathBuf::from(file_path.trim_start_matches('/'));
-    if file_path.components().any(|c| matches!(c, std::path::Component::ParentDir)) {
-        println!("Rejected due to parent directory traversal attempt");
-        return Response::builder()
-            .status(403)
-            .body(Body::from("Invalid path"))
-            .unwrap();
+        match fs::remove_file(entry.path()) {
+            Ok(()) => {
+                println!("Pruned stale index: {}", file_name);
+                result.removed += 1;
+                result.bytes_reclaimed += metadata.len();
+            }
+            Err(e) => println!("Error pruning index {}: {}", file_name, e),
+        }
     }
 
-    let full_path = state.user_selected_dir.read().await.join(&file_path);
-    println!("Full path constructed: {}", full_path.display());
-    
-    // Additional check to ensure we're only serving files within root_path
-    let user_dir = state.user_selected_dir.read().await;
-    if !full_path.starts_with(&*user_dir) {
-        println!("Rejected: Path {} is outside selected directory {}", full_path.display(), user_dir.display());
-        return Response::builder()
-            .status(404)
-            .body(Body::from("File path outside root directory"))
-            .unwrap();
+    Ok(result)
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(post, path = "/prune-indices", responses((status = 200, body = PruneResult), (status = 500, body = String))))]
+async fn prune_indices(State(state): State<AppState>) -> Response {
+    println!("\n=== Pruning Indices ===");
+    match prune_stale_indices(&state).await {
+        Ok(result) => {
+            println!("Removed {} index files ({} bytes)", result.removed, result.bytes_reclaimed);
+            Json(result).into_response()
+        }
+        Err(e) => {
+            metrics::counter!("errors_total", "kind" => "prune").increment(1);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Error pruning indices: {}", e)),
+            ).into_response()
+        }
     }
+}
 
-    if !full_path.is_file() {
-        println!("Rejected: Path {} is not a file", full_path.display());
-        return Response::builder()
-            .status(404)
-            .body(Body::from("Not a file"))
-            .unwrap();
+/// Records that `root`'s index was just used, for `unload_least_recently_used`.
+async fn touch_index(state: &AppState, root: &str) {
+    state.index_used.write().await.insert(root.to_string(), Utc::now());
+}
+
+/// Unloads the least recently used indices until all loaded ones together
+/// hold at most `--max-loaded-entries` entries. The selected root's index is
+/// never unloaded, even if it alone is over the cap.
+async fn unload_least_recently_used(state: &AppState) {
+    let Some(max_entries) = state.args.max_loaded_entries else {
+        return;
+    };
+    let selected = state.user_selected_dir.read().await.to_string_lossy().to_string();
+    let mut indices = state.indices.write().await;
+    let mut total: usize = indices.values().map(Vec::len).sum();
+    if total <= max_entries {
+        return;
     }
-    
-    match tokio::fs::read(&full_path).await {
-        Ok(contents) => {
-            let filename = full_path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("download")
-                .to_string();
-            
-            println!("Successfully read file: {} ({} bytes)", filename, contents.len());
-            
-            Response::builder()
-                .header(
-                    header::CONTENT_DISPOSITION,
-                    format!("attachment; filename=\"{}\"", filename),
-                )
-                .header(header::CONTENT_TYPE, "application/octet-stream")
-                .body(Body::from(contents))
-                .unwrap()
+
+    let mut index_used = state.index_used.write().await;
+    // Indices never used since loading sort first
+    let mut candidates: Vec<(String, Option<DateTime<Utc>>)> = indices.keys()
+        .filter(|root| **root != selected)
+        .map(|root| (root.clone(), index_used.get(root).copied()))
+        .collect();
+    candidates.sort_by_key(|(_, used)| *used);
+    let mut unloaded = Vec::new();
+    for (root, _) in candidates {
+        if total <= max_entries {
+            break;
         }
-        Err(e) => {
-            println!("Error reading file {}: {}", full_path.display(), e);
-            Response::builder()
-                .status(404)
-                .body(Body::from(format!("Error reading file: {}", e)))
-                .unwrap()
+        if let Some(index) = indices.remove(&root) {
+            total -= index.len();
+            index_used.remove(&root);
+            println!("Unloaded index for {} ({} entries) to stay under {} loaded entries", root, index.len(), max_entries);
+            unloaded.push(root);
         }
     }
+    drop(index_used);
+    drop(indices);
+    let mut search_cache = state.search_cache.write().await;
+    unloaded.iter().for_each(|root| search_cache.invalidate_root(root));
 }
 
-#[derive(Deserialize)]
-struct ChangePathRequest {
-    path: String,
+/// Loads the saved index of every recent path that isn't in memory yet, one
+/// at a time so startup isn't slowed by all of them reading at once.
+async fn preload_recent_indices(state: &AppState) {
+    let roots: Vec<String> = state.config.read().await.recent_paths.iter()
+        .map(|path_config| path_config.path.clone())
+        .collect();
+    for root in roots {
+        if state.indices.read().await.contains_key(&root) {
+            continue;
+        }
+        let path = PathBuf::from(&root);
+        let has_saved_index = IndexEntry::saved_files(&path).is_ok_and(|files| !files.is_empty());
+        if !has_saved_index {
+            continue;
+        }
+        let loaded = tokio::task::spawn_blocking(move || IndexEntry::load_index(&path)).await;
+        let mut index = match loaded {
+            Ok(Ok(index)) => index,
+            Ok(Err(e)) => {
+                println!("Could not preload index for {}: {}", root, e);
+                continue;
+            }
+            Err(e) => {
+                println!("Preloading index for {} failed: {}", root, e);
+                continue;
+            }
+        };
+        state.tags.read().await.apply(&root, &mut index);
+        let len = index.len();
+        // A switch to this root may have loaded it meanwhile
+        let inserted = match state.indices.write().await.entry(root.clone()) {
+            std::collections::hash_map::Entry::Occupied(_) => false,
+            std::collections::hash_map::Entry::Vacant(slot) => {
+                slot.insert(index);
+                true
+            }
+        };
+        if inserted {
+            state.search_cache.write().await.invalidate_root(&root);
+            println!("Preloaded index for {} with {} entries", root, len);
+            unload_least_recently_used(state).await;
+        }
+    }
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct LoadedIndexStats {
+    root: String,
+    entries: usize,
+    /// Rough bytes the index takes in memory
+    approx_bytes: usize,
+    /// When the index was last selected or searched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_used: Option<DateTime<Utc>>,
+}
+
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct Stats {
+    /// Indices held in memory, most recently used first
+    loaded_indices: Vec<LoadedIndexStats>,
+    total_entries: usize,
+    approx_bytes: usize,
+    /// The `--max-loaded-entries` cap, if set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_loaded_entries: Option<usize>,
+    /// Whether the server runs with `--read-only`
+    read_only: bool,
+    /// When the selected directory is next reindexed, if it has a schedule
+    next_reindex: Option<DateTime<Utc>>,
+}
+
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/stats", responses((status = 200, body = Stats))))]
+async fn stats(State(state): State<AppState>) -> Json<Stats> {
+    let index_used = state.index_used.read().await.clone();
+    let indices = state.indices.read().await;
+    let mut loaded_indices: Vec<LoadedIndexStats> = indices.iter()
+        .map(|(root, index)| LoadedIndexStats {
+            root: root.clone(),
+            entries: index.len(),
+            approx_bytes: index.iter().map(IndexEntry::approx_size).sum(),
+            last_used: index_used.get(root).copied(),
+        })
+        .collect();
+    loaded_indices.sort_by_key(|index| std::cmp::Reverse(index.last_used));
+    drop(indices);
+
+    let current_path = state.user_selected_dir.read().await.to_string_lossy().to_string();
+    let next_reindex = state.config.read().await
+        .path_config(&current_path)
+        .and_then(PathConfig::next_reindex);
+
+    Json(Stats {
+        total_entries: loaded_indices.iter().map(|index| index.entries).sum(),
+        approx_bytes: loaded_indices.iter().map(|index| index.approx_bytes).sum(),
+        loaded_indices,
+        max_loaded_entries: state.args.max_loaded_entries,
+        read_only: state.args.read_only,
+        next_reindex,
+    })
 }
 
-async fn get_recent_paths(State(state): State<AppState>) -> Json<Vec<PathConfig>> {
-    let config = state.config.read().await;
-    Json(config.recent_paths.clone())
+#[cfg(feature = "openapi")]
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    info(title = "Fuzzy Search Webapp"),
+    paths(
+        search, search_post, search_stream, search_export, search_html, search_dirs, largest_files, files_by_hash, new_files, download_file, prefetch, absolute_path, editor_link, file_info, highlight_file, render_markdown_file,
+        frequent_files, prometheus_metrics, health, diagnostics, export_csv, create_index, cancel_index, reindex_subtree, refresh_metadata, rename_preview, rename_apply,
+        verify_index, diff_indices, rekey_index, get_recent_paths, get_bookmarks, add_bookmark, remove_bookmark, set_tags, bulk_tags, set_search_defaults, set_scoring_weights,
+        get_reindex_schedule, set_reindex_schedule, change_path, list_directories, purge_indices, prune_indices, get_current_path,
+        clear_recent_paths, stats,
+    ),
+    components(schemas(IndexEntry))
+)]
+struct ApiDoc;
+
+#[cfg(feature = "openapi")]
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    use utoipa::OpenApi;
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(feature = "openapi")]
+async fn swagger_ui() -> impl IntoResponse {
+    // Swagger UI comes from unpkg, which the default policy wouldn't allow
+    let policy = "default-src 'self'; script-src 'self' 'unsafe-inline' https://unpkg.com; style-src 'self' 'unsafe-inline' https://unpkg.com; img-src 'self' data: https:";
+    ([(header::CONTENT_SECURITY_POLICY, policy)], Html(r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            <title>Fuzzy File Search API</title>
+            <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+        </head>
+        <body>
+            <div id="swagger-ui"></div>
+            <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+            <script>
+                window.addEventListener('load', () => {
+                    SwaggerUIBundle({ url: 'openapi.json', dom_id: '#swagger-ui' });
+                });
+            </script>
+        </body>
+        </html>
+    "#))
+}
+
+/// Sentinel path the directory browser uses for the top level. On Unix this is
+/// the filesystem root; on Windows it lists the available drives.
+const BROWSE_ROOT: &str = "/";
+
+#[cfg(windows)]
+fn list_drives() -> Vec<String> {
+    (b'A'..=b'Z')
+        .map(|letter| format!("{}:\\", letter as char))
+        .filter(|drive| std::path::Path::new(drive).exists())
+        .collect()
 }
 
-async fn change_path(
-    State(state): State<AppState>,
-    Json(req): Json<ChangePathRequest>,
-) -> Json<IndexStatus> {
-    println!("\n=== Changing Path ===");
-    println!("Request to change path to: {}", req.path);
-    
-    // Update the root path in the existing state
-    let new_path = PathBuf::from(&req.path);
-    *state.user_selected_dir.write().await = new_path.clone();
-    println!("Updated selected directory to: {}", state.user_selected_dir.read().await.display());
-    
-    // Try to load existing index for the new path
-    let loaded_index = IndexEntry::load_index(&new_path).unwrap_or_else(|e| {
-        println!("Could not load existing index for {}: {}", new_path.display(), e);
-        Vec::new()
-    });
+/// Returns the entry used for ".." navigation, or `None` at the top level.
+fn parent_directory(path: &std::path::Path) -> Option<String> {
+    if path == std::path::Path::new(BROWSE_ROOT) {
+        return None;
+    }
 
-    // Update the indices map with the loaded index
+    #[cfg(windows)]
     {
-        let mut indices = state.indices.write().await;
-        indices.insert(new_path.to_string_lossy().to_string(), loaded_index.clone());
-        println!("Loaded existing index with {} entries", loaded_index.len());
+        // A drive root such as `C:\` has no parent; step back out to the drive list
+        // rather than producing an empty path.
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                parent.to_str().map(String::from)
+            }
+            _ => Some(BROWSE_ROOT.to_string()),
+        }
     }
-    
-    // Update config with new path
+
+    #[cfg(not(windows))]
     {
-        let mut config = state.config.write().await;
-        config.add_path(req.path.clone(), loaded_index.len());
-        let _ = config.save();
-        println!("Updated config with new path");
+        path.parent().and_then(|parent| parent.to_str()).map(String::from)
     }
+}
 
-    // Return current index status
-    Json(IndexStatus {
-        total_files: state.indices.read().await.get(&req.path).map(|idx| idx.len()).unwrap_or(0),
-        last_updated: Utc::now(),
-        root_path: state.user_selected_dir.read().await.to_string_lossy().to_string(),
-    })
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+#[derive(Deserialize)]
+struct ListDirectoriesQuery {
+    /// Subdirectories to skip
+    #[serde(default)]
+    offset: usize,
+    /// Most subdirectories to return; capped by `--max-listed-directories`
+    limit: Option<usize>,
 }
 
-async fn get_current_path(State(state): State<AppState>) -> Json<serde_json::Value> {
-    let current_path = state.user_selected_dir.read().await;
-    Json(serde_json::json!({
-        "path": current_path.to_string_lossy().to_string()
-    }))
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[derive(Serialize)]
+struct DirectoryListing {
+    /// Entry for ".." navigation, `null` at the top level
+    parent: Option<String>,
+    /// Subdirectories in `offset..offset + limit`, sorted
+    dirs: Vec<String>,
+    /// Subdirectories in all
+    total: usize,
+    /// Whether there are subdirectories past the ones in `dirs`
+    truncated: bool,
 }
 
-async fn clear_recent_paths(State(state): State<AppState>) -> Json<String> {
-    // Clear the recent paths while keeping the current directory
-    let current_dir = state.user_selected_dir.read().await.clone();
-    let current_dir_str = current_dir.to_string_lossy().to_string();
-    
-    // Reset the config with empty recent paths
-    let mut config = state.config.write().await;
-    config.recent_paths.clear();
-    
-    // Add back only the current directory
-    if let Ok(metadata) = std::fs::metadata(&current_dir) {
-        if metadata.is_dir() {
-            config.add_path(current_dir_str, 0);
-        }
-    }
-    
-    // Save the updated config
-    if let Err(e) = config.save() {
-        return Json(format!("Error saving config: {}", e));
+#[cfg_attr(feature = "openapi", utoipa::path(get, path = "/list-directories/{path}", params(("path" = String, Path, description = "Directory to list, or `/` for the top level"), ListDirectoriesQuery), responses((status = 200, body = DirectoryListing))))]
+async fn list_directories(
+    Path(current_path): Path<String>,
+    Query(query): Query<ListDirectoriesQuery>,
+    State(state): State<AppState>,
+) -> Json<DirectoryListing> {
+    let limit = query.limit.unwrap_or(usize::MAX).min(state.args.max_listed_directories);
+
+    #[cfg(windows)]
+    if current_path == BROWSE_ROOT {
+        return Json(DirectoryListing::page(None, list_drives(), query.offset, limit));
     }
-    
-    Json("Recent paths cleared successfully".to_string())
-}
 
-async fn purge_indices() -> Json<String> {
-    if let Ok(index_dir) = get_index_dir() {
-        if let Err(e) = fs::remove_dir_all(&index_dir) {
-            return Json(format!("Error purging indices: {}", e));
-        }
+    #[allow(unused_mut)]
+    let mut path = PathBuf::from(current_path);
+
+    // `C:` on its own refers to the drive's current directory, not its root.
+    #[cfg(windows)]
+    if path.parent().is_none() && !path.has_root() {
+        path.push(std::path::MAIN_SEPARATOR_STR);
     }
-    Json("All indices purged successfully".to_string())
-}
 
-async fn list_directories(Path(current_path): Path<String>) -> Json<Vec<String>> {
-    let path = PathBuf::from(current_path);
     let mut dirs = Vec::new();
     
-    // Add parent directory if not at root
-    if path != PathBuf::from("/") {
-        if let Some(parent) = path.parent() {
-            if let Some(parent_str) = parent.to_str() {
-                dirs.push(parent_str.to_string());
-            }
-        }
-    }
-    
     // List current directory contents
     if let Ok(entries) = fs::read_dir(&path) {
         for entry in entries.filter_map(|e| e.ok()) {
@@ -862,70 +7981,844 @@ async fn list_directories(Path(current_path): Path<String>) -> Json<Vec<String>>
         }
     }
     
-    dirs.sort();
-    Json(dirs)
+    Json(DirectoryListing::page(parent_directory(&path), dirs, query.offset, limit))
+}
+
+impl DirectoryListing {
+    /// Sorts all of `dirs` so pages are stable, then keeps one page of them.
+    fn page(parent: Option<String>, mut dirs: Vec<String>, offset: usize, limit: usize) -> Self {
+        let total = dirs.len();
+        dirs.sort();
+        let dirs: Vec<String> = dirs.into_iter().skip(offset).take(limit).collect();
+        if total > dirs.len() {
+            println!("Listing {} of {} directories", dirs.len(), total);
+        }
+        DirectoryListing {
+            parent,
+            truncated: offset.saturating_add(dirs.len()) < total,
+            dirs,
+            total,
+        }
+    }
+}
+
+/// Builds the CORS policy for the API. With no configured origins the layer
+/// allows nothing, leaving browsers at the default same-origin behaviour.
+fn cors_layer(args: &Args) -> Result<CorsLayer, header::InvalidHeaderValue> {
+    let origin = if args.cors_any {
+        // Mirroring rather than `*` so credentialed requests still work
+        AllowOrigin::mirror_request()
+    } else {
+        let origins = args.cors_origins.iter()
+            .map(|origin| origin.parse())
+            .collect::<Result<Vec<header::HeaderValue>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+        .allow_credentials(true))
+}
+
+/// Policy for the app's own pages: scripts and styles only from this server,
+/// inline ones included since the embedded page uses them, and no framing.
+const DEFAULT_CONTENT_SECURITY_POLICY: &str =
+    "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src 'self' data:; frame-ancestors 'none'; base-uri 'none'; form-action 'self'";
+
+/// Adds `X-Content-Type-Options: nosniff` and `X-Frame-Options: DENY` to every
+/// response, and `policy` as the Content-Security-Policy of any that don't
+/// carry their own.
+async fn security_headers(
+    State(policy): State<Option<header::HeaderValue>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, header::HeaderValue::from_static("nosniff"));
+    headers.insert(header::X_FRAME_OPTIONS, header::HeaderValue::from_static("DENY"));
+    if let Some(policy) = policy {
+        headers.entry(header::CONTENT_SECURITY_POLICY).or_insert(policy);
+    }
+    response
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tokio::runtime::Runtime::new()?.block_on(async {
     // Parse command line arguments
     let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_target(false)
+        .with_ansi(std::io::IsTerminal::is_terminal(&io::stdout()))
+        .init();
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = args.blocking_threads {
+        runtime.max_blocking_threads(threads);
+    }
+
+    runtime.build()?.block_on(async {
     println!("\n=== Application Startup ===");
     
     // Use provided path or current directory as default
     let working_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
     println!("Working directory: {}", working_dir.display());
     
-    let user_selected_dir = args.path.unwrap_or_else(|| working_dir.clone());
-    println!("Initial user selected directory: {}", user_selected_dir.display());
+    let config = Config::load().unwrap_or_default();
 
-    let config = Config::load().unwrap_or_else(|_| Config { recent_paths: vec![] });
+    // An explicit --path wins, then wherever the last session left off
+    let last_root = config.last_root.as_ref()
+        .map(PathBuf::from)
+        .filter(|path| {
+            let exists = path.is_dir();
+            if !exists {
+                println!("Last active directory no longer exists: {}", path.display());
+            }
+            exists
+        });
+    let user_selected_dir = normalize_root(&args.path.clone()
+        .or(last_root)
+        .unwrap_or_else(|| working_dir.clone()));
+
+    let access_log = AccessLog::load().unwrap_or_default();
+    let tags = FileTags::load().unwrap_or_default();
+    let metrics = PrometheusBuilder::new()
+        .set_buckets(&[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0, 120.0])?
+        .install_recorder()?;
     
-    // Try to load existing index
+    // Load the saved index, if there is one; a directory that was never
+    // indexed is the normal first run, not an error
     let mut initial_indices = HashMap::new();
-    let initial_index = IndexEntry::load_index(&user_selected_dir)
-        .unwrap_or_else(|e| {
-            println!("Could not load existing index: {}", e);
+    let has_saved_index = IndexEntry::saved_files(&user_selected_dir).is_ok_and(|files| !files.is_empty());
+    let initial_index = match IndexEntry::load_index(&user_selected_dir) {
+        Ok(index) if has_saved_index => {
+            tracing::info!(root = %user_selected_dir.display(), entries = index.len(), "Loaded saved index");
+            index
+        }
+        Ok(index) => {
+            tracing::info!(root = %user_selected_dir.display(), entries = 0, "No saved index yet, starting empty");
+            index
+        }
+        Err(e) => {
+            tracing::warn!(root = %user_selected_dir.display(), error = %e, "Saved index unreadable, starting empty");
             Vec::new()
-        });
+        }
+    };
+    let mut initial_index = initial_index;
+    tags.apply(&user_selected_dir.to_string_lossy(), &mut initial_index);
     initial_indices.insert(user_selected_dir.to_string_lossy().to_string(), initial_index.clone());
 
     let state = AppState {
         working_dir: Arc::new(working_dir.clone()),
         user_selected_dir: Arc::new(RwLock::new(user_selected_dir.clone())),
         indices: Arc::new(RwLock::new(initial_indices)),
+        index_used: Arc::new(RwLock::new(HashMap::from([(user_selected_dir.to_string_lossy().to_string(), Utc::now())]))),
         config: Arc::new(RwLock::new(config)),
+        access_log: Arc::new(RwLock::new(access_log)),
+        tags: Arc::new(RwLock::new(tags)),
+        metrics,
+        search_cache: Arc::new(RwLock::new(SearchCache::default())),
+        index_cancel: Arc::new(RwLock::new(None)),
+        highlighter: Arc::new(Highlighter::load()),
+        download_permits: Arc::new(tokio::sync::Semaphore::new(args.max_concurrent_downloads)),
+        query_log: match (args.query_log, get_query_log_path()) {
+            (true, Ok(path)) => Some(Arc::new(QueryLog::start(path, args.query_log_max_bytes))),
+            (true, Err(e)) => {
+                println!("Error locating query log: {}; not logging queries", e);
+                None
+            }
+            (false, _) => None,
+        },
+        new_files: Arc::new(RwLock::new(std::collections::VecDeque::new())),
+        new_files_signal: Arc::new(tokio::sync::watch::channel(0).0),
+        last_request: Arc::new(std::sync::atomic::AtomicI64::new(Utc::now().timestamp_millis())),
+        args: Arc::new(args),
     };
     
     // Add initial path to config
     {
         let mut config = state.config.write().await;
         config.add_path(user_selected_dir.to_string_lossy().to_string(), initial_index.len());
+        config.last_root = Some(user_selected_dir.to_string_lossy().to_string());
         let _ = config.save();
     }
 
-    let app = Router::new()
-        .route("/", get(index))
-        .route("/search", get(search))
-        .route("/download/*path", get(download_file))
-        .route("/create-index", post(create_index))
-        .route("/recent-paths", get(get_recent_paths))
-        .route("/change-path", post(change_path))
-        .route("/list-directories/:path", get(list_directories))
-        .route("/purge-indices", post(purge_indices))
-        .route("/current-path", get(get_current_path))
-        .route("/clear-recent-paths", post(clear_recent_paths))
+    // Periodically drop indices for directories that have fallen out of use
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = prune_stale_indices(&state).await {
+                    println!("Error pruning indices: {}", e);
+                }
+            }
+        });
+    }
+
+    if state.args.preload_recent {
+        let state = state.clone();
+        tokio::spawn(async move {
+            preload_recent_indices(&state).await;
+        });
+    }
+
+    // Rebuild the active root's index on its configured schedule, if any
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REINDEX_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                run_scheduled_reindex(&state).await;
+            }
+        });
+    }
+
+    let static_dir = state.args.static_dir.clone();
+    let cors = cors_layer(&state.args)?;
+    let content_security_policy = match state.args.content_security_policy.as_str() {
+        "" => None,
+        policy => Some(header::HeaderValue::from_str(policy)?),
+    };
+    let max_body_bytes = state.args.max_body_bytes;
+    let base_path = state.args.base_path.clone();
+    let not_found = {
+        let base_path = base_path.clone();
+        move |method: Method, uri: axum::extract::OriginalUri, headers: axum::http::HeaderMap| {
+            not_found(base_path.clone(), method, uri, headers)
+        }
+    };
+    let index_state = state.clone();
+    let shutdown_state = state.clone();
+
+    let app = api_routes(&state)
+        // Caps every route that reads a JSON body. Axum's own smaller default
+        // is lifted so --max-body-bytes alone decides.
+        .layer(axum::extract::DefaultBodyLimit::disable())
+        .layer(tower_http::limit::RequestBodyLimitLayer::new(max_body_bytes))
+        .layer(cors)
         .with_state(state);
 
+    #[cfg(feature = "openapi")]
+    let app = app
+        .route("/openapi.json", get(openapi_json))
+        .route("/docs", get(swagger_ui));
+
+    // A custom frontend replaces the embedded page; API routes take precedence
+    let app = match static_dir {
+        Some(dir) => {
+            println!("Serving frontend from: {}", dir.display());
+            let not_found = axum::handler::HandlerWithoutStateExt::into_service(not_found.clone());
+            app.fallback_service(tower_http::services::ServeDir::new(dir).not_found_service(not_found))
+        }
+        None => app
+            .route("/", get(index).with_state(index_state))
+            .route("/favicon.ico", get(favicon))
+            .fallback(not_found.clone()),
+    };
+
+    let app = if base_path.is_empty() {
+        app
+    } else {
+        println!("Serving under base path: {}", base_path);
+        // Proxies usually forward the prefix with a trailing slash
+        let root = base_path.clone();
+        Router::new()
+            .route(&format!("{}/", base_path), get(|| async move { axum::response::Redirect::permanent(&root) }))
+            .nest(&base_path, app)
+            .fallback(not_found)
+    };
+    let app = app
+        .layer(axum::middleware::from_fn_with_state(content_security_policy, security_headers))
+        .layer(axum::middleware::from_fn_with_state(shutdown_state.clone(), record_activity));
+
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     println!("Server running on http://localhost:3000");
     
     axum::serve(
         tokio::net::TcpListener::bind(&addr).await?,
-        app,
+        // Client addresses are recorded when downloads are refused
+        app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(idle_shutdown(shutdown_state))
     .await?;
 
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scratch home holding the app's config, cache and data directories
+    /// for the whole test run, so no test reads or overwrites the real ones
+    fn test_home() -> &'static PathBuf {
+        static HOME: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+        HOME.get_or_init(|| {
+            let home = std::env::temp_dir().join(format!("fuzzy-search-test-{}-home", std::process::id()));
+            let _ = fs::remove_dir_all(&home);
+            fs::create_dir_all(&home).unwrap();
+            std::env::set_var("XDG_CONFIG_HOME", home.join(".config"));
+            std::env::set_var("XDG_CACHE_HOME", home.join(".cache"));
+            std::env::set_var("XDG_DATA_HOME", home.join(".local/share"));
+            home
+        })
+    }
+
+    /// Fresh, empty scratch directory for one test
+    fn scratch_dir(name: &str) -> PathBuf {
+        test_home();
+        let dir = std::env::temp_dir().join(format!("fuzzy-search-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Index entry for `path`, as loaded from an index file
+    fn entry(path: &str) -> IndexEntry {
+        serde_json::from_value(serde_json::json!({
+            "path": path,
+            "name": path.rsplit('/').next().unwrap(),
+            "last_modified": "2024-01-01T00:00:00Z",
+            "size": 0,
+        }))
+        .unwrap()
+    }
+
+    /// Search query parsed from a query string the way `/search` parses it
+    fn search_query(params: &str) -> SearchQuery {
+        let uri: axum::http::Uri = format!("/search?{}", params).parse().unwrap();
+        Query::<SearchQuery>::try_from_uri(&uri).unwrap().0
+    }
+
+    /// `search_query` with its inline operators extracted, ready to score
+    fn query(params: &str) -> SearchQuery {
+        search_query(params).extract_operators()
+    }
+
+    /// App state with default arguments and `index` loaded for `root`, the
+    /// selected directory
+    fn test_state(root: &str, index: Vec<IndexEntry>) -> AppState {
+        test_home();
+        AppState {
+            working_dir: Arc::new(PathBuf::from(root)),
+            user_selected_dir: Arc::new(RwLock::new(PathBuf::from(root))),
+            indices: Arc::new(RwLock::new(HashMap::from([(root.to_string(), index)]))),
+            index_used: Arc::new(RwLock::new(HashMap::new())),
+            config: Arc::new(RwLock::new(Config::default())),
+            access_log: Arc::new(RwLock::new(AccessLog::default())),
+            tags: Arc::new(RwLock::new(FileTags::default())),
+            metrics: PrometheusBuilder::new().build_recorder().handle(),
+            search_cache: Arc::new(RwLock::new(SearchCache::default())),
+            index_cancel: Arc::new(RwLock::new(None)),
+            highlighter: Arc::new(Highlighter::load()),
+            download_permits: Arc::new(tokio::sync::Semaphore::new(1)),
+            query_log: None,
+            new_files: Arc::new(RwLock::new(std::collections::VecDeque::new())),
+            new_files_signal: Arc::new(tokio::sync::watch::channel(0).0),
+            last_request: Arc::new(std::sync::atomic::AtomicI64::new(0)),
+            args: Arc::new(Args::parse_from(["fuzzy-search-webapp"])),
+        }
+    }
+
+    #[tokio::test]
+    async fn health_answers_during_a_long_walk() {
+        use tower::ServiceExt;
+
+        let root = scratch_dir("health");
+        for dir in 0..40 {
+            let dir = root.join(format!("dir{}", dir));
+            fs::create_dir(&dir).unwrap();
+            for file in 0..50 {
+                fs::write(dir.join(format!("file{}.txt", file)), "contents").unwrap();
+            }
+        }
+        let options = WalkOptions { content_hashes: Some(1024), ..WalkOptions::default() };
+        let scratch = root.clone();
+
+        // A single-threaded runtime: were the walk run inline, the health
+        // check couldn't be answered until it was done
+        let walk = tokio::spawn(async move {
+            let walk = walk_directory_blocking(root, options, CancellationToken::new()).await;
+            (walk, std::time::Instant::now())
+        });
+        tokio::task::yield_now().await;
+
+        let app: Router = Router::new().route("/health", get(health));
+        let response = app
+            .oneshot(axum::http::Request::get("/health").body(axum::body::Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let answered = std::time::Instant::now();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (walk, walked) = walk.await.unwrap();
+        let _ = fs::remove_dir_all(&scratch);
+        assert_eq!(walk.entries.len(), 2000);
+        assert!(answered < walked, "health check waited for the walk");
+    }
+
+    #[test]
+    fn fold_diacritics_lets_plain_letters_match_accented_ones() {
+        assert_eq!(fold_diacritics("café/Crème Brûlée.txt"), "cafe/Creme Brulee.txt");
+
+        let entry = entry("menus/café.txt");
+        let folded = query("q=cafe&fold_diacritics=true&strict_substring=true");
+        assert!(folded.score(&*folded.matcher(), &entry).is_some());
+        let strict = query("q=cafe&strict_substring=true");
+        assert!(strict.score(&*strict.matcher(), &entry).is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn walk_skips_app_data_dirs_under_the_root() {
+        let root = test_home();
+        let config_dir = get_config_path().unwrap().parent().unwrap().to_path_buf();
+        let index_dir = get_index_dir().unwrap();
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::create_dir_all(&index_dir).unwrap();
+        fs::write(config_dir.join("config.json"), "{}").unwrap();
+        fs::write(index_dir.join("index_0.json"), "[]").unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/notes.txt"), "notes").unwrap();
+
+        let excluded = app_data_dirs_under(root);
+        let walk = walk_directory(root, &WalkOptions::default(), &CancellationToken::new());
+
+        assert!(excluded.contains(&config_dir));
+        assert!(excluded.contains(&index_dir));
+        let paths: Vec<&str> = walk.entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["docs/notes.txt"]);
+    }
+
+    #[test]
+    fn slash_paths_round_trip_through_resolve_in_root() {
+        let root = std::env::temp_dir().join("root");
+        let relative: PathBuf = ["src", "sub", "main.rs"].iter().collect();
+        let slashed = slash_path(&relative);
+        assert_eq!(slashed, "src/sub/main.rs");
+        assert_eq!(resolve_in_root(&root, &slashed).unwrap(), root.join(&relative));
+
+        // Backslash separates on Windows but is part of the name elsewhere
+        #[cfg(windows)]
+        {
+            assert_eq!(slash_path(std::path::Path::new(r"src\sub\main.rs")), "src/sub/main.rs");
+            assert_eq!(resolve_in_root(&root, r"\src\sub\main.rs").unwrap(), root.join(&relative));
+        }
+        #[cfg(not(windows))]
+        {
+            assert_eq!(slash_path(std::path::Path::new(r"src\main.rs")), r"src\main.rs");
+            assert_eq!(resolve_in_root(&root, r"src\main.rs").unwrap(), root.join(r"src\main.rs"));
+        }
+    }
+
+    #[test]
+    fn tie_breaker_orders_equal_scores() {
+        let sorted = |tie_breaker: TieBreaker| {
+            let mut matches: Vec<ScoredEntry> = ["src/alpha.rs", "b.rs", "src/zeta.rs", "a/b.rs", "zzz/top.rs"]
+                .iter()
+                .map(|path| (if *path == "zzz/top.rs" { 20 } else { 10 }, entry(path)))
+                .collect();
+            matches.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then_with(|| tie_breaker.compare(a, b)));
+            matches.into_iter().map(|(_, entry)| entry.path).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted(TieBreaker::ShortestPath), ["zzz/top.rs", "b.rs", "a/b.rs", "src/zeta.rs", "src/alpha.rs"]);
+        assert_eq!(sorted(TieBreaker::Path), ["zzz/top.rs", "a/b.rs", "b.rs", "src/alpha.rs", "src/zeta.rs"]);
+    }
+
+    #[test]
+    fn collapse_case_duplicates_keeps_the_on_disk_casing() {
+        let root = scratch_dir("collapse-case");
+        fs::write(root.join("README.md"), "readme").unwrap();
+        let results = vec![(30, entry("Readme.md")), (20, entry("README.md")), (10, entry("notes.txt"))];
+
+        let collapsed = collapse_case_duplicates(results, &root.to_string_lossy());
+        let _ = fs::remove_dir_all(&root);
+
+        let collapsed: Vec<(i64, &str)> = collapsed.iter().map(|(score, entry)| (*score, entry.path.as_str())).collect();
+        assert_eq!(collapsed, [(30, "README.md"), (10, "notes.txt")]);
+    }
+
+    #[tokio::test]
+    async fn dropping_a_scan_stops_it() {
+        let index: Vec<IndexEntry> = (0..SEARCH_SCAN_CHUNK * 3).map(|i| entry(&format!("dir/file{}.txt", i))).collect();
+        let state = test_state("/root", index);
+
+        // Folding fills in each entry's folded text as it is scored, which
+        // shows how far the scan got
+        let mut scan = Box::pin(scan_indices(&state, search_query("q=file&fold_diacritics=true"), "/root"));
+        assert!(futures::poll!(scan.as_mut()).is_pending());
+        drop(scan);
+        tokio::task::yield_now().await;
+
+        let indices = state.indices.read().await;
+        let scored = indices["/root"].iter().filter(|entry| entry.folded.get().is_some()).count();
+        assert_eq!(scored, SEARCH_SCAN_CHUNK);
+    }
+
+    #[test]
+    fn depth_penalty_ranks_shallow_paths_first_without_overflowing() {
+        let shallow = entry("main.rs");
+        let deep = entry("src/bin/main.rs");
+        for penalty in [10, i64::MAX] {
+            let query = query(&format!("q=main&depth_penalty={}", penalty));
+            let matcher = query.matcher();
+            let shallow = query.score(&*matcher, &shallow).unwrap();
+            let deep = query.score(&*matcher, &deep).unwrap();
+            assert!(shallow > deep, "penalty {}: {} <= {}", penalty, shallow, deep);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn downloads_a_non_utf8_name_by_its_raw_path() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = scratch_dir("raw-path");
+        let name = std::ffi::OsStr::from_bytes(b"\xff.txt");
+        fs::write(root.join(name), "not utf-8").unwrap();
+
+        let walk = walk_directory(&root, &WalkOptions::default(), &CancellationToken::new());
+        assert_eq!(walk.entries.len(), 1);
+        let indexed = &walk.entries[0];
+        assert_eq!(indexed.path, "\u{fffd}.txt");
+        let raw = indexed.raw_path.clone().expect("non-UTF-8 name has a raw path");
+
+        let state = test_state(&root.to_string_lossy(), walk.entries.clone());
+        let uri: axum::http::Uri = format!("/download/x?raw={}", percent_encoding::utf8_percent_encode(&raw, percent_encoding::NON_ALPHANUMERIC))
+            .parse()
+            .unwrap();
+        let response = download_file(
+            Path(indexed.path.clone()),
+            Query::try_from_uri(&uri).unwrap(),
+            axum::extract::ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))),
+            State(state),
+        )
+        .await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"not utf-8");
+    }
+
+    #[test]
+    fn romanized_names_match_their_pinyin() {
+        let root = scratch_dir("romanize");
+        fs::write(root.join("東京.txt"), "").unwrap();
+        let options = WalkOptions { transliterate: true, ..WalkOptions::default() };
+        let walk = walk_directory(&root, &options, &CancellationToken::new());
+        let _ = fs::remove_dir_all(&root);
+
+        let romanized = &walk.entries[0];
+        assert_eq!(romanized.path, "東京.txt");
+        let query = query("q=dongjing");
+        assert!(query.score(&*query.matcher(), romanized).is_some());
+        assert!(query.score(&*query.matcher(), &entry("東京.txt")).is_none());
+    }
+
+    #[test]
+    fn add_path_ignores_a_trailing_slash() {
+        let root = scratch_dir("add-path");
+        let path = root.to_string_lossy().to_string();
+        let mut config = Config::default();
+        config.add_path(format!("{}/", path), 1);
+        config.add_path(path.clone(), 2);
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!(config.recent_paths.len(), 1);
+        assert_eq!(config.recent_paths[0].path, path);
+        assert_eq!(config.recent_paths[0].total_files, 2);
+    }
+
+    #[test]
+    fn upper_case_extensions_match_and_classify() {
+        let photo = entry("camera/Photo.JPG");
+        assert_eq!(photo.extension().as_deref(), Some("jpg"));
+        assert_eq!(FileType::of(&photo), FileType::Image);
+
+        let jpg = query("q=photo+ext:jpg");
+        assert!(jpg.score(&*jpg.matcher(), &photo).is_some());
+        let png = query("q=photo+ext:png");
+        assert!(png.score(&*png.matcher(), &photo).is_none());
+    }
+
+    #[tokio::test]
+    async fn html_pages_past_the_first_are_not_cut_by_limit() {
+        let state = test_state("/root", (0..3).map(|i| entry(&format!("file{}.txt", i))).collect());
+        let uri: axum::http::Uri = "/search/html?q=file&limit=2&offset=2".parse().unwrap();
+
+        let Html(html) = search_html(Query::try_from_uri(&uri).unwrap(), Query::try_from_uri(&uri).unwrap(), State(state)).await;
+        assert_eq!(html.matches("class=\"file-link\"").count(), 1, "{}", html);
+    }
+
+    #[test]
+    fn ages_must_be_positive_and_in_range() {
+        assert_eq!(parse_age("7d"), chrono::Duration::try_days(7));
+        assert_eq!(parse_age("12H"), chrono::Duration::try_hours(12));
+        for invalid in ["0d", "-5d", "100000000d", "9223372036854775807h", "7w", "d"] {
+            assert_eq!(parse_age(invalid), None, "{}", invalid);
+        }
+        assert!(Query::<SearchQuery>::try_from_uri(&"/search?q=a&age=-5d".parse().unwrap()).is_err());
+        assert!(Query::<SearchQuery>::try_from_uri(&"/search?q=a&age=100000000d".parse().unwrap()).is_err());
+
+        let old = entry("old.txt");
+        let any_age = query("q=old");
+        assert!(any_age.score(&*any_age.matcher(), &old).is_some());
+        let recent = query("q=old&age=7d");
+        assert!(recent.score(&*recent.matcher(), &old).is_none());
+    }
+
+    #[tokio::test]
+    async fn scheduled_reindex_merges_changes_unless_a_run_is_active() {
+        let root = scratch_dir("scheduled-reindex");
+        let root_path = root.to_string_lossy().to_string();
+        fs::write(root.join("new.txt"), "new").unwrap();
+        let state = test_state(&root_path, vec![entry("gone.txt")]);
+        {
+            let mut config = state.config.write().await;
+            config.add_path(root_path.clone(), 1);
+            let path_config = &mut config.recent_paths[0];
+            path_config.reindex_interval_minutes = Some(1);
+            path_config.last_indexed = Some(Utc::now() - chrono::Duration::hours(1));
+        }
+        async fn paths(state: &AppState, root: &str) -> Vec<String> {
+            state.indices.read().await[root].iter().map(|entry| entry.path.clone()).collect()
+        }
+
+        // Another run holds the slot, so the reindex waits
+        *state.index_cancel.write().await = Some(Arc::new(CancellationToken::new()));
+        run_scheduled_reindex(&state).await;
+        assert_eq!(paths(&state, &root_path).await, ["gone.txt"]);
+
+        *state.index_cancel.write().await = None;
+        run_scheduled_reindex(&state).await;
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(paths(&state, &root_path).await, ["new.txt"]);
+        assert!(state.index_cancel.read().await.is_none());
+        let next = state.config.read().await.recent_paths[0].next_reindex().unwrap();
+        assert!(next > Utc::now());
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_allows_delete() {
+        use tower::ServiceExt;
+
+        let args = Args::parse_from(["fuzzy-search-webapp", "--cors-any"]);
+        let app: Router = Router::new()
+            .route("/bookmarks", axum::routing::delete(|| async {}))
+            .layer(cors_layer(&args).unwrap());
+        let preflight = axum::http::Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/bookmarks")
+            .header(header::ORIGIN, "http://example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+            .body(axum::body::Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(preflight).await.unwrap();
+        let allowed = response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap();
+        assert!(allowed.split(',').any(|method| method.trim() == "DELETE"), "{}", allowed);
+    }
+
+    #[tokio::test]
+    async fn read_only_refuses_every_mutating_route() {
+        use tower::ServiceExt;
+
+        let mut state = test_state("/root", vec![entry("notes.txt")]);
+        state.args = Arc::new(Args::parse_from(["fuzzy-search-webapp", "--read-only"]));
+        let app: Router = api_routes(&state).with_state(state);
+        let request = |method: Method, uri: &str| {
+            axum::http::Request::builder().method(method).uri(uri).body(axum::body::Body::empty()).unwrap()
+        };
+
+        for (method, uri) in [
+            (Method::POST, "/create-index"),
+            (Method::POST, "/cancel-index"),
+            (Method::GET, "/ws/index"),
+            (Method::POST, "/reindex-subtree"),
+            (Method::POST, "/refresh-metadata"),
+            (Method::POST, "/rename-apply"),
+            (Method::POST, "/bookmarks"),
+            (Method::DELETE, "/bookmarks"),
+            (Method::POST, "/rekey-index"),
+            (Method::POST, "/tags"),
+            (Method::POST, "/tags/bulk"),
+            (Method::POST, "/config/search-defaults"),
+            (Method::POST, "/config/scoring"),
+            (Method::POST, "/config/reindex-schedule"),
+            (Method::POST, "/change-path"),
+            (Method::POST, "/purge-indices"),
+            (Method::POST, "/prune-indices"),
+            (Method::POST, "/clear-recent-paths"),
+        ] {
+            let response = app.clone().oneshot(request(method.clone(), uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+        let response = app.oneshot(request(Method::GET, "/search?q=notes")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn nucleo_scores_follow_the_pattern_and_case() {
+        let matcher = NucleoMatcher::new(CaseMode::Smart);
+        let (score, indices) = matcher.fuzzy_indices("src/Main.rs", "main").unwrap();
+        assert_eq!(matcher.fuzzy_match("src/Main.rs", "main"), Some(score));
+        assert_eq!(indices, [4, 5, 6, 7]);
+
+        // The prepared needle is replaced when the pattern changes
+        assert_eq!(matcher.fuzzy_match("src/Main.rs", "lib"), None);
+        assert!(matcher.fuzzy_match("src/Main.rs", "Main").is_some());
+        assert_eq!(matcher.fuzzy_match("src/main.rs", "Main"), None);
+        assert!(matcher.fuzzy_match("src/main.rs", "main").is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn renames_through_a_symlinked_directory_are_rejected() {
+        let root = scratch_dir("rename-symlink");
+        let outside = scratch_dir("rename-symlink-outside");
+        fs::write(root.join("notes.txt"), "notes").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("linkdir")).unwrap();
+        fs::create_dir(root.join("inside")).unwrap();
+
+        let index = vec![entry("notes.txt")];
+        let plan = |replacement: &str| plan_renames(&root, &index, &regex::Regex::new("^(.*)$").unwrap(), replacement);
+        let (renames, rejected) = plan("linkdir/$1");
+        let (inside, _) = plan("inside/$1");
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&outside);
+
+        assert!(renames.is_empty());
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].reason, "New path would be outside the selected directory");
+        assert_eq!(inside.len(), 1);
+        assert_eq!(inside[0].to, "inside/notes.txt");
+    }
+
+    #[tokio::test]
+    async fn rename_apply_moves_files_index_entries_and_tags() {
+        let root = scratch_dir("rename-apply");
+        let root_path = root.to_string_lossy().to_string();
+        fs::write(root.join("draft.txt"), "draft").unwrap();
+        let mut draft = entry("draft.txt");
+        draft.tags = vec!["todo".to_string()];
+        let state = test_state(&root_path, vec![draft, entry("other.md")]);
+        state.tags.write().await.set(&root.join("draft.txt"), vec!["todo".to_string()]);
+
+        let request = RenameRequest { pattern: "^draft".to_string(), replacement: "docs/final".to_string() };
+        let response = rename_apply(State(state.clone()), Json(request)).await;
+        let moved = root.join("docs/final.txt").is_file() && !root.join("draft.txt").exists();
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(moved);
+        let paths: Vec<String> = state.indices.read().await[&root_path].iter().map(|entry| entry.path.clone()).collect();
+        assert_eq!(paths, ["docs/final.txt", "other.md"]);
+        let tags = state.tags.read().await;
+        assert_eq!(tags.get(&root_path, "docs/final.txt"), ["todo"]);
+        assert!(tags.get(&root_path, "draft.txt").is_empty());
+    }
+
+    #[tokio::test]
+    async fn refresh_metadata_patches_the_live_index() {
+        let root = scratch_dir("refresh-metadata");
+        let root_path = root.to_string_lossy().to_string();
+        fs::write(root.join("kept.txt"), "12345").unwrap();
+        let mut kept = entry("kept.txt");
+        kept.tags = vec!["keep".to_string()];
+        let state = test_state(&root_path, vec![kept, entry("gone.txt")]);
+
+        let response = refresh_metadata(State(state.clone())).await;
+        let _ = fs::remove_dir_all(&root);
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let indices = state.indices.read().await;
+        let index = &indices[&root_path];
+        assert_eq!(index.len(), 1);
+        assert_eq!((index[0].path.as_str(), index[0].size), ("kept.txt", 5));
+        assert_eq!(index[0].tags, ["keep"]);
+    }
+
+    #[tokio::test]
+    async fn search_stream_sends_the_best_matches_first() {
+        // Matches spread over several scan chunks and stream batches
+        let mut index: Vec<IndexEntry> = (0..SEARCH_SCAN_CHUNK * 2).map(|i| entry(&format!("logs/file{}.log", i))).collect();
+        index[0] = entry("archive/old/report_draft_v2.txt");
+        index[SEARCH_SCAN_CHUNK] = entry("reports/report.txt");
+        index[SEARCH_SCAN_CHUNK * 2 - 1] = entry("report.txt");
+        let state = test_state("/root", index);
+        let streamed = |params: &'static str| {
+            let state = state.clone();
+            async move {
+                let response = search_stream(Query(search_query(params)), State(state)).await;
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+                    .lines()
+                    .map(|line| serde_json::from_str::<IndexEntry>(line).unwrap().path)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let expected: Vec<String> = scan_indices(&state, search_query("q=report"), "/root").await
+            .files.into_iter().map(|entry| entry.path).collect();
+        assert_eq!(expected.len(), 3);
+        assert_eq!(streamed("q=report").await, expected);
+        assert_eq!(streamed("q=report&limit=2").await, expected[..2]);
+    }
+
+    #[tokio::test]
+    async fn search_stream_stops_scanning_once_the_client_is_gone() {
+        let index: Vec<IndexEntry> = (0..SEARCH_SCAN_CHUNK * 3).map(|i| entry(&format!("dir/file{}.txt", i))).collect();
+        let state = test_state("/root", index);
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        drop(rx);
+
+        let matches = best_stream_matches(&state, &query("q=file&fold_diacritics=true"), "/root", SEARCH_STREAM_MAX_RESULTS, &tx).await;
+
+        assert!(matches.is_none());
+        // Nothing was scored, so no entry had its folded text filled in
+        assert!(state.indices.read().await["/root"].iter().all(|entry| entry.folded.get().is_none()));
+    }
+
+    #[tokio::test]
+    async fn pruning_removes_only_stale_indices_of_unknown_roots() {
+        let state = test_state("/prune/active", Vec::new());
+        state.config.write().await.add_path("/prune/recent".to_string(), 0);
+        state.config.write().await.add_bookmark("/prune/bookmarked".to_string());
+        *state.index_cancel.write().await = Some(Arc::new(CancellationToken::new()));
+
+        let index_dir = get_index_dir().unwrap();
+        fs::create_dir_all(&index_dir).unwrap();
+        let stale = std::time::SystemTime::now() - std::time::Duration::from_secs(60 * 60 * 24 * 60);
+        let write_stale = |name: String| {
+            let path = index_dir.join(name);
+            fs::File::create(&path).unwrap().set_modified(stale).unwrap();
+            path
+        };
+        let unknown = write_stale(format!("{}.json", index_file_stem("/prune/unknown")));
+        let kept = [
+            write_stale(format!("{}.json", index_file_stem("/prune/active"))),
+            write_stale(format!("{}.json", index_file_stem("/prune/recent"))),
+            write_stale(format!("{}.json", index_file_stem("/prune/bookmarked"))),
+            // The live run started before another root was selected
+            write_stale(format!("{}.partial.json", index_file_stem("/prune/previous"))),
+        ];
+
+        let result = prune_stale_indices(&state).await.unwrap();
+
+        assert!(!unknown.exists());
+        assert_eq!(result.removed, 1);
+        for path in kept {
+            assert!(path.exists(), "{}", path.display());
+            let _ = fs::remove_file(path);
+        }
+    }
+}